@@ -0,0 +1,370 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{CompactMessage, MeasurementModule};
+
+/// Segments a multiScan136 splits one 360° rotation into.
+pub const MULTISCAN_SEGMENTS_PER_FRAME: u32 = 12;
+
+/// A frame number this far behind the last emitted one means the sensor restarted rather
+/// than a segment arriving late.
+pub const RESTART_DISTANCE: u64 = 8;
+
+#[derive(Clone, Debug)]
+pub struct AssemblerConfig {
+    /// Emit a frame as soon as this many segments arrived. `None` waits for a rollover or
+    /// the timeout instead.
+    pub segments_per_frame: Option<u32>,
+    /// How long a frame may stay open after its first segment arrived.
+    pub timeout: Duration,
+    /// Frames per sender that may be collected at the same time. When a new frame would
+    /// exceed this the oldest one is emitted, so 1 emits on every frame number rollover.
+    pub max_frames_in_flight: usize,
+}
+
+impl Default for AssemblerConfig {
+    fn default() -> Self {
+        Self {
+            segments_per_frame: Some(MULTISCAN_SEGMENTS_PER_FRAME),
+            timeout: Duration::from_millis(200),
+            max_frames_in_flight: 2,
+        }
+    }
+}
+
+/// Why an [`AssembledFrame`] was emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameCompletion {
+    AllSegments,
+    Rollover,
+    Timeout,
+    Flushed,
+}
+
+#[derive(Clone, Debug)]
+pub struct AssembledFrame {
+    pub sender_id: u32,
+    pub frame_number: u64,
+    /// Received segments, ordered by `segment_counter`.
+    pub segments: Vec<MeasurementModule>,
+    /// Segment counters that were expected in this frame but never arrived.
+    pub missing_segments: Vec<u64>,
+    pub completion: FrameCompletion,
+}
+
+impl AssembledFrame {
+    pub fn is_complete(&self) -> bool {
+        self.missing_segments.is_empty()
+    }
+}
+
+struct PendingFrame {
+    sender_id: u32,
+    frame_number: u64,
+    segments: Vec<MeasurementModule>,
+    first_seen: Instant,
+}
+
+#[derive(Default)]
+struct SenderState {
+    last_frame: Option<u64>,
+    last_segment: Option<u64>,
+}
+
+/// Groups the segments of a multiScan stream into full frames.
+///
+/// Segments are keyed by `sender_id` and `frame_number`, so several sensors can share one
+/// assembler.
+pub struct FrameAssembler {
+    pub config: AssemblerConfig,
+    pending: Vec<PendingFrame>,
+    senders: HashMap<u32, SenderState>,
+    pub late_segments: u64,
+    pub duplicate_segments: u64,
+}
+
+impl FrameAssembler {
+    pub fn new(config: AssemblerConfig) -> Self {
+        Self {
+            config,
+            pending: Vec::new(),
+            senders: HashMap::new(),
+            late_segments: 0,
+            duplicate_segments: 0,
+        }
+    }
+
+    /// Feeds every module of a distance telegram, IMU telegrams are ignored.
+    pub fn push_message(&mut self, msg: CompactMessage, now: Instant) -> Vec<AssembledFrame> {
+        let mut out = Vec::new();
+        if let CompactMessage::DistanceMessage { header: _, data } = msg {
+            for module in data.into_iter() {
+                out.append(&mut self.push(module, now));
+            }
+        }
+        out
+    }
+
+    /// Adds one segment and returns every frame that finished because of it.
+    pub fn push(&mut self, module: MeasurementModule, now: Instant) -> Vec<AssembledFrame> {
+        let mut out = self.poll_timeouts(now);
+        let (sender_id, frame_number) = (module.sender_id, module.frame_number);
+        if self
+            .senders
+            .get(&sender_id)
+            .and_then(|s| s.last_frame)
+            .is_some_and(|last| frame_number + RESTART_DISTANCE < last)
+        {
+            out.append(&mut self.restart(sender_id));
+        }
+        let pending = self
+            .pending
+            .iter()
+            .position(|p| p.sender_id == sender_id && p.frame_number == frame_number);
+        let index = match pending {
+            Some(i) => i,
+            None if self
+                .senders
+                .get(&sender_id)
+                .and_then(|s| s.last_frame)
+                .is_some_and(|last| frame_number <= last) =>
+            {
+                self.late_segments += 1;
+                return out;
+            }
+            None => {
+                let in_flight = self.pending.iter().filter(|p| p.sender_id == sender_id);
+                if in_flight.count() >= self.config.max_frames_in_flight.max(1) {
+                    let oldest = self.oldest_of(sender_id).unwrap();
+                    out.push(self.finish(oldest, FrameCompletion::Rollover));
+                }
+                self.pending.push(PendingFrame {
+                    sender_id,
+                    frame_number,
+                    segments: Vec::new(),
+                    first_seen: now,
+                });
+                self.pending.len() - 1
+            }
+        };
+
+        let frame = &mut self.pending[index];
+        if frame
+            .segments
+            .iter()
+            .any(|s| s.segment_counter == module.segment_counter)
+        {
+            self.duplicate_segments += 1;
+            return out;
+        }
+        frame.segments.push(module);
+        if self
+            .config
+            .segments_per_frame
+            .is_some_and(|n| frame.segments.len() >= n as usize)
+        {
+            out.push(self.finish(index, FrameCompletion::AllSegments));
+        }
+        out
+    }
+
+    /// Emits every frame whose first segment is older than the configured timeout.
+    pub fn poll_timeouts(&mut self, now: Instant) -> Vec<AssembledFrame> {
+        let mut out = Vec::new();
+        while let Some(i) = self
+            .pending
+            .iter()
+            .position(|p| now.duration_since(p.first_seen) >= self.config.timeout)
+        {
+            out.push(self.finish(i, FrameCompletion::Timeout));
+        }
+        out
+    }
+
+    /// Emits everything that is still being collected, oldest frame first.
+    pub fn flush(&mut self) -> Vec<AssembledFrame> {
+        self.pending.sort_by_key(|p| (p.sender_id, p.frame_number));
+        let mut out = Vec::new();
+        while !self.pending.is_empty() {
+            out.push(self.finish(0, FrameCompletion::Flushed));
+        }
+        out
+    }
+
+    /// Emits what is left of the previous run of `sender_id` and forgets its frame numbers.
+    fn restart(&mut self, sender_id: u32) -> Vec<AssembledFrame> {
+        let mut out = Vec::new();
+        while let Some(oldest) = self.oldest_of(sender_id) {
+            out.push(self.finish(oldest, FrameCompletion::Rollover));
+        }
+        self.senders.remove(&sender_id);
+        out
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn oldest_of(&self, sender_id: u32) -> Option<usize> {
+        self.pending
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.sender_id == sender_id)
+            .min_by_key(|(_, p)| p.frame_number)
+            .map(|(i, _)| i)
+    }
+
+    fn finish(&mut self, index: usize, completion: FrameCompletion) -> AssembledFrame {
+        let mut frame = self.pending.remove(index);
+        frame.segments.sort_by_key(|s| s.segment_counter);
+        let state = self.senders.entry(frame.sender_id).or_default();
+
+        let received: Vec<u64> = frame.segments.iter().map(|s| s.segment_counter).collect();
+        let first = match (state.last_frame, state.last_segment) {
+            (Some(f), Some(s)) if f + 1 == frame.frame_number => s + 1,
+            _ => received.first().copied().unwrap_or(0),
+        };
+        let last = match self.config.segments_per_frame {
+            Some(n) => first + n.max(1) as u64 - 1,
+            None => received.last().copied().unwrap_or(first),
+        };
+        let missing_segments = (first..=last)
+            .filter(|c| received.binary_search(c).is_err())
+            .collect();
+
+        if state.last_frame.is_none_or(|f| f < frame.frame_number) {
+            state.last_frame = Some(frame.frame_number);
+            state.last_segment = Some(last.max(received.last().copied().unwrap_or(last)));
+        }
+        AssembledFrame {
+            sender_id: frame.sender_id,
+            frame_number: frame.frame_number,
+            segments: frame.segments,
+            missing_segments,
+            completion,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::SmallVec;
+
+    use super::*;
+    use crate::{BeamContent, EchoContent};
+
+    fn segment(sender_id: u32, frame_number: u64, segment_counter: u64) -> MeasurementModule {
+        MeasurementModule::new(
+            segment_counter,
+            frame_number,
+            sender_id,
+            1.0,
+            EchoContent::DISTANCE,
+            BeamContent::empty(),
+            SmallVec::new(),
+        )
+    }
+
+    fn push_frame(
+        assembler: &mut FrameAssembler,
+        frame_number: u64,
+        segments: impl IntoIterator<Item = u64>,
+        now: Instant,
+    ) -> Vec<AssembledFrame> {
+        segments
+            .into_iter()
+            .flat_map(|c| assembler.push(segment(1, frame_number, c), now))
+            .collect()
+    }
+
+    #[test]
+    fn a_full_frame_is_emitted_with_its_last_segment() {
+        let mut assembler = FrameAssembler::new(AssemblerConfig::default());
+        let now = Instant::now();
+        let frames = push_frame(&mut assembler, 1, (0..12).rev(), now);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].completion, FrameCompletion::AllSegments);
+        assert!(frames[0].is_complete());
+        let counters: Vec<u64> = frames[0]
+            .segments
+            .iter()
+            .map(|s| s.segment_counter)
+            .collect();
+        assert_eq!(counters, (0..12).collect::<Vec<_>>());
+        assert_eq!(assembler.frames_in_flight(), 0);
+    }
+
+    #[test]
+    fn a_missing_segment_is_reported_after_the_timeout() {
+        let mut assembler = FrameAssembler::new(AssemblerConfig::default());
+        let now = Instant::now();
+        push_frame(&mut assembler, 1, 0..12, now);
+        let frames = push_frame(&mut assembler, 2, (12..24).filter(|c| *c != 17), now);
+        assert!(frames.is_empty());
+        assert!(assembler
+            .poll_timeouts(now + Duration::from_millis(100))
+            .is_empty());
+        let frames = assembler.poll_timeouts(now + Duration::from_millis(200));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].completion, FrameCompletion::Timeout);
+        assert_eq!(frames[0].missing_segments, vec![17]);
+
+        assert!(push_frame(&mut assembler, 2, [17], now).is_empty());
+        assert_eq!(assembler.late_segments, 1);
+    }
+
+    #[test]
+    fn a_new_frame_number_rolls_the_oldest_frame_over() {
+        let mut assembler = FrameAssembler::new(AssemblerConfig {
+            segments_per_frame: None,
+            max_frames_in_flight: 1,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(push_frame(&mut assembler, 1, 0..5, now).is_empty());
+        let frames = push_frame(&mut assembler, 2, [5], now);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].frame_number, 1);
+        assert_eq!(frames[0].completion, FrameCompletion::Rollover);
+        assert!(frames[0].is_complete());
+    }
+
+    #[test]
+    fn the_oldest_frame_is_evicted_when_too_many_are_in_flight() {
+        let mut assembler = FrameAssembler::new(AssemblerConfig::default());
+        let now = Instant::now();
+        assert!(push_frame(&mut assembler, 1, 0..3, now).is_empty());
+        assert!(push_frame(&mut assembler, 2, 12..15, now).is_empty());
+        let frames = push_frame(&mut assembler, 3, [24], now);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].frame_number, 1);
+        assert_eq!(frames[0].completion, FrameCompletion::Rollover);
+        assert_eq!(frames[0].missing_segments, (3..12).collect::<Vec<_>>());
+        assert_eq!(assembler.frames_in_flight(), 2);
+
+        let frames = assembler.flush();
+        let numbers: Vec<u64> = frames.iter().map(|f| f.frame_number).collect();
+        assert_eq!(numbers, vec![2, 3]);
+    }
+
+    #[test]
+    fn a_restarted_sensor_is_accepted_again() {
+        let mut assembler = FrameAssembler::new(AssemblerConfig::default());
+        let now = Instant::now();
+        push_frame(&mut assembler, 1000, 0..12, now);
+        assert!(push_frame(&mut assembler, 1001, 12..14, now).is_empty());
+
+        let frames = push_frame(&mut assembler, 1, 0..12, now);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].frame_number, 1001);
+        assert_eq!(frames[0].completion, FrameCompletion::Rollover);
+        assert_eq!(frames[1].frame_number, 1);
+        assert!(frames[1].is_complete());
+        assert_eq!(assembler.late_segments, 0);
+
+        assert!(push_frame(&mut assembler, 1, [3], now).is_empty());
+        assert_eq!(assembler.late_segments, 1);
+    }
+}
@@ -0,0 +1,344 @@
+//! Structure-of-arrays storage for decoded modules that is reused across frames.
+//!
+//! [`MeasurementModule`](crate::MeasurementModule) allocates a `Vec` per line and a
+//! `SmallVec` per beam. [`ModuleBuffers`] instead keeps one flat array per field, ordered
+//! line by line, and only grows them when a bigger module arrives.
+
+use crate::{
+    frame::{DistanceFrameRef, ModuleRef},
+    points::PointConverter,
+    BeamContent, BeamProperties, Echo, EchoContent, ScanPoint, MAX_ECHOES_PER_BEAM,
+};
+
+/// Metadata of one line.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LineMeta {
+    pub phi: f32,
+    pub theta_start: f32,
+    pub theta_end: f32,
+    pub time_stamp_start: u64,
+    pub time_stamp_end: u64,
+}
+
+/// One decoded module. Echo arrays are indexed `[line][beam][echo]` and beam arrays
+/// `[line][beam]`, so every line is a contiguous slice next to its metadata in `lines`.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleBuffers {
+    pub segment_counter: u64,
+    pub frame_number: u64,
+    pub sender_id: u32,
+    pub distance_scale_factor: f32,
+    pub data_content_echoes: EchoContent,
+    pub data_content_beams: BeamContent,
+    pub beams: usize,
+    pub echoes: usize,
+    pub lines: Vec<LineMeta>,
+    /// Empty when the module carries no distances.
+    pub distances: Vec<u16>,
+    /// Empty when the module carries no RSSI.
+    pub rssi: Vec<u16>,
+    /// Empty when the module carries no beam properties.
+    pub properties: Vec<BeamProperties>,
+    /// Empty when the module carries no per-beam azimuth.
+    pub azimuths: Vec<u16>,
+}
+
+/// Borrowed view of one line of a [`ModuleBuffers`].
+#[derive(Clone, Copy, Debug)]
+pub struct LineView<'a> {
+    pub index: usize,
+    pub meta: &'a LineMeta,
+    pub beams: usize,
+    pub echoes: usize,
+    pub distances: &'a [u16],
+    pub rssi: &'a [u16],
+    pub properties: &'a [BeamProperties],
+    pub azimuths: &'a [u16],
+}
+
+impl ModuleBuffers {
+    /// Replaces the contents with `module`, keeping the allocations.
+    pub fn decode(&mut self, module: &ModuleRef<'_>) {
+        let lines = module.number_lines_in_module() as usize;
+        self.segment_counter = module.segment_counter();
+        self.frame_number = module.frame_number();
+        self.sender_id = module.sender_id();
+        self.distance_scale_factor = module.distance_scale_factor();
+        self.data_content_echoes = module.data_content_echoes();
+        self.data_content_beams = module.data_content_beams();
+        self.beams = module.number_of_beams_per_scan() as usize;
+        self.echoes = module.number_of_echoes_per_beam() as usize;
+
+        self.lines.clear();
+        self.lines.extend((0..lines).map(|line| LineMeta {
+            phi: module.phi(line),
+            theta_start: module.theta_start(line),
+            theta_end: module.theta_end(line),
+            time_stamp_start: module.time_stamp_start(line),
+            time_stamp_end: module.time_stamp_end(line),
+        }));
+
+        let echo_values = lines * self.beams * self.echoes;
+        let beam_values = lines * self.beams;
+        let reset = |v: &mut Vec<u16>, present: bool, len: usize| {
+            v.clear();
+            if present {
+                v.resize(len, 0);
+            }
+        };
+        reset(
+            &mut self.distances,
+            self.data_content_echoes.contains(EchoContent::DISTANCE),
+            echo_values,
+        );
+        reset(
+            &mut self.rssi,
+            self.data_content_echoes.contains(EchoContent::RSSI),
+            echo_values,
+        );
+        reset(
+            &mut self.azimuths,
+            self.data_content_beams.contains(BeamContent::AZIMUTH),
+            beam_values,
+        );
+        self.properties.clear();
+        if self.data_content_beams.contains(BeamContent::PROPERTIES) {
+            self.properties.resize(beam_values, BeamProperties::empty());
+        }
+
+        // The wire order is beam-major, the buffers are line-major.
+        for beam in 0..self.beams {
+            for line in 0..lines {
+                let record = module.beam(beam, line);
+                let slot = line * self.beams + beam;
+                for echo in 0..self.echoes {
+                    let Echo { distance, rssi } = record.echo(echo);
+                    let i = slot * self.echoes + echo;
+                    if let Some(d) = distance {
+                        self.distances[i] = d;
+                    }
+                    if let Some(r) = rssi {
+                        self.rssi[i] = r;
+                    }
+                }
+                if let Some(p) = record.beam_properties() {
+                    self.properties[slot] = p;
+                }
+                if let Some(a) = record.azimuth_angle() {
+                    self.azimuths[slot] = a;
+                }
+            }
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn line(&self, line: usize) -> LineView<'_> {
+        let echoes = line * self.beams * self.echoes..(line + 1) * self.beams * self.echoes;
+        let beams = line * self.beams..(line + 1) * self.beams;
+        LineView {
+            index: line,
+            meta: &self.lines[line],
+            beams: self.beams,
+            echoes: self.echoes,
+            distances: self.distances.get(echoes.clone()).unwrap_or(&[]),
+            rssi: self.rssi.get(echoes).unwrap_or(&[]),
+            properties: self.properties.get(beams.clone()).unwrap_or(&[]),
+            azimuths: self.azimuths.get(beams).unwrap_or(&[]),
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = LineView<'_>> {
+        (0..self.lines.len()).map(|l| self.line(l))
+    }
+}
+
+impl LineView<'_> {
+    pub fn echo(&self, beam: usize, echo: usize) -> Echo {
+        let i = beam * self.echoes + echo;
+        Echo {
+            distance: self.distances.get(i).copied(),
+            rssi: self.rssi.get(i).copied(),
+        }
+    }
+
+    /// Azimuth of `beam` in radians, from the beam itself when the module carries one.
+    pub fn beam_azimuth(&self, beam: usize) -> f32 {
+        match self.azimuths.get(beam) {
+            Some(a) => crate::msgpack::azimuth_to_radians(*a),
+            None => {
+                let steps = self.beams.saturating_sub(1).max(1) as f32;
+                let meta = self.meta;
+                meta.theta_start + (meta.theta_end - meta.theta_start) * beam as f32 / steps
+            }
+        }
+    }
+
+    pub fn beam_time_stamp(&self, beam: usize) -> u64 {
+        let steps = self.beams.saturating_sub(1).max(1) as u64;
+        let span = self
+            .meta
+            .time_stamp_end
+            .saturating_sub(self.meta.time_stamp_start);
+        self.meta.time_stamp_start + span * beam as u64 / steps
+    }
+}
+
+/// Every module of a telegram, with the buffers of earlier telegrams reused.
+#[derive(Clone, Debug, Default)]
+pub struct ScanBuffers {
+    modules: Vec<ModuleBuffers>,
+    len: usize,
+}
+
+impl ScanBuffers {
+    pub fn decode(&mut self, frame: &DistanceFrameRef<'_>) {
+        self.len = 0;
+        for module in frame.modules() {
+            if self.len == self.modules.len() {
+                self.modules.push(ModuleBuffers::default());
+            }
+            self.modules[self.len].decode(&module);
+            self.len += 1;
+        }
+    }
+
+    pub fn modules(&self) -> &[ModuleBuffers] {
+        &self.modules[..self.len]
+    }
+}
+
+impl PointConverter {
+    /// Appends the points of every line of `module` to `out`, like
+    /// [`PointConverter::convert_module`].
+    pub fn convert_buffers(&mut self, module: &ModuleBuffers, out: &mut Vec<ScanPoint>) {
+        let mut echoes = [Echo {
+            distance: None,
+            rssi: None,
+        }; MAX_ECHOES_PER_BEAM as usize];
+        for line in module.lines() {
+            let (sin_phi, cos_phi) = self.device.profile().elevation(line.meta.phi).sin_cos();
+            for beam in 0..line.beams {
+                let count = line.echoes.min(echoes.len());
+                for (e, slot) in echoes[..count].iter_mut().enumerate() {
+                    *slot = line.echo(beam, e);
+                }
+                let (selected, skipped) = self.policy.select(&echoes[..count]);
+                self.skipped += skipped as u64;
+                self.points += selected.len() as u64;
+                if selected.is_empty() {
+                    continue;
+                }
+                let (sin_theta, cos_theta) = line.beam_azimuth(beam).sin_cos();
+                let time_stamp = line.beam_time_stamp(beam);
+                out.extend(selected.into_iter().map(|echo| {
+                    let e = echoes[echo];
+                    let r = e.distance.unwrap_or(0) as f32 * module.distance_scale_factor;
+                    ScanPoint {
+                        x: r * cos_phi * cos_theta,
+                        y: r * cos_phi * sin_theta,
+                        z: r * sin_phi,
+                        intensity: e.rssi,
+                        layer: line.index,
+                        beam,
+                        echo,
+                        time_stamp,
+                        properties: line.properties.get(beam).copied(),
+                    }
+                }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{
+        points::EchoPolicy, CompactFrameRef, CompactHeader, CompactMessage, Measurement,
+        MeasurementLayerOutput, MeasurementModule,
+    };
+
+    /// A module whose every value encodes the line it belongs to.
+    fn module(lines: u32, beams: u32) -> MeasurementModule {
+        let layer = |line: u32| MeasurementLayerOutput {
+            phi: line as f32 * 0.1,
+            theta_start: -(line as f32),
+            theta_end: line as f32,
+            time_stamp_start: 1000 + line as u64,
+            time_stamp_end: 2000 + line as u64,
+            data: (0..beams)
+                .map(|beam| Measurement::Filled {
+                    echoes: smallvec![Echo {
+                        distance: Some((line * 1000 + beam) as u16),
+                        rssi: Some(line as u16),
+                    }],
+                    beam_properties: Some(BeamProperties::from_bits_retain(line as u8)),
+                    azimuth_angle: Some((line * 100 + beam) as u16),
+                })
+                .collect(),
+        };
+        MeasurementModule::new(
+            7,
+            3,
+            1,
+            1.0,
+            EchoContent::all(),
+            BeamContent::all(),
+            (0..lines).map(layer).collect(),
+        )
+    }
+
+    fn decode(buffers: &mut ScanBuffers, modules: Vec<MeasurementModule>) {
+        let telegram = CompactMessage::distance(CompactHeader::new(1, 2, 4), modules).to_bytes();
+        match CompactFrameRef::parse(&telegram).unwrap() {
+            CompactFrameRef::Distance(frame) => buffers.decode(&frame),
+            CompactFrameRef::Imu(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn lines_keep_their_metadata_and_data_in_order() {
+        let mut buffers = ScanBuffers::default();
+        decode(&mut buffers, vec![module(16, 30)]);
+        let module = &buffers.modules()[0];
+        assert_eq!(module.line_count(), 16);
+        for line in module.lines() {
+            let l = line.index as u32;
+            assert_eq!(line.meta.phi, l as f32 * 0.1);
+            assert_eq!(line.meta.theta_start, -(l as f32));
+            assert_eq!(line.meta.time_stamp_start, 1000 + l as u64);
+            assert_eq!(line.beams, 30);
+            for beam in 0..30 {
+                let b = beam as u32;
+                assert_eq!(line.echo(beam, 0).distance, Some((l * 1000 + b) as u16));
+                assert_eq!(line.echo(beam, 0).rssi, Some(l as u16));
+                assert_eq!(line.properties[beam].bits(), l as u8);
+                assert_eq!(line.azimuths[beam], (l * 100 + b) as u16);
+            }
+        }
+    }
+
+    #[test]
+    fn buffers_are_reused_and_match_the_owned_decoder() {
+        let mut buffers = ScanBuffers::default();
+        decode(&mut buffers, vec![module(16, 30), module(16, 30)]);
+        let capacity = buffers.modules()[0].distances.capacity();
+        decode(&mut buffers, vec![module(4, 12)]);
+        assert_eq!(buffers.modules().len(), 1);
+        assert_eq!(buffers.modules()[0].distances.capacity(), capacity);
+        assert_eq!(buffers.modules()[0].line(3).meta.phi, 3.0 * 0.1);
+
+        let owned = module(4, 12);
+        let mut from_buffers = Vec::new();
+        let mut from_owned = Vec::new();
+        let mut converter = PointConverter::new(EchoPolicy::All);
+        converter.convert_buffers(&buffers.modules()[0], &mut from_buffers);
+        converter.convert_module(&owned, &mut from_owned);
+        assert_eq!(from_buffers, from_owned);
+    }
+}
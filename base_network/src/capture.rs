@@ -0,0 +1,808 @@
+//! Reading of Wireshark captures in the pcap and pcapng formats.
+//!
+//! Only what is needed to get sensor traffic back out is decoded: Ethernet, Linux cooked
+//! and raw IP link layers, IPv4 with fragment reassembly, IPv6 without extension headers,
+//! UDP and TCP. TCP payloads are reassembled into the byte stream each side sent.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use crate::frame::read_u32_at;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+/// Out of order TCP data buffered per flow before the missing bytes are given up on.
+const MAX_TCP_PENDING: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// A UDP datagram, or the next in-order bytes of a TCP stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// Capture time since the unix epoch.
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transport: Transport,
+    pub payload: Vec<u8>,
+}
+
+/// Which packets of a capture to keep.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureFilter {
+    /// Keep packets whose source or destination port is listed, or every packet if empty.
+    pub ports: Vec<u16>,
+}
+
+impl CaptureFilter {
+    pub fn ports(ports: impl IntoIterator<Item = u16>) -> Self {
+        Self {
+            ports: ports.into_iter().collect(),
+        }
+    }
+
+    fn matches(&self, source: &SocketAddr, destination: &SocketAddr) -> bool {
+        self.ports.is_empty()
+            || self.ports.contains(&source.port())
+            || self.ports.contains(&destination.port())
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+/// Reads a u16/u32 in the byte order of the capture file.
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, data: &[u8], offset: usize) -> io::Result<u16> {
+        let bytes: [u8; 2] = data
+            .get(offset..offset + 2)
+            .ok_or_else(|| invalid("capture truncated"))?
+            .try_into()
+            .unwrap();
+        Ok(match self.big {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(self, data: &[u8], offset: usize) -> io::Result<u32> {
+        let bytes: [u8; 4] = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| invalid("capture truncated"))?
+            .try_into()
+            .unwrap();
+        Ok(match self.big {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+}
+
+/// Network byte order, the caller checks the length.
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// The packets of a capture that passed the filter, in capture order.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    pub packets: Vec<CapturedPacket>,
+    /// Frames that were not UDP or TCP, or could not be decoded.
+    pub skipped: u64,
+}
+
+impl Capture {
+    pub fn open(path: impl AsRef<Path>, filter: &CaptureFilter) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?, filter)
+    }
+
+    /// Parses a complete pcap or pcapng file.
+    pub fn parse(file: &[u8], filter: &CaptureFilter) -> io::Result<Self> {
+        let mut decoder = PacketDecoder::new(filter.clone());
+        let magic = read_u32_at(file.get(..4).ok_or_else(|| invalid("empty capture"))?, 0);
+        match magic {
+            PCAPNG_SECTION_HEADER => parse_pcapng(file, &mut decoder)?,
+            _ => parse_pcap(file, &mut decoder)?,
+        }
+        Ok(Self {
+            packets: decoder.packets,
+            skipped: decoder.skipped,
+        })
+    }
+
+    /// UDP datagrams sent from or to `port`.
+    pub fn udp(&self, port: u16) -> impl Iterator<Item = &CapturedPacket> {
+        self.packets.iter().filter(move |p| {
+            p.transport == Transport::Udp
+                && (p.source.port() == port || p.destination.port() == port)
+        })
+    }
+
+    /// The bytes a TCP server listening on `port` sent, i.e. what a client reads.
+    pub fn tcp_from(&self, port: u16) -> impl Iterator<Item = &CapturedPacket> {
+        self.packets
+            .iter()
+            .filter(move |p| p.transport == Transport::Tcp && p.source.port() == port)
+    }
+}
+
+fn parse_pcap(file: &[u8], decoder: &mut PacketDecoder) -> io::Result<()> {
+    if file.len() < 24 {
+        return Err(invalid("pcap header truncated"));
+    }
+    let (endian, nanos) = match (read_u32_at(file, 0), read_u32_at(file, 0).swap_bytes()) {
+        (PCAP_MAGIC_MICROS, _) => (Endian { big: false }, false),
+        (PCAP_MAGIC_NANOS, _) => (Endian { big: false }, true),
+        (_, PCAP_MAGIC_MICROS) => (Endian { big: true }, false),
+        (_, PCAP_MAGIC_NANOS) => (Endian { big: true }, true),
+        _ => return Err(invalid("not a pcap or pcapng file")),
+    };
+    let link_type = endian.u32(file, 20)? & 0x0fff_ffff;
+    let mut offset = 24;
+    while offset + 16 <= file.len() {
+        let seconds = endian.u32(file, offset)? as u64;
+        let fraction = endian.u32(file, offset + 4)? as u64;
+        let captured = endian.u32(file, offset + 8)? as usize;
+        let data = file
+            .get(offset + 16..offset + 16 + captured)
+            .ok_or_else(|| invalid("pcap record truncated"))?;
+        let timestamp = Duration::from_secs(seconds)
+            + match nanos {
+                true => Duration::from_nanos(fraction),
+                false => Duration::from_micros(fraction),
+            };
+        decoder.frame(link_type, timestamp, data);
+        offset += 16 + captured;
+    }
+    Ok(())
+}
+
+struct Interface {
+    link_type: u32,
+    /// Timestamp units per second.
+    resolution: u64,
+}
+
+fn parse_pcapng(file: &[u8], decoder: &mut PacketDecoder) -> io::Result<()> {
+    let mut endian = Endian { big: false };
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut offset = 0;
+    while offset + 12 <= file.len() {
+        let block_type = read_u32_at(file, offset);
+        if block_type == PCAPNG_SECTION_HEADER {
+            endian.big = match read_u32_at(file, offset + 8) {
+                PCAPNG_BYTE_ORDER_MAGIC => false,
+                m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+                _ => return Err(invalid("bad pcapng byte order magic")),
+            };
+            interfaces.clear();
+        }
+        let length = endian.u32(file, offset + 4)? as usize;
+        if length < 12 {
+            return Err(invalid("pcapng block too short"));
+        }
+        let block = file
+            .get(offset..offset + length)
+            .ok_or_else(|| invalid("pcapng block truncated"))?;
+        match endian.u32(block, 0)? {
+            PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(Interface {
+                link_type: endian.u16(block, 8)? as u32,
+                resolution: interface_resolution(
+                    endian,
+                    block
+                        .get(16..length - 4)
+                        .ok_or_else(|| invalid("pcapng interface description too short"))?,
+                )?,
+            }),
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = interfaces
+                    .get(endian.u32(block, 8)? as usize)
+                    .ok_or_else(|| invalid("packet for an unknown interface"))?;
+                let ticks = (endian.u32(block, 12)? as u64) << 32 | endian.u32(block, 16)? as u64;
+                let captured = endian.u32(block, 20)? as usize;
+                let data = block
+                    .get(28..28 + captured)
+                    .ok_or_else(|| invalid("pcapng packet truncated"))?;
+                let fraction = (ticks % interface.resolution) as u128 * 1_000_000_000
+                    / interface.resolution as u128;
+                let timestamp = Duration::from_secs(ticks / interface.resolution)
+                    + Duration::from_nanos(fraction as u64);
+                decoder.frame(interface.link_type, timestamp, data);
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid("packet before any interface"))?;
+                let room = length
+                    .checked_sub(16)
+                    .ok_or_else(|| invalid("pcapng simple packet too short"))?;
+                let captured = (endian.u32(block, 8)? as usize).min(room);
+                decoder.frame(
+                    interface.link_type,
+                    Duration::ZERO,
+                    &block[12..12 + captured],
+                );
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(())
+}
+
+/// Reads the `if_tsresol` option of an interface description, microseconds by default.
+fn interface_resolution(endian: Endian, mut options: &[u8]) -> io::Result<u64> {
+    while options.len() >= 4 {
+        let code = endian.u16(options, 0)?;
+        let length = endian.u16(options, 2)? as usize;
+        if code == 0 {
+            break;
+        }
+        if code == 9 && length >= 1 {
+            let value = *options
+                .get(4)
+                .ok_or_else(|| invalid("pcapng interface option truncated"))?;
+            let exponent = (value & 0x7f) as u32;
+            return Ok(match value & 0x80 {
+                0 => 10_u64.saturating_pow(exponent),
+                _ => 2_u64.saturating_pow(exponent),
+            });
+        }
+        options = options.get(4 + length.next_multiple_of(4)..).unwrap_or(&[]);
+    }
+    Ok(1_000_000)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    source: IpAddr,
+    destination: IpAddr,
+    id: u16,
+    protocol: u8,
+}
+
+#[derive(Default)]
+struct Fragments {
+    /// (offset, bytes)
+    parts: Vec<(usize, Vec<u8>)>,
+    total: Option<usize>,
+}
+
+impl Fragments {
+    /// Whether every part fits inside the length the last fragment announced.
+    fn consistent(&self) -> bool {
+        let Some(total) = self.total else {
+            return true;
+        };
+        self.parts
+            .iter()
+            .all(|(offset, bytes)| *offset < total && offset + bytes.len() <= total)
+    }
+
+    /// The reassembled payload once every byte up to the last fragment arrived.
+    fn complete(&mut self) -> Option<Vec<u8>> {
+        let total = self.total?;
+        if !self.consistent() {
+            return None;
+        }
+        self.parts.sort_by_key(|p| p.0);
+        let mut covered = 0;
+        for (offset, bytes) in &self.parts {
+            if *offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + bytes.len());
+        }
+        if covered < total {
+            return None;
+        }
+        let mut payload = vec![0; total];
+        for (offset, bytes) in &self.parts {
+            payload[*offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        Some(payload)
+    }
+}
+
+#[derive(Default)]
+struct TcpFlow {
+    next: Option<u32>,
+    /// (sequence number, bytes) received ahead of `next`.
+    pending: Vec<(u32, Vec<u8>)>,
+}
+
+impl TcpFlow {
+    /// Accepts one segment and returns the bytes that are now in order.
+    fn segment(&mut self, seq: u32, syn: bool, data: &[u8]) -> Vec<u8> {
+        if syn {
+            self.next = Some(seq.wrapping_add(1));
+            self.pending.clear();
+            return Vec::new();
+        }
+        if data.is_empty() {
+            return Vec::new();
+        }
+        let next = *self.next.get_or_insert(seq);
+        self.pending.push((seq, data.to_vec()));
+        let mut out = Vec::new();
+        let mut next = next;
+        loop {
+            let ready = self
+                .pending
+                .iter()
+                .position(|(s, _)| (s.wrapping_sub(next) as i32) <= 0);
+            let Some(i) = ready else {
+                if self.pending.iter().map(|p| p.1.len()).sum::<usize>() <= MAX_TCP_PENDING {
+                    break;
+                }
+                // The capture lost a segment, skip over the gap.
+                next = self
+                    .pending
+                    .iter()
+                    .map(|p| p.0)
+                    .min_by_key(|s| s.wrapping_sub(next))
+                    .unwrap();
+                continue;
+            };
+            let (s, bytes) = self.pending.swap_remove(i);
+            let overlap = next.wrapping_sub(s) as usize;
+            if overlap < bytes.len() {
+                out.extend_from_slice(&bytes[overlap..]);
+                next = s.wrapping_add(bytes.len() as u32);
+            }
+        }
+        self.next = Some(next);
+        out
+    }
+}
+
+struct PacketDecoder {
+    filter: CaptureFilter,
+    packets: Vec<CapturedPacket>,
+    skipped: u64,
+    fragments: HashMap<FragmentKey, Fragments>,
+    flows: HashMap<(SocketAddr, SocketAddr), TcpFlow>,
+}
+
+impl PacketDecoder {
+    fn new(filter: CaptureFilter) -> Self {
+        Self {
+            filter,
+            packets: Vec::new(),
+            skipped: 0,
+            fragments: HashMap::new(),
+            flows: HashMap::new(),
+        }
+    }
+
+    fn frame(&mut self, link_type: u32, timestamp: Duration, data: &[u8]) {
+        if self.link_layer(link_type, timestamp, data).is_none() {
+            self.skipped += 1;
+        }
+    }
+
+    fn link_layer(&mut self, link_type: u32, timestamp: Duration, data: &[u8]) -> Option<()> {
+        let (ether_type, ip) = match link_type {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ether_type = be16(data.get(..offset + 2)?, offset);
+                while ether_type == 0x8100 || ether_type == 0x88a8 {
+                    offset += 4;
+                    ether_type = be16(data.get(..offset + 2)?, offset);
+                }
+                (ether_type, data.get(offset + 2..)?)
+            }
+            LINKTYPE_LINUX_SLL => (be16(data.get(..16)?, 14), data.get(16..)?),
+            LINKTYPE_LINUX_SLL2 => (be16(data.get(..20)?, 0), data.get(20..)?),
+            LINKTYPE_NULL => (0, data.get(4..)?),
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => (0, data),
+            _ => return None,
+        };
+        match (ether_type, ip.first()? >> 4) {
+            (0x0800, _) | (0, 4) => self.ipv4(timestamp, ip),
+            (0x86dd, _) | (0, 6) => self.ipv6(timestamp, ip),
+            _ => None,
+        }
+    }
+
+    fn ipv4(&mut self, timestamp: Duration, ip: &[u8]) -> Option<()> {
+        let header = (ip.first()? & 0x0f) as usize * 4;
+        let total = (be16(ip.get(..4)?, 2) as usize).min(ip.len());
+        let id = be16(ip.get(..6)?, 4);
+        let flags = be16(ip.get(..8)?, 6);
+        let protocol = *ip.get(9)?;
+        let source = IpAddr::V4(Ipv4Addr::from(be32(ip.get(..16)?, 12)));
+        let destination = IpAddr::V4(Ipv4Addr::from(be32(ip.get(..20)?, 16)));
+        let payload = ip.get(header..total)?;
+        let more_fragments = flags & 0x2000 != 0;
+        let fragment_offset = (flags & 0x1fff) as usize * 8;
+        if !more_fragments && fragment_offset == 0 {
+            return self.transport(timestamp, protocol, source, destination, payload);
+        }
+        let key = FragmentKey {
+            source,
+            destination,
+            id,
+            protocol,
+        };
+        let fragments = self.fragments.entry(key).or_default();
+        fragments.parts.push((fragment_offset, payload.to_vec()));
+        if !more_fragments {
+            fragments.total = Some(fragment_offset + payload.len());
+        }
+        if !fragments.consistent() {
+            // A part lies past the end of the datagram, nothing sensible can be rebuilt.
+            self.fragments.remove(&key);
+            return None;
+        }
+        if let Some(whole) = fragments.complete() {
+            self.fragments.remove(&key);
+            self.transport(timestamp, protocol, source, destination, &whole)?;
+        }
+        Some(())
+    }
+
+    fn ipv6(&mut self, timestamp: Duration, ip: &[u8]) -> Option<()> {
+        let length = be16(ip.get(..6)?, 4) as usize;
+        let protocol = *ip.get(6)?;
+        let address = |offset: usize| -> Option<IpAddr> {
+            let bytes: [u8; 16] = ip.get(offset..offset + 16)?.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+        };
+        let (source, destination) = (address(8)?, address(24)?);
+        let payload = ip.get(40..(40 + length).min(ip.len()))?;
+        self.transport(timestamp, protocol, source, destination, payload)
+    }
+
+    fn transport(
+        &mut self,
+        timestamp: Duration,
+        protocol: u8,
+        source: IpAddr,
+        destination: IpAddr,
+        segment: &[u8],
+    ) -> Option<()> {
+        let source = SocketAddr::new(source, be16(segment.get(..2)?, 0));
+        let destination = SocketAddr::new(destination, be16(segment.get(..4)?, 2));
+        if !self.filter.matches(&source, &destination) {
+            return Some(());
+        }
+        let (transport, payload) = match protocol {
+            IP_PROTOCOL_UDP => {
+                let length = (be16(segment.get(..8)?, 4) as usize).clamp(8, segment.len());
+                (Transport::Udp, segment[8..length].to_vec())
+            }
+            IP_PROTOCOL_TCP => {
+                let seq = be32(segment.get(..8)?, 4);
+                let header = (segment.get(12)? >> 4) as usize * 4;
+                let flags = *segment.get(13)?;
+                let flow = self.flows.entry((source, destination)).or_default();
+                let data = flow.segment(seq, flags & 0x02 != 0, segment.get(header..)?);
+                if data.is_empty() {
+                    return Some(());
+                }
+                (Transport::Tcp, data)
+            }
+            _ => return None,
+        };
+        self.packets.push(CapturedPacket {
+            timestamp,
+            source,
+            destination,
+            transport,
+            payload,
+        });
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENSOR: [u8; 4] = [192, 168, 0, 1];
+    const HOST: [u8; 4] = [192, 168, 0, 100];
+
+    /// An Ethernet frame carrying an IPv4 packet with the given transport segment.
+    fn ethernet(protocol: u8, segment: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&0x0800_u16.to_be_bytes());
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&(20 + segment.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 1, 0, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&SENSOR);
+        frame.extend_from_slice(&HOST);
+        frame.extend_from_slice(segment);
+        frame
+    }
+
+    /// One IPv4 fragment of the datagram with identification `id`.
+    fn fragment(id: u16, offset: usize, more: bool, bytes: &[u8]) -> Vec<u8> {
+        let mut frame = ethernet(IP_PROTOCOL_UDP, bytes);
+        let flags = (offset / 8) as u16 | if more { 0x2000 } else { 0 };
+        frame[18..20].copy_from_slice(&id.to_be_bytes());
+        frame[20..22].copy_from_slice(&flags.to_be_bytes());
+        frame
+    }
+
+    /// The UDP segment `udp` wraps in a frame.
+    fn udp_segment(payload: &[u8]) -> Vec<u8> {
+        udp(payload)[34..].to_vec()
+    }
+
+    fn udp(payload: &[u8]) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&2115_u16.to_be_bytes());
+        segment.extend_from_slice(&2115_u16.to_be_bytes());
+        segment.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(payload);
+        ethernet(IP_PROTOCOL_UDP, &segment)
+    }
+
+    fn tcp(seq: u32, syn: bool, payload: &[u8]) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&2112_u16.to_be_bytes());
+        segment.extend_from_slice(&50000_u16.to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0, 0x50, if syn { 0x02 } else { 0x18 }]);
+        segment.extend_from_slice(&[0xff, 0xff, 0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        ethernet(IP_PROTOCOL_TCP, &segment)
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = [
+            PCAP_MAGIC_MICROS.to_le_bytes().as_slice(),
+            &[2, 0, 4, 0],
+            &[0; 8],
+            &65535_u32.to_le_bytes(),
+            &LINKTYPE_ETHERNET.to_le_bytes(),
+        ]
+        .concat();
+        for (i, frame) in frames.iter().enumerate() {
+            let length = frame.len() as u32;
+            for v in [10, i as u32 * 250, length, length] {
+                file.extend_from_slice(&v.to_le_bytes());
+            }
+            file.extend_from_slice(frame);
+        }
+        file
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len().next_multiple_of(4)) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.resize(length as usize - 4, 0);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    }
+
+    fn section_header() -> Vec<u8> {
+        let mut body = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        body.extend_from_slice(&[1, 0, 0, 0]);
+        body.extend_from_slice(&u64::MAX.to_le_bytes());
+        block(PCAPNG_SECTION_HEADER, &body)
+    }
+
+    /// An Ethernet interface with `if_tsresol` of nanoseconds.
+    fn interface(options: &[u8]) -> Vec<u8> {
+        let mut body = (LINKTYPE_ETHERNET as u16).to_le_bytes().to_vec();
+        body.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(options);
+        block(PCAPNG_INTERFACE_DESCRIPTION, &body)
+    }
+
+    const NANOSECONDS: [u8; 12] = [9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0];
+
+    fn enhanced_packet(ticks: u64, frame: &[u8]) -> Vec<u8> {
+        let mut body = 0_u32.to_le_bytes().to_vec();
+        body.extend_from_slice(&((ticks >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ticks as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        body.extend_from_slice(frame);
+        block(PCAPNG_ENHANCED_PACKET, &body)
+    }
+
+    fn simple_packet(frame: &[u8]) -> Vec<u8> {
+        let mut body = (frame.len() as u32).to_le_bytes().to_vec();
+        body.extend_from_slice(frame);
+        block(PCAPNG_SIMPLE_PACKET, &body)
+    }
+
+    fn parse(file: &[u8]) -> io::Result<Capture> {
+        Capture::parse(file, &CaptureFilter::default())
+    }
+
+    fn is_invalid(result: io::Result<Capture>) -> bool {
+        result.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn pcap_udp_datagrams_are_read() {
+        let capture = parse(&pcap(&[udp(b"first"), udp(b"second")])).unwrap();
+        let payloads: Vec<&[u8]> = capture.udp(2115).map(|p| p.payload.as_slice()).collect();
+        assert_eq!(payloads, [b"first".as_slice(), b"second"]);
+        let first = &capture.packets[0];
+        assert_eq!(first.source, SocketAddr::from((SENSOR, 2115)));
+        assert_eq!(first.destination, SocketAddr::from((HOST, 2115)));
+        assert_eq!(
+            capture.packets[1].timestamp,
+            Duration::from_micros(10_000_250)
+        );
+        assert_eq!(capture.skipped, 0);
+    }
+
+    #[test]
+    fn pcap_tcp_segments_are_put_back_in_order() {
+        let frames = [
+            tcp(99, true, b""),
+            tcp(100, false, b"sMN "),
+            tcp(108, false, b"scan"),
+            tcp(104, false, b"Run "),
+        ];
+        let capture = parse(&pcap(&frames)).unwrap();
+        let stream: Vec<u8> = capture
+            .tcp_from(2112)
+            .flat_map(|p| p.payload.clone())
+            .collect();
+        assert_eq!(stream, b"sMN Run scan");
+    }
+
+    #[test]
+    fn pcap_filters_by_port() {
+        let capture = Capture::parse(&pcap(&[udp(b"x")]), &CaptureFilter::ports([2112])).unwrap();
+        assert!(capture.packets.is_empty());
+    }
+
+    #[test]
+    fn truncated_pcap_files_are_errors() {
+        let file = pcap(&[udp(b"payload")]);
+        assert!(is_invalid(parse(&file[..20])));
+        assert!(is_invalid(parse(&file[..file.len() - 1])));
+        assert!(is_invalid(parse(&[])));
+    }
+
+    #[test]
+    fn malformed_frames_are_skipped() {
+        let short_udp = ethernet(IP_PROTOCOL_UDP, &[0x08, 0x43, 0x08, 0x43, 0, 4]);
+        let capture = parse(&pcap(&[short_udp, vec![0; 10], udp(b"ok")])).unwrap();
+        assert_eq!(capture.skipped, 2);
+        assert_eq!(capture.packets.len(), 1);
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order_and_overlapping() {
+        let segment = udp_segment(b"fragmented datagram payload!");
+        assert_eq!(segment.len(), 36);
+        let frames = [
+            fragment(7, 24, false, &segment[24..]),
+            fragment(7, 8, true, &segment[8..24]),
+            fragment(7, 0, true, &segment[..16]),
+        ];
+        let capture = parse(&pcap(&frames)).unwrap();
+        let payloads: Vec<&[u8]> = capture.udp(2115).map(|p| p.payload.as_slice()).collect();
+        assert_eq!(payloads, [b"fragmented datagram payload!".as_slice()]);
+        assert_eq!(capture.skipped, 0);
+    }
+
+    #[test]
+    fn fragments_past_the_end_of_the_datagram_are_dropped() {
+        let segment = udp_segment(&[0x55; 92]);
+        let frames = [
+            fragment(1, 0, true, &segment),
+            fragment(1, 24, true, &segment[24..40]),
+            fragment(1, 8, false, &segment[8..16]),
+            // The last fragment arriving first, then a part running past it.
+            fragment(2, 8, false, &segment[8..16]),
+            fragment(2, 8, true, &segment[8..32]),
+            fragment(3, 8, false, &[0; 8]),
+            fragment(3, 16, true, &[0; 8]),
+            udp(b"ok"),
+        ];
+        let capture = parse(&pcap(&frames)).unwrap();
+        let payloads: Vec<&[u8]> = capture.udp(2115).map(|p| p.payload.as_slice()).collect();
+        assert_eq!(payloads, [b"ok".as_slice()]);
+        assert_eq!(capture.skipped, 3);
+    }
+
+    #[test]
+    fn pcapng_packets_are_read() {
+        let file = [
+            section_header(),
+            interface(&NANOSECONDS),
+            enhanced_packet(1_500_000_123, &udp(b"enhanced")),
+            simple_packet(&udp(b"simple")),
+        ]
+        .concat();
+        let capture = parse(&file).unwrap();
+        let payloads: Vec<&[u8]> = capture.udp(2115).map(|p| p.payload.as_slice()).collect();
+        assert_eq!(payloads, [b"enhanced".as_slice(), b"simple"]);
+        assert_eq!(capture.packets[0].timestamp, Duration::new(1, 500_000_123));
+    }
+
+    #[test]
+    fn pcapng_timestamps_default_to_microseconds() {
+        let file = [
+            section_header(),
+            interface(&[]),
+            enhanced_packet(2_000_001, &udp(b"x")),
+        ]
+        .concat();
+        let capture = parse(&file).unwrap();
+        assert_eq!(capture.packets[0].timestamp, Duration::new(2, 1_000));
+    }
+
+    #[test]
+    fn truncated_pcapng_blocks_are_errors() {
+        let header = section_header();
+        let with = |blocks: &[Vec<u8>]| [std::slice::from_ref(&header), blocks].concat().concat();
+
+        // An interface description without its link type and snap length.
+        assert!(is_invalid(parse(&with(&[block(
+            PCAPNG_INTERFACE_DESCRIPTION,
+            &[1, 0, 0, 0]
+        )]))));
+        // A simple packet block without its length field.
+        assert!(is_invalid(parse(&with(&[
+            interface(&[]),
+            block(PCAPNG_SIMPLE_PACKET, &[]),
+        ]))));
+        // An if_tsresol option announcing a value that is not there.
+        assert!(is_invalid(parse(&with(&[interface(&[9, 0, 1, 0])]))));
+        // A block running past the end of the file.
+        let mut file = with(&[interface(&[]), enhanced_packet(0, &udp(b"x"))]);
+        file.truncate(file.len() - 8);
+        assert!(is_invalid(parse(&file)));
+    }
+
+    #[test]
+    fn huge_timestamp_resolutions_do_not_overflow() {
+        let file = [
+            section_header(),
+            interface(&[9, 0, 1, 0, 0x7f, 0, 0, 0]),
+            enhanced_packet(u64::MAX, &udp(b"x")),
+        ]
+        .concat();
+        assert_eq!(parse(&file).unwrap().packets.len(), 1);
+    }
+}
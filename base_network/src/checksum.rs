@@ -0,0 +1,132 @@
+use crate::{CompactError, ScanDataFormat, StandardResult};
+
+/// What to do with a telegram whose trailing CRC32 does not match its contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumPolicy {
+    /// Drop the telegram and return [`CompactError::ChecksumMismatch`].
+    Reject,
+    /// Count the mismatch in [`ChecksumVerifier::failures`] and decode the telegram anyway.
+    #[default]
+    Warn,
+    /// Skip the CRC computation entirely.
+    Ignore,
+}
+
+/// Checksum policy plus the counters for one stream of telegrams.
+#[derive(Clone, Debug, Default)]
+pub struct ChecksumVerifier {
+    pub policy: ChecksumPolicy,
+    pub verified: u64,
+    pub failures: u64,
+}
+
+impl ChecksumVerifier {
+    pub fn new(policy: ChecksumPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Checks a complete telegram, start marker and checksum included.
+    pub fn verify(&mut self, telegram: &[u8]) -> StandardResult<()> {
+        if self.policy == ChecksumPolicy::Ignore {
+            return Ok(());
+        }
+        let (expected, computed) = telegram_checksums(telegram)?;
+        self.verified += 1;
+        if expected == computed {
+            return Ok(());
+        }
+        self.failures += 1;
+        match self.policy {
+            ChecksumPolicy::Reject => Err(CompactError::ChecksumMismatch { expected, computed }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        if self.verified == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.verified as f64
+    }
+}
+
+/// CRC32 (IEEE) over everything between the start marker and the checksum field, or over
+/// just the payload for MSGPACK telegrams.
+pub fn compact_crc(telegram_body: &[u8]) -> u32 {
+    crc32fast::hash(telegram_body)
+}
+
+/// Returns the (transmitted, computed) checksums of a complete telegram in either format.
+pub fn telegram_checksums(telegram: &[u8]) -> StandardResult<(u32, u32)> {
+    if telegram.len() < 12 {
+        return Err(CompactError::TruncatedTelegram {
+            needed: 12,
+            available: telegram.len(),
+        });
+    }
+    let (body, crc) = telegram.split_at(telegram.len() - 4);
+    let expected = u32::from_le_bytes(crc.try_into().unwrap());
+    let covered = match ScanDataFormat::detect(telegram) {
+        Some(ScanDataFormat::Msgpack) => &body[8..],
+        _ => &body[4..],
+    };
+    Ok((expected, compact_crc(covered)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompactHeader, CompactMessage, IMUData};
+
+    fn corrupted() -> Vec<u8> {
+        let imudata = IMUData {
+            telegram_version: 1,
+            acceleration: (0.0, 0.0, 9.81),
+            angular_velocity: (0.0, 0.0, 0.0),
+            orientation: (1.0, 0.0, 0.0, 0.0),
+            time_stamp: 42,
+        };
+        let mut telegram = CompactMessage::IMUMessage { imudata }.to_bytes();
+        telegram[20] ^= 0x01;
+        telegram
+    }
+
+    #[test]
+    fn intact_telegrams_pass_every_policy() {
+        let telegram = CompactMessage::distance(CompactHeader::new(1, 2, 4), []).to_bytes();
+        for policy in [
+            ChecksumPolicy::Reject,
+            ChecksumPolicy::Warn,
+            ChecksumPolicy::Ignore,
+        ] {
+            let mut verifier = ChecksumVerifier::new(policy);
+            assert!(verifier.verify(&telegram).is_ok());
+            assert_eq!(verifier.failures, 0);
+        }
+    }
+
+    #[test]
+    fn a_flipped_byte_is_handled_by_the_policy() {
+        let telegram = corrupted();
+
+        let mut reject = ChecksumVerifier::new(ChecksumPolicy::Reject);
+        assert!(matches!(
+            reject.verify(&telegram),
+            Err(CompactError::ChecksumMismatch { expected, computed }) if expected != computed
+        ));
+        assert_eq!((reject.verified, reject.failures), (1, 1));
+
+        let mut warn = ChecksumVerifier::new(ChecksumPolicy::Warn);
+        assert!(warn.verify(&telegram).is_ok());
+        assert!(warn.verify(&telegram).is_ok());
+        assert_eq!((warn.verified, warn.failures), (2, 2));
+        assert_eq!(warn.failure_rate(), 1.0);
+
+        let mut ignore = ChecksumVerifier::new(ChecksumPolicy::Ignore);
+        assert!(ignore.verify(&telegram).is_ok());
+        assert_eq!((ignore.verified, ignore.failures), (0, 0));
+    }
+}
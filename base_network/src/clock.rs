@@ -0,0 +1,304 @@
+//! Mapping of sensor timestamps onto the host clocks.
+//!
+//! Sensor timestamps are microseconds of a clock local to the sensor. The offset between a
+//! sensor timestamp and the arrival time of its telegram is the true clock offset plus the
+//! transmission and scheduling delay, which is never negative. [`ClockSync`] keeps the
+//! smallest offset per window, which is the one closest to the true offset, and fits a line
+//! through the window minima to follow the drift between the two oscillators.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{ArrivalTime, CompactMessage};
+
+/// A sensor clock that jumps back further than this has restarted.
+const RESTART_MICROS: u64 = 1_000_000;
+
+/// Window minimum as (sensor µs, host µs minus sensor µs).
+type Sample = (f64, f64);
+
+#[derive(Clone, Debug)]
+pub struct ClockSync {
+    /// Host time span the minimum delay is taken over.
+    pub window: Duration,
+    /// Window minima kept for the drift fit.
+    pub history: usize,
+    anchor: Option<ArrivalTime>,
+    window_start: Option<Instant>,
+    current: Option<Sample>,
+    minima: VecDeque<Sample>,
+    /// (offset at sensor time 0, drift)
+    fit: Option<(f64, f64)>,
+    last_sensor: Option<u64>,
+    pub restarts: u64,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), 60)
+    }
+}
+
+impl ClockSync {
+    pub fn new(window: Duration, history: usize) -> Self {
+        Self {
+            window,
+            history,
+            anchor: None,
+            window_start: None,
+            current: None,
+            minima: VecDeque::new(),
+            fit: None,
+            last_sensor: None,
+            restarts: 0,
+        }
+    }
+
+    /// Observes the header timestamp of a distance telegram or the timestamp of IMU data.
+    pub fn observe_message(&mut self, msg: &CompactMessage, arrival: ArrivalTime) {
+        match msg {
+            CompactMessage::DistanceMessage { header, .. } => {
+                self.observe(header.timestamp, arrival)
+            }
+            CompactMessage::IMUMessage { imudata } => self.observe(imudata.time_stamp, arrival),
+        }
+    }
+
+    /// Observes a 32 bit microsecond counter such as the `time_of_transmission` of a CoLa
+    /// scan, which wraps after about 71 minutes.
+    pub fn observe_u32(&mut self, sensor_micros: u32, arrival: ArrivalTime) {
+        self.observe(self.unwrap_u32(sensor_micros), arrival);
+    }
+
+    /// Extends a 32 bit counter to 64 bits using the latest observed sensor time.
+    pub fn unwrap_u32(&self, sensor_micros: u32) -> u64 {
+        let Some(last) = self.last_sensor else {
+            return sensor_micros as u64;
+        };
+        let candidate = (last & !0xffff_ffff) | sensor_micros as u64;
+        if candidate + (1 << 31) < last {
+            candidate + (1 << 32)
+        } else if candidate > last + (1 << 31) && candidate >= 1 << 32 {
+            candidate - (1 << 32)
+        } else {
+            candidate
+        }
+    }
+
+    /// Records that a telegram stamped `sensor_micros` arrived at `arrival`.
+    pub fn observe(&mut self, sensor_micros: u64, arrival: ArrivalTime) {
+        if self
+            .last_sensor
+            .is_some_and(|last| sensor_micros + RESTART_MICROS < last)
+        {
+            self.reset();
+            self.restarts += 1;
+        }
+        self.last_sensor = Some(self.last_sensor.unwrap_or(0).max(sensor_micros));
+        let anchor = *self.anchor.get_or_insert(arrival);
+        let host = signed_micros(anchor.instant, arrival.instant);
+        let sample = (sensor_micros as f64, host - sensor_micros as f64);
+
+        let window_start = *self.window_start.get_or_insert(arrival.instant);
+        if arrival.instant.saturating_duration_since(window_start) >= self.window {
+            if let Some(min) = self.current.take() {
+                self.minima.push_back(min);
+                while self.minima.len() > self.history.max(2) {
+                    self.minima.pop_front();
+                }
+            }
+            self.window_start = Some(arrival.instant);
+        }
+        if self.current.is_none_or(|c| sample.1 < c.1) {
+            self.current = Some(sample);
+        }
+        self.fit = self.fit_line();
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            restarts: self.restarts,
+            ..Self::new(self.window, self.history)
+        };
+    }
+
+    /// Least squares line through the window minima. Until two windows are complete the
+    /// offset is the smallest one seen so far and no drift is assumed.
+    fn fit_line(&self) -> Option<(f64, f64)> {
+        if self.minima.len() < 2 {
+            let min = self
+                .minima
+                .iter()
+                .chain(self.current.iter())
+                .min_by(|a, b| a.1.total_cmp(&b.1))?;
+            return Some((min.1, 0.0));
+        }
+        let n = self.minima.len() as f64;
+        let mean_x = self.minima.iter().map(|s| s.0).sum::<f64>() / n;
+        let mean_y = self.minima.iter().map(|s| s.1).sum::<f64>() / n;
+        let (sxy, sxx) = self.minima.iter().fold((0.0, 0.0), |(sxy, sxx), s| {
+            let dx = s.0 - mean_x;
+            (sxy + dx * (s.1 - mean_y), sxx + dx * dx)
+        });
+        let drift = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        Some((mean_y - drift * mean_x, drift))
+    }
+
+    /// Estimated host minus sensor time at `sensor_micros`, in µs.
+    pub fn offset_micros(&self, sensor_micros: u64) -> Option<f64> {
+        self.fit
+            .map(|(offset, drift)| offset + drift * sensor_micros as f64)
+    }
+
+    /// Rate the sensor clock runs slow against the host clock, in parts per million.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.fit.map(|(_, drift)| drift * 1e6)
+    }
+
+    /// Host monotonic and wall clock time at which the sensor clock read `sensor_micros`.
+    pub fn to_host_time(&self, sensor_micros: u64) -> Option<ArrivalTime> {
+        let anchor = self.anchor?;
+        let host = sensor_micros as f64 + self.offset_micros(sensor_micros)?;
+        let delta = Duration::from_micros(host.abs().round() as u64);
+        Some(if host >= 0.0 {
+            ArrivalTime {
+                instant: anchor.instant + delta,
+                system: anchor.system + delta,
+            }
+        } else {
+            ArrivalTime {
+                instant: anchor.instant.checked_sub(delta)?,
+                system: anchor.system.checked_sub(delta)?,
+            }
+        })
+    }
+}
+
+fn signed_micros(from: Instant, to: Instant) -> f64 {
+    match to.checked_duration_since(from) {
+        Some(d) => d.as_micros() as f64,
+        None => -(from.duration_since(to).as_micros() as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    const SENSOR_START: u64 = 5_000_000;
+
+    struct Host {
+        base: ArrivalTime,
+    }
+
+    impl Host {
+        fn new() -> Self {
+            Self {
+                base: ArrivalTime::now(),
+            }
+        }
+
+        fn at(&self, micros: f64) -> ArrivalTime {
+            let delta = Duration::from_micros(micros.round() as u64);
+            ArrivalTime {
+                instant: self.base.instant + delta,
+                system: self.base.system + delta,
+            }
+        }
+    }
+
+    /// Feeds 10 s of telegrams every 10 ms. The host clock runs `drift` faster than the
+    /// sensor and every tenth telegram arrives without delay.
+    fn feed(clock: &mut ClockSync, host: &Host, drift: f64) {
+        for i in 0..1000_u64 {
+            let sensor = SENSOR_START + i * 10_000;
+            let delay = if i % 10 == 0 { 0 } else { i * 7919 % 500 + 1 };
+            let elapsed = (sensor - SENSOR_START) as f64 * (1.0 + drift);
+            clock.observe(sensor, host.at(elapsed + delay as f64));
+        }
+    }
+
+    #[test]
+    fn a_constant_offset_is_found_through_the_delays() {
+        let (mut clock, host) = (ClockSync::default(), Host::new());
+        feed(&mut clock, &host, 0.0);
+        for sensor in [
+            SENSOR_START,
+            SENSOR_START + 5_000_000,
+            SENSOR_START + 9_990_000,
+        ] {
+            let offset = clock.offset_micros(sensor).unwrap();
+            assert!((offset + SENSOR_START as f64).abs() < 2.0, "{offset}");
+        }
+        assert!(clock.drift_ppm().unwrap().abs() < 0.5);
+    }
+
+    #[test]
+    fn drift_is_fitted_in_ppm() {
+        let (mut clock, host) = (ClockSync::default(), Host::new());
+        feed(&mut clock, &host, 50e-6);
+        let drift = clock.drift_ppm().unwrap();
+        assert!((drift - 50.0).abs() < 1.0, "{drift}");
+
+        let sensor = SENSOR_START + 8_000_000;
+        let expected = host.at(8_000_000.0 * (1.0 + 50e-6));
+        let mapped = clock.to_host_time(sensor).unwrap();
+        let error = signed_micros(expected.instant, mapped.instant);
+        assert!(error.abs() < 5.0, "{error}");
+        let wall = mapped
+            .system
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let expected_wall = expected
+            .system
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert!(wall.abs_diff(expected_wall) < Duration::from_micros(5));
+    }
+
+    #[test]
+    fn the_first_window_uses_the_smallest_offset() {
+        let (mut clock, host) = (ClockSync::default(), Host::new());
+        assert_eq!(clock.offset_micros(0), None);
+        // Host time counts from the first arrival, so the offsets are -1000 and -1200.
+        clock.observe(1_000, host.at(300.0));
+        clock.observe(2_000, host.at(1_100.0));
+        assert_eq!(clock.offset_micros(0), Some(-1200.0));
+        assert_eq!(clock.drift_ppm(), Some(0.0));
+    }
+
+    #[test]
+    fn u32_counters_are_unwrapped() {
+        let mut clock = ClockSync::default();
+        let host = Host::new();
+        assert_eq!(clock.unwrap_u32(7), 7);
+        clock.observe_u32(u32::MAX - 99, host.at(0.0));
+        assert_eq!(clock.unwrap_u32(u32::MAX), u32::MAX as u64);
+        assert_eq!(clock.unwrap_u32(50), (1 << 32) + 50);
+        clock.observe_u32(50, host.at(150.0));
+        assert_eq!(clock.unwrap_u32(u32::MAX - 9), u32::MAX as u64 - 9);
+        assert_eq!(clock.unwrap_u32(100), (1 << 32) + 100);
+        assert_eq!(clock.restarts, 0);
+        assert!((clock.offset_micros(0).unwrap() + (u32::MAX - 99) as f64).abs() < 1.0);
+    }
+
+    #[test]
+    fn a_sensor_clock_jumping_back_is_a_restart() {
+        let (mut clock, host) = (ClockSync::default(), Host::new());
+        feed(&mut clock, &host, 0.0);
+        clock.observe(SENSOR_START, host.at(9_999_000.0));
+        assert_eq!(clock.restarts, 1);
+        assert_eq!(clock.drift_ppm(), Some(0.0));
+        let mapped = clock.to_host_time(SENSOR_START + 1_000).unwrap();
+        let expected = host.at(10_000_000.0);
+        assert!(signed_micros(expected.instant, mapped.instant).abs() < 1.0);
+
+        // Less than RESTART_MICROS back is only a late telegram.
+        clock.observe(SENSOR_START - 1_000, host.at(10_000_000.0));
+        assert_eq!(clock.restarts, 1);
+    }
+}
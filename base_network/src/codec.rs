@@ -0,0 +1,276 @@
+//! Compact and MSGPACK framing as a tokio-util codec.
+//!
+//! [`CompactCodec`] finds telegram boundaries in a byte stream the same way
+//! [`CompactMessage::read_raw_telegram`] does, but on buffered bytes, so it never blocks in
+//! the middle of a telegram and can resynchronise on the next start marker when the stream
+//! contains garbage or a telegram was cut short.
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    checksum::ChecksumVerifier,
+    frame::{module_next_size, read_u32_at, COMPACT_HEADER_SIZE, IMU_DATA_SIZE},
+    msgpack, CompactError, CompactMessage, ToBytes, COMPACT_DISTANCE_COMMAND, COMPACT_IMU_COMMAND,
+    COMPACT_STX, MAX_MODULES, MAX_MODULE_SIZE,
+};
+
+/// What the bytes at the start of the buffer are.
+enum Framing {
+    Complete(usize),
+    Incomplete(usize),
+    /// Not the start of a telegram.
+    Invalid,
+}
+
+/// Length of the telegram at the start of `buf`, which begins with a start marker.
+fn telegram_length(buf: &[u8]) -> Framing {
+    let need = |length: usize| match buf.len() >= length {
+        true => Framing::Complete(length),
+        false => Framing::Incomplete(length),
+    };
+    if buf.len() < 8 {
+        return Framing::Incomplete(8);
+    }
+    match read_u32_at(buf, 4) {
+        COMPACT_DISTANCE_COMMAND => {
+            let mut length = 8 + COMPACT_HEADER_SIZE;
+            if buf.len() < length {
+                return Framing::Incomplete(length);
+            }
+            let mut next = read_u32_at(buf, length - 4);
+            for _ in 0..=MAX_MODULES {
+                if next == 0 {
+                    return need(length + 4);
+                }
+                if next > MAX_MODULE_SIZE {
+                    return Framing::Invalid;
+                }
+                let module = length..length + next as usize;
+                let Some(bytes) = buf.get(module.clone()) else {
+                    return Framing::Incomplete(module.end);
+                };
+                let Some(size) = module_next_size(bytes) else {
+                    return Framing::Invalid;
+                };
+                next = size;
+                length = module.end;
+            }
+            Framing::Invalid
+        }
+        COMPACT_IMU_COMMAND => need(8 + IMU_DATA_SIZE + 4),
+        len if len <= msgpack::MSGPACK_MAX_PAYLOAD => match buf.get(8) {
+            None => Framing::Incomplete(9),
+            Some(b) if msgpack::is_map_marker(*b) => need(8 + len as usize + 4),
+            Some(_) => Framing::Invalid,
+        },
+        _ => Framing::Invalid,
+    }
+}
+
+/// Decodes Compact and MSGPACK telegrams from a byte stream, e.g.
+/// `FramedRead::new(stream, CompactCodec::default())`.
+///
+/// Telegrams that are framed correctly but fail to decode, including checksum failures
+/// under [`ChecksumPolicy::Reject`](crate::ChecksumPolicy::Reject), are skipped and counted
+/// instead of ending the stream.
+#[derive(Debug, Default)]
+pub struct CompactCodec {
+    pub verifier: ChecksumVerifier,
+    /// Bytes skipped while looking for the start of a telegram.
+    pub discarded: u64,
+    /// Telegrams skipped because they failed to decode.
+    pub skipped: u64,
+    pub last_error: Option<CompactError>,
+}
+
+impl CompactCodec {
+    pub fn new(verifier: ChecksumVerifier) -> Self {
+        Self {
+            verifier,
+            ..Default::default()
+        }
+    }
+
+    /// Drops everything before the next start marker, keeping a partial marker at the end.
+    fn synchronise(&mut self, src: &mut BytesMut) {
+        let stx = COMPACT_STX.to_le_bytes();
+        let start = src
+            .windows(stx.len())
+            .position(|w| w == stx)
+            .unwrap_or_else(|| {
+                let partial = src.iter().rev().take_while(|b| **b == 0x02).count();
+                src.len() - partial.min(stx.len() - 1)
+            });
+        self.discarded += start as u64;
+        src.advance(start);
+    }
+}
+
+impl Decoder for CompactCodec {
+    type Item = CompactMessage;
+    type Error = CompactError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<CompactMessage>, CompactError> {
+        loop {
+            self.synchronise(src);
+            match telegram_length(src) {
+                Framing::Incomplete(length) => {
+                    src.reserve(length.saturating_sub(src.len()));
+                    return Ok(None);
+                }
+                Framing::Invalid => {
+                    self.discarded += 1;
+                    src.advance(1);
+                }
+                Framing::Complete(length) => {
+                    let telegram = src.split_to(length);
+                    match CompactMessage::from_slice(&telegram, &mut self.verifier) {
+                        Ok(msg) => return Ok(Some(msg)),
+                        Err(e) => {
+                            self.skipped += 1;
+                            self.last_error = Some(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Encoder<&CompactMessage> for CompactCodec {
+    type Error = CompactError;
+
+    fn encode(&mut self, item: &CompactMessage, dst: &mut BytesMut) -> Result<(), CompactError> {
+        let mut data = Vec::new();
+        item.write_to_data(&mut data);
+        dst.extend_from_slice(&data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{
+        BeamContent, ChecksumPolicy, CompactHeader, Echo, EchoContent, IMUData, Measurement,
+        MeasurementLayerOutput, MeasurementModule,
+    };
+
+    fn distance(counter: u64) -> CompactMessage {
+        let layer = MeasurementLayerOutput {
+            phi: 0.0,
+            theta_start: -1.0,
+            theta_end: 1.0,
+            time_stamp_start: 1,
+            time_stamp_end: 2,
+            data: (0..4)
+                .map(|beam| Measurement::Filled {
+                    echoes: smallvec![Echo {
+                        distance: Some(100 + beam),
+                        rssi: None,
+                    }],
+                    beam_properties: None,
+                    azimuth_angle: None,
+                })
+                .collect(),
+        };
+        let module = MeasurementModule::new(
+            0,
+            counter,
+            1,
+            1.0,
+            EchoContent::DISTANCE,
+            BeamContent::empty(),
+            smallvec![layer],
+        );
+        CompactMessage::distance(CompactHeader::new(counter, 2, 4), [module.clone(), module])
+    }
+
+    fn imu() -> CompactMessage {
+        CompactMessage::IMUMessage {
+            imudata: IMUData {
+                telegram_version: 1,
+                acceleration: (0.0, 0.0, 9.81),
+                angular_velocity: (0.0, 0.0, 0.0),
+                orientation: (1.0, 0.0, 0.0, 0.0),
+                time_stamp: 1,
+            },
+        }
+    }
+
+    fn decode_all(codec: &mut CompactCodec, src: &mut BytesMut) -> Vec<CompactMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = codec.decode(src).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn telegrams_split_across_calls_are_decoded_once_complete() {
+        let bytes = distance(1).to_bytes();
+        for split in [1, 4, 7, 8, 20, 40, bytes.len() - 1] {
+            let mut codec = CompactCodec::default();
+            let mut src = BytesMut::from(&bytes[..split]);
+            assert_eq!(codec.decode(&mut src).unwrap(), None);
+            src.extend_from_slice(&bytes[split..]);
+            assert_eq!(codec.decode(&mut src).unwrap(), Some(distance(1)));
+            assert!(src.is_empty());
+            assert_eq!(codec.discarded, 0);
+        }
+    }
+
+    #[test]
+    fn garbage_before_the_start_marker_is_discarded() {
+        let garbage = b"\x02\x02\x00noise\x02\x02\x02";
+        let mut src = BytesMut::from(&garbage[..]);
+        src.extend_from_slice(&distance(1).to_bytes());
+        let mut codec = CompactCodec::default();
+        assert_eq!(decode_all(&mut codec, &mut src), [distance(1)]);
+        assert_eq!(codec.discarded, garbage.len() as u64);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn telegrams_sharing_a_buffer_are_decoded_in_order() {
+        let mut src = BytesMut::new();
+        for message in [distance(1), imu(), distance(2)] {
+            src.extend_from_slice(&message.to_bytes());
+        }
+        let mut codec = CompactCodec::default();
+        assert_eq!(
+            decode_all(&mut codec, &mut src),
+            [distance(1), imu(), distance(2)]
+        );
+        assert_eq!(codec.discarded, 0);
+    }
+
+    #[test]
+    fn encoded_telegrams_decode_to_themselves() {
+        let mut codec = CompactCodec::new(ChecksumVerifier::new(ChecksumPolicy::Reject));
+        let mut dst = BytesMut::new();
+        for message in [distance(3), imu()] {
+            codec.encode(&message, &mut dst).unwrap();
+        }
+        assert_eq!(decode_all(&mut codec, &mut dst), [distance(3), imu()]);
+    }
+
+    #[test]
+    fn undecodable_telegrams_are_skipped() {
+        let mut corrupted = distance(1).to_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        let mut src = BytesMut::from(&corrupted[..]);
+        src.extend_from_slice(&distance(2).to_bytes());
+        let mut codec = CompactCodec::new(ChecksumVerifier::new(ChecksumPolicy::Reject));
+        assert_eq!(decode_all(&mut codec, &mut src), [distance(2)]);
+        assert_eq!(codec.skipped, 1);
+        assert!(matches!(
+            codec.last_error,
+            Some(CompactError::ChecksumMismatch { .. })
+        ));
+    }
+}
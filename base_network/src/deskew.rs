@@ -0,0 +1,393 @@
+//! Motion compensation of scan points with the multiScan's IMU.
+//!
+//! A frame is recorded while the sensor moves, so every beam is measured from a slightly
+//! different pose. [`Deskewer`] integrates the angular velocity and acceleration of the IMU
+//! telegrams over the frame and moves each point into the pose the sensor had at the end of
+//! the frame.
+
+use std::collections::VecDeque;
+
+use crate::{CompactMessage, IMUData, ScanPoint};
+
+/// Points are in millimetres, the IMU works in metres.
+const MM_PER_M: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Quat {
+    w: f32,
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Quat {
+    const IDENTITY: Quat = Quat {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// Rotation by the angle and around the axis of the rotation vector `v`.
+    fn from_rotation_vector(v: [f32; 3]) -> Self {
+        let angle = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        if angle < 1e-9 {
+            return Self::IDENTITY;
+        }
+        let (s, c) = (angle / 2.0).sin_cos();
+        let k = s / angle;
+        Quat {
+            w: c,
+            x: v[0] * k,
+            y: v[1] * k,
+            z: v[2] * k,
+        }
+    }
+
+    fn mul(self, o: Quat) -> Quat {
+        Quat {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+
+    fn conjugate(self) -> Quat {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    fn normalize(self) -> Quat {
+        let n = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        if n < 1e-9 {
+            return Self::IDENTITY;
+        }
+        Quat {
+            w: self.w / n,
+            x: self.x / n,
+            y: self.y / n,
+            z: self.z / n,
+        }
+    }
+
+    fn rotate(self, v: [f32; 3]) -> [f32; 3] {
+        let p = Quat {
+            w: 0.0,
+            x: v[0],
+            y: v[1],
+            z: v[2],
+        };
+        let r = self.mul(p).mul(self.conjugate());
+        [r.x, r.y, r.z]
+    }
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: [f32; 3], k: f32) -> [f32; 3] {
+    [a[0] * k, a[1] * k, a[2] * k]
+}
+
+/// Pose of the sensor relative to its pose at the start of the integration, plus the
+/// motion that is applied until the next step.
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    time: u64,
+    rotation: Quat,
+    /// Metres.
+    position: [f32; 3],
+    /// Metres per second.
+    velocity: [f32; 3],
+    angular_velocity: [f32; 3],
+    /// Gravity free acceleration in the sensor frame.
+    acceleration: [f32; 3],
+}
+
+impl Step {
+    /// Pose `dt` seconds after this step.
+    fn advance(&self, dt: f32) -> (Quat, [f32; 3], [f32; 3]) {
+        let rotation = self
+            .rotation
+            .mul(Quat::from_rotation_vector(scale(self.angular_velocity, dt)))
+            .normalize();
+        let acceleration = self.rotation.rotate(self.acceleration);
+        let position = add(
+            self.position,
+            add(scale(self.velocity, dt), scale(acceleration, 0.5 * dt * dt)),
+        );
+        let velocity = add(self.velocity, scale(acceleration, dt));
+        (rotation, position, velocity)
+    }
+}
+
+/// Corrects scan points for the motion of the sensor using its IMU telegrams.
+///
+/// The IMU only measures acceleration, so the velocity at the start of a frame has to come
+/// from elsewhere, e.g. odometry. Left at zero only the rotation and the change of velocity
+/// within the frame are compensated.
+#[derive(Clone, Debug)]
+pub struct Deskewer {
+    /// Velocity of the sensor at the start of the next frame in m/s, in sensor coordinates.
+    pub velocity: [f32; 3],
+    /// Gravity in m/s², removed from the acceleration using the IMU orientation.
+    pub gravity: f32,
+    /// IMU samples kept for integration.
+    pub max_samples: usize,
+    samples: VecDeque<IMUData>,
+}
+
+impl Default for Deskewer {
+    fn default() -> Self {
+        Self {
+            velocity: [0.0; 3],
+            gravity: 9.81,
+            max_samples: 1024,
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl Deskewer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the IMU data of `msg`, distance telegrams are ignored.
+    pub fn push_message(&mut self, msg: &CompactMessage) {
+        if let CompactMessage::IMUMessage { imudata } = msg {
+            self.push(*imudata);
+        }
+    }
+
+    pub fn push(&mut self, imu: IMUData) {
+        let at = self
+            .samples
+            .iter()
+            .rposition(|s| s.time_stamp <= imu.time_stamp)
+            .map_or(0, |i| i + 1);
+        self.samples.insert(at, imu);
+        while self.samples.len() > self.max_samples.max(1) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Drops samples older than `time_stamp`, keeping the one in effect at that time.
+    pub fn prune_before(&mut self, time_stamp: u64) {
+        while self.samples.len() > 1 && self.samples[1].time_stamp <= time_stamp {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Acceleration of `imu` with gravity removed. The orientation is (w, x, y, z) and
+    /// rotates sensor coordinates into the world frame.
+    fn linear_acceleration(&self, imu: &IMUData) -> [f32; 3] {
+        let (w, x, y, z) = imu.orientation;
+        let orientation = Quat { w, x, y, z }.normalize();
+        let gravity = orientation.conjugate().rotate([0.0, 0.0, self.gravity]);
+        let (ax, ay, az) = imu.acceleration;
+        [ax - gravity[0], ay - gravity[1], az - gravity[2]]
+    }
+
+    /// The IMU sample in effect at `time_stamp`.
+    fn sample_at(&self, time_stamp: u64) -> Option<&IMUData> {
+        self.samples
+            .iter()
+            .rev()
+            .find(|s| s.time_stamp <= time_stamp)
+            .or(self.samples.front())
+    }
+
+    /// Integrates the IMU from `start` to `end`, one step per sample in between.
+    fn integrate(&self, start: u64, end: u64) -> Vec<Step> {
+        let mut times = vec![start];
+        times.extend(
+            self.samples
+                .iter()
+                .map(|s| s.time_stamp)
+                .filter(|t| *t > start && *t < end),
+        );
+        let mut steps: Vec<Step> = Vec::with_capacity(times.len());
+        for time in times {
+            let (rotation, position, velocity) = match steps.last() {
+                Some(prev) => prev.advance((time - prev.time) as f32 * 1e-6),
+                None => (Quat::IDENTITY, [0.0; 3], self.velocity),
+            };
+            let imu = self.sample_at(time);
+            steps.push(Step {
+                time,
+                rotation,
+                position,
+                velocity,
+                angular_velocity: imu.map_or([0.0; 3], |s| s.angular_velocity.into()),
+                acceleration: imu.map_or([0.0; 3], |s| self.linear_acceleration(s)),
+            });
+        }
+        steps
+    }
+
+    fn pose_at(steps: &[Step], time_stamp: u64) -> (Quat, [f32; 3]) {
+        let i = steps.partition_point(|s| s.time <= time_stamp).max(1) - 1;
+        let step = &steps[i];
+        let dt = time_stamp.saturating_sub(step.time) as f32 * 1e-6;
+        let (rotation, position, _) = step.advance(dt);
+        (rotation, position)
+    }
+
+    /// Moves every point into the sensor pose at the time of the latest point.
+    pub fn deskew(&self, points: &mut [ScanPoint]) {
+        if let Some(end) = points.iter().map(|p| p.time_stamp).max() {
+            self.deskew_to(points, end);
+        }
+    }
+
+    /// Moves every point into the sensor pose at `end`, in µs of the sensor clock. Does
+    /// nothing until IMU data arrived.
+    pub fn deskew_to(&self, points: &mut [ScanPoint], end: u64) {
+        let Some(start) = points.iter().map(|p| p.time_stamp).min() else {
+            return;
+        };
+        if self.samples.is_empty() {
+            return;
+        }
+        let steps = self.integrate(start.min(end), end.max(start));
+        let (end_rotation, end_position) = Self::pose_at(&steps, end);
+        let to_end = end_rotation.conjugate();
+        for point in points.iter_mut() {
+            let (rotation, position) = Self::pose_at(&steps, point.time_stamp);
+            let world = add(
+                rotation.rotate([point.x, point.y, point.z]),
+                scale(position, MM_PER_M),
+            );
+            let [x, y, z] = to_end.rotate(add(world, scale(end_position, -MM_PER_M)));
+            point.x = x;
+            point.y = y;
+            point.z = z;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    use super::*;
+
+    fn imu(time_stamp: u64, angular_velocity: (f32, f32, f32)) -> IMUData {
+        IMUData {
+            telegram_version: 1,
+            acceleration: (0.0, 0.0, 9.81),
+            angular_velocity,
+            orientation: (1.0, 0.0, 0.0, 0.0),
+            time_stamp,
+        }
+    }
+
+    fn point(x: f32, y: f32, z: f32, time_stamp: u64) -> ScanPoint {
+        ScanPoint {
+            x,
+            y,
+            z,
+            intensity: None,
+            layer: 0,
+            beam: 0,
+            echo: 0,
+            time_stamp,
+            properties: None,
+        }
+    }
+
+    fn assert_close(p: &ScanPoint, expected: [f32; 3]) {
+        let error = [p.x - expected[0], p.y - expected[1], p.z - expected[2]];
+        assert!(
+            error.iter().all(|e| e.abs() < 0.05),
+            "({}, {}, {}) is not {expected:?}",
+            p.x,
+            p.y,
+            p.z
+        );
+    }
+
+    #[test]
+    fn a_static_sensor_leaves_points_in_place() {
+        let mut deskewer = Deskewer::new();
+        for t in (0..=100_000).step_by(10_000) {
+            deskewer.push(imu(t, (0.0, 0.0, 0.0)));
+        }
+        let mut points = [
+            point(1000.0, 0.0, 0.0, 0),
+            point(0.0, 2000.0, 500.0, 100_000),
+        ];
+        deskewer.deskew(&mut points);
+        assert_close(&points[0], [1000.0, 0.0, 0.0]);
+        assert_close(&points[1], [0.0, 2000.0, 500.0]);
+    }
+
+    #[test]
+    fn gravity_is_removed_along_the_orientation() {
+        let deskewer = Deskewer::new();
+        // Rolled by 90° about x, gravity shows up on the y axis of the sensor.
+        let tilted = IMUData {
+            acceleration: (0.0, 9.81, 0.0),
+            orientation: (FRAC_1_SQRT_2, FRAC_1_SQRT_2, 0.0, 0.0),
+            ..imu(0, (0.0, 0.0, 0.0))
+        };
+        for a in deskewer.linear_acceleration(&tilted) {
+            assert!(a.abs() < 1e-4, "{a}");
+        }
+        let level = deskewer.linear_acceleration(&imu(0, (0.0, 0.0, 0.0)));
+        assert!(level.iter().all(|a| a.abs() < 1e-4));
+    }
+
+    #[test]
+    fn a_constant_rotation_is_undone() {
+        let mut deskewer = Deskewer::new();
+        for t in (0..=100_000).step_by(10_000) {
+            deskewer.push(imu(t, (0.0, 0.0, 1.0)));
+        }
+        // 0.1 s at 1 rad/s about z: the first point is seen 0.1 rad further clockwise.
+        let mut points = [point(1000.0, 0.0, 0.0, 0), point(1000.0, 0.0, 0.0, 100_000)];
+        deskewer.deskew(&mut points);
+        let (sin, cos) = 0.1_f32.sin_cos();
+        assert_close(&points[0], [1000.0 * cos, -1000.0 * sin, 0.0]);
+        assert_close(&points[1], [1000.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn a_constant_velocity_moves_points_back() {
+        let mut deskewer = Deskewer::new();
+        deskewer.push(imu(0, (0.0, 0.0, 0.0)));
+        deskewer.velocity = [2.0, 0.0, 0.0];
+        // 0.1 s at 2 m/s forward: the sensor closed 200 mm on the first point.
+        let mut points = [point(1000.0, 0.0, 0.0, 0), point(1000.0, 0.0, 0.0, 100_000)];
+        deskewer.deskew(&mut points);
+        assert_close(&points[0], [800.0, 0.0, 0.0]);
+        assert_close(&points[1], [1000.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn samples_are_kept_in_time_order_and_pruned() {
+        let mut deskewer = Deskewer {
+            max_samples: 3,
+            ..Default::default()
+        };
+        for t in [30, 10, 20, 40] {
+            deskewer.push(imu(t, (0.0, 0.0, 0.0)));
+        }
+        let times: Vec<u64> = deskewer.samples.iter().map(|s| s.time_stamp).collect();
+        assert_eq!(times, [20, 30, 40]);
+        deskewer.prune_before(35);
+        assert_eq!(deskewer.sample_count(), 2);
+        assert_eq!(deskewer.samples[0].time_stamp, 30);
+    }
+}
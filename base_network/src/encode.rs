@@ -0,0 +1,324 @@
+use smallvec::SmallVec;
+
+use crate::{
+    checksum::compact_crc,
+    frame::{beam_stride, module_metadata_size},
+    BeamContent, CompactHeader, CompactMessage, CompactModule, Echo, EchoContent, IMUData,
+    Measurement, MeasurementModule, COMPACT_DISTANCE_COMMAND, COMPACT_IMU_COMMAND, COMPACT_STX,
+};
+
+/// Serialises a value in the little-endian Compact wire format.
+pub trait ToBytes {
+    fn write_to_data(&self, data: &mut Vec<u8>);
+}
+
+impl CompactMessage {
+    /// Builds a distance telegram, chaining the module sizes so that the result encodes and
+    /// decodes back to itself.
+    pub fn distance(
+        mut header: CompactHeader,
+        modules: impl IntoIterator<Item = MeasurementModule>,
+    ) -> Self {
+        let mut data: SmallVec<[MeasurementModule; 4]> = modules.into_iter().collect();
+        let mut next = 0;
+        for module in data.iter_mut().rev() {
+            module.next_module_size = next;
+            next = module.encoded_size() as u32;
+        }
+        header.next_module_size = next;
+        CompactMessage::DistanceMessage {
+            header,
+            data: Box::new(data),
+        }
+    }
+
+    /// Encodes the complete telegram, start marker and CRC32 included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(16384);
+        self.write_to_data(&mut data);
+        data
+    }
+}
+
+impl ToBytes for CompactMessage {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        let start = data.len();
+        COMPACT_STX.write_to_data(data);
+        match self {
+            CompactMessage::IMUMessage { imudata } => {
+                COMPACT_IMU_COMMAND.write_to_data(data);
+                imudata.write_to_data(data);
+            }
+            CompactMessage::DistanceMessage {
+                header,
+                data: modules,
+            } => {
+                COMPACT_DISTANCE_COMMAND.write_to_data(data);
+                header.write_to_data(data);
+                modules.iter().for_each(|m| m.write_to_data(data));
+            }
+        }
+        compact_crc(&data[start + 4..]).write_to_data(data);
+    }
+}
+
+impl ToBytes for CompactHeader {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        self.telegram_counter.write_to_data(data);
+        self.timestamp.write_to_data(data);
+        self.telegram_version.write_to_data(data);
+        self.next_module_size().write_to_data(data);
+    }
+}
+
+impl MeasurementModule {
+    /// Number of bytes this module takes on the wire.
+    pub fn encoded_size(&self) -> usize {
+        let lines = self.number_lines_in_module as usize;
+        module_metadata_size(lines)
+            + lines
+                * self.number_of_beams_per_scan as usize
+                * beam_stride(
+                    self.number_of_echoes_per_beam as usize,
+                    self.data_content_echoes,
+                    self.data_content_beams,
+                )
+    }
+
+    /// Writes one beam record. Missing or empty measurements are written as zeros so the
+    /// record keeps the size the data content flags promise.
+    fn write_measurement(&self, measurement: Option<&Measurement>, data: &mut Vec<u8>) {
+        let (echoes, beam_properties, azimuth_angle) = match measurement {
+            Some(Measurement::Filled {
+                echoes,
+                beam_properties,
+                azimuth_angle,
+            }) => (echoes.as_slice(), *beam_properties, *azimuth_angle),
+            _ => (&[][..], None, None),
+        };
+        for i in 0..self.number_of_echoes_per_beam as usize {
+            let echo = echoes.get(i).copied().unwrap_or(Echo {
+                distance: None,
+                rssi: None,
+            });
+            if self.data_content_echoes.contains(EchoContent::DISTANCE) {
+                echo.distance.unwrap_or(0).write_to_data(data);
+            }
+            if self.data_content_echoes.contains(EchoContent::RSSI) {
+                echo.rssi.unwrap_or(0).write_to_data(data);
+            }
+        }
+        if self.data_content_beams.contains(BeamContent::PROPERTIES) {
+            beam_properties.map_or(0, |p| p.bits()).write_to_data(data);
+        }
+        if self.data_content_beams.contains(BeamContent::AZIMUTH) {
+            azimuth_angle.unwrap_or(0).write_to_data(data);
+        }
+    }
+}
+
+impl ToBytes for MeasurementModule {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        self.segment_counter.write_to_data(data);
+        self.frame_number.write_to_data(data);
+        self.sender_id.write_to_data(data);
+        self.number_lines_in_module.write_to_data(data);
+        self.number_of_beams_per_scan.write_to_data(data);
+        self.number_of_echoes_per_beam.write_to_data(data);
+        self.time_stamp_start
+            .iter()
+            .for_each(|v| v.write_to_data(data));
+        self.time_stamp_end
+            .iter()
+            .for_each(|v| v.write_to_data(data));
+        self.phi.iter().for_each(|v| v.write_to_data(data));
+        self.theta_start.iter().for_each(|v| v.write_to_data(data));
+        self.theta_end.iter().for_each(|v| v.write_to_data(data));
+        self.distance_scale_factor.write_to_data(data);
+        self.next_module_size().write_to_data(data);
+        0_u8.write_to_data(data);
+        self.data_content_echoes.bits().write_to_data(data);
+        self.data_content_beams.bits().write_to_data(data);
+        0_u8.write_to_data(data);
+        for beam in 0..self.number_of_beams_per_scan as usize {
+            for layer in self.data.iter() {
+                self.write_measurement(layer.data.get(beam), data);
+            }
+        }
+    }
+}
+
+impl ToBytes for IMUData {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        self.telegram_version.write_to_data(data);
+        let (x, y, z) = self.acceleration;
+        [x, y, z].iter().for_each(|v| v.write_to_data(data));
+        let (x, y, z) = self.angular_velocity;
+        [x, y, z].iter().for_each(|v| v.write_to_data(data));
+        let (w, x, y, z) = self.orientation;
+        [w, x, y, z].iter().for_each(|v| v.write_to_data(data));
+        self.time_stamp.write_to_data(data);
+    }
+}
+
+impl ToBytes for u8 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.push(*self);
+    }
+}
+impl ToBytes for u16 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl ToBytes for u32 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl ToBytes for u64 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_le_bytes());
+    }
+}
+impl ToBytes for f32 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{BeamProperties, ChecksumPolicy, ChecksumVerifier, MeasurementLayerOutput};
+
+    /// A module holding only the fields its content flags announce, so decoding gives it back
+    /// unchanged.
+    fn module(
+        frame: u64,
+        lines: u16,
+        beams: u16,
+        echoes: u16,
+        echo_content: EchoContent,
+        beam_content: BeamContent,
+    ) -> MeasurementModule {
+        let layer = |line: u16| MeasurementLayerOutput {
+            phi: line as f32 * 0.25,
+            theta_start: -1.5,
+            theta_end: 1.5,
+            time_stamp_start: 1_000 + line as u64,
+            time_stamp_end: 2_000 + line as u64,
+            data: (0..beams)
+                .map(|beam| Measurement::Filled {
+                    echoes: (0..echoes)
+                        .map(|echo| Echo {
+                            distance: echo_content
+                                .contains(EchoContent::DISTANCE)
+                                .then_some(line * 1_000 + beam * 3 + echo),
+                            rssi: echo_content
+                                .contains(EchoContent::RSSI)
+                                .then_some(beam ^ echo),
+                        })
+                        .collect(),
+                    beam_properties: beam_content
+                        .contains(BeamContent::PROPERTIES)
+                        .then(|| BeamProperties::from_bits_retain(beam as u8)),
+                    azimuth_angle: beam_content
+                        .contains(BeamContent::AZIMUTH)
+                        .then_some(16_000 + beam),
+                })
+                .collect(),
+        };
+        MeasurementModule::new(
+            frame * 10,
+            frame,
+            7,
+            2.0,
+            echo_content,
+            beam_content,
+            (0..lines).map(layer).collect(),
+        )
+    }
+
+    fn decode(telegram: &[u8]) -> CompactMessage {
+        let mut verifier = ChecksumVerifier::new(ChecksumPolicy::Reject);
+        CompactMessage::from_slice(telegram, &mut verifier).unwrap()
+    }
+
+    #[test]
+    fn distance_telegrams_decode_to_themselves() {
+        let echo_flags = [EchoContent::DISTANCE, EchoContent::RSSI, EchoContent::all()];
+        let beam_flags = [
+            BeamContent::empty(),
+            BeamContent::PROPERTIES,
+            BeamContent::AZIMUTH,
+            BeamContent::all(),
+        ];
+        for echo_content in echo_flags {
+            for beam_content in beam_flags {
+                for echoes in 1..=3 {
+                    let modules = [
+                        module(1, 4, 30, echoes, echo_content, beam_content),
+                        module(1, 1, 12, echoes, echo_content, beam_content),
+                        module(1, 16, 3, echoes, echo_content, beam_content),
+                    ];
+                    let mut sizes = 0;
+                    for module in &modules {
+                        let mut bytes = Vec::new();
+                        module.write_to_data(&mut bytes);
+                        assert_eq!(bytes.len(), module.encoded_size());
+                        sizes += module.encoded_size();
+                    }
+                    let message = CompactMessage::distance(CompactHeader::new(5, 6, 4), modules);
+                    let telegram = message.to_bytes();
+                    assert_eq!(telegram.len(), 8 + 24 + sizes + 4);
+                    assert_eq!(decode(&telegram), message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_distance_and_imu_telegrams_decode_to_themselves() {
+        let empty = CompactMessage::distance(CompactHeader::new(1, 2, 4), []);
+        assert_eq!(decode(&empty.to_bytes()), empty);
+
+        let imu = CompactMessage::IMUMessage {
+            imudata: IMUData {
+                telegram_version: 1,
+                acceleration: (0.1, -0.2, 9.81),
+                angular_velocity: (0.01, 0.02, -0.03),
+                orientation: (1.0, 0.0, 0.0, 0.0),
+                time_stamp: 123_456,
+            },
+        };
+        assert_eq!(decode(&imu.to_bytes()), imu);
+        assert_eq!(imu.to_bytes().len(), 8 + 52 + 4);
+    }
+
+    #[test]
+    fn modules_with_single_line_and_no_beams_decode_to_themselves() {
+        let message = CompactMessage::distance(
+            CompactHeader::new(1, 2, 4),
+            [MeasurementModule::new(
+                0,
+                3,
+                1,
+                1.0,
+                EchoContent::all(),
+                BeamContent::all(),
+                smallvec![MeasurementLayerOutput {
+                    phi: 0.0,
+                    theta_start: 0.0,
+                    theta_end: 0.0,
+                    time_stamp_start: 0,
+                    time_stamp_end: 0,
+                    data: Vec::new(),
+                }],
+            )],
+        );
+        assert_eq!(decode(&message.to_bytes()), message);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{error::Error, fmt, io};
+
+/// Everything that can go wrong while decoding a Compact or MSGPACK telegram.
+///
+/// Apart from [`CompactError::Io`] every variant describes a single malformed telegram, so the
+/// caller can report it and move on to the next one.
+#[derive(Debug)]
+pub enum CompactError {
+    BadStartMarker(u32),
+    UnknownCommand(u32),
+    TruncatedTelegram {
+        needed: usize,
+        available: usize,
+    },
+    TruncatedModule {
+        module: usize,
+        size: u32,
+    },
+    OversizedCount {
+        field: &'static str,
+        value: u32,
+        max: u32,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        computed: u32,
+    },
+    /// A module that does not fit the layout of the configured device.
+    UnexpectedLayout {
+        device: &'static str,
+        field: &'static str,
+        value: u32,
+        max: u32,
+    },
+    /// A MSGPACK telegram whose payload is not a well formed scan segment.
+    InvalidMsgpack(String),
+    Io(io::Error),
+}
+
+impl CompactError {
+    pub(crate) fn in_module(self, module: usize, size: u32) -> Self {
+        match self {
+            CompactError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                CompactError::TruncatedModule { module, size }
+            }
+            CompactError::TruncatedTelegram { .. } => {
+                CompactError::TruncatedModule { module, size }
+            }
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactError::BadStartMarker(stx) => {
+                write!(f, "incorrect start of message: {stx:#010x}")
+            }
+            CompactError::UnknownCommand(id) => write!(f, "unknown command id: {id}"),
+            CompactError::TruncatedTelegram { needed, available } => {
+                write!(
+                    f,
+                    "telegram truncated, needed {needed} bytes but got {available}"
+                )
+            }
+            CompactError::TruncatedModule { module, size } => {
+                write!(f, "module {module} truncated, expected {size} bytes")
+            }
+            CompactError::OversizedCount { field, value, max } => {
+                write!(f, "{field} of {value} exceeds the maximum of {max}")
+            }
+            CompactError::ChecksumMismatch { expected, computed } => write!(
+                f,
+                "checksum mismatch, telegram says {expected:#010x} but data gives {computed:#010x}"
+            ),
+            CompactError::UnexpectedLayout {
+                device,
+                field,
+                value,
+                max,
+            } => write!(f, "{field} of {value} exceeds the {max} a {device} sends"),
+            CompactError::InvalidMsgpack(what) => write!(f, "invalid MSGPACK telegram: {what}"),
+            CompactError::Io(e) => write!(f, "io error: {e}"),
+        }
+    }
+}
+
+impl Error for CompactError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompactError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CompactError {
+    fn from(value: io::Error) -> Self {
+        CompactError::Io(value)
+    }
+}
+
+impl From<CompactError> for io::Error {
+    fn from(value: CompactError) -> Self {
+        match value {
+            CompactError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Typed views of the data content bytes of a module and the properties byte of a beam.
+//!
+//! Unknown bits are kept, so re-encoding a decoded module writes back the same bytes.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Which fields every echo of a module carries (`data_content_echoes`).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct EchoContent: u8 {
+        const DISTANCE = 0b1;
+        const RSSI = 0b10;
+    }
+}
+
+bitflags! {
+    /// Which per-beam fields a module carries (`data_content_beams`).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct BeamContent: u8 {
+        const PROPERTIES = 0b1;
+        const AZIMUTH = 0b10;
+    }
+}
+
+bitflags! {
+    /// The properties byte of a beam.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct BeamProperties: u8 {
+        /// The beam hit a reflector.
+        const REFLECTOR = 0b1;
+    }
+}
+
+impl EchoContent {
+    /// Bytes taken by one echo: an optional u16 distance and an optional u16 RSSI.
+    pub fn echo_size(self) -> usize {
+        self.contains(Self::DISTANCE) as usize * 2 + self.contains(Self::RSSI) as usize * 2
+    }
+}
+
+impl BeamContent {
+    /// Bytes taken by the per-beam fields that follow the echoes.
+    pub fn beam_size(self) -> usize {
+        self.contains(Self::PROPERTIES) as usize + self.contains(Self::AZIMUTH) as usize * 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{
+        ChecksumPolicy, ChecksumVerifier, CompactHeader, CompactMessage, Echo, Measurement,
+        MeasurementLayerOutput, MeasurementModule,
+    };
+
+    #[test]
+    fn sizes_follow_the_flags() {
+        assert_eq!(EchoContent::empty().echo_size(), 0);
+        assert_eq!(EchoContent::DISTANCE.echo_size(), 2);
+        assert_eq!(EchoContent::RSSI.echo_size(), 2);
+        assert_eq!(EchoContent::all().echo_size(), 4);
+        assert_eq!(BeamContent::empty().beam_size(), 0);
+        assert_eq!(BeamContent::PROPERTIES.beam_size(), 1);
+        assert_eq!(BeamContent::AZIMUTH.beam_size(), 2);
+        assert_eq!(BeamContent::all().beam_size(), 3);
+    }
+
+    #[test]
+    fn unknown_bits_survive_a_round_trip() {
+        let echoes = EchoContent::from_bits_retain(0b1000_0001);
+        let beams = BeamContent::from_bits_retain(0b0100_0001);
+        let properties = BeamProperties::from_bits_retain(0b1010_0001);
+        assert_eq!(echoes.echo_size(), 2);
+        assert_eq!(beams.beam_size(), 1);
+
+        let module = MeasurementModule::new(
+            0,
+            1,
+            1,
+            1.0,
+            echoes,
+            beams,
+            smallvec![MeasurementLayerOutput {
+                phi: 0.0,
+                theta_start: 0.0,
+                theta_end: 1.0,
+                time_stamp_start: 0,
+                time_stamp_end: 1,
+                data: vec![Measurement::Filled {
+                    echoes: smallvec![Echo {
+                        distance: Some(500),
+                        rssi: None,
+                    }],
+                    beam_properties: Some(properties),
+                    azimuth_angle: None,
+                }],
+            }],
+        );
+        let message = CompactMessage::distance(CompactHeader::new(1, 2, 4), [module]);
+        let mut verifier = ChecksumVerifier::new(ChecksumPolicy::Reject);
+        let decoded = CompactMessage::from_slice(&message.to_bytes(), &mut verifier).unwrap();
+        assert_eq!(decoded, message);
+        let CompactMessage::DistanceMessage { data, .. } = decoded else {
+            unreachable!();
+        };
+        assert_eq!(data[0].data_content_echoes.bits(), 0b1000_0001);
+        assert_eq!(data[0].data_content_beams.bits(), 0b0100_0001);
+    }
+
+    #[test]
+    fn reflectors_are_flagged_by_the_lowest_bit() {
+        assert!(BeamProperties::from_bits_retain(0b11).contains(BeamProperties::REFLECTOR));
+        assert!(!BeamProperties::from_bits_retain(0b10).contains(BeamProperties::REFLECTOR));
+    }
+}
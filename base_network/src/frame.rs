@@ -0,0 +1,565 @@
+use smallvec::SmallVec;
+
+use crate::{
+    check_count, BeamContent, BeamProperties, ChecksumVerifier, CompactError, CompactHeader,
+    CompactMessage, CompactModule, Echo, EchoContent, IMUData, Measurement, MeasurementLayerOutput,
+    MeasurementModule, StandardResult, COMPACT_DISTANCE_COMMAND, COMPACT_IMU_COMMAND, COMPACT_STX,
+    MAX_BEAMS_PER_SCAN, MAX_ECHOES_PER_BEAM, MAX_LINES_IN_MODULE, MAX_MODULES,
+};
+
+pub(crate) const COMPACT_HEADER_SIZE: usize = 24;
+pub(crate) const IMU_DATA_SIZE: usize = 52;
+/// Size of the fixed part of a module, before the per-line metadata arrays.
+pub(crate) const MODULE_FIXED_SIZE: usize = 32;
+/// Bytes of per-line metadata: two u64 timestamps and three f32 angles.
+pub(crate) const MODULE_LINE_META_SIZE: usize = 28;
+/// Scale factor, next module size and the four content bytes.
+pub(crate) const MODULE_TRAILER_SIZE: usize = 12;
+
+pub(crate) fn read_u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+pub(crate) fn read_u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+pub(crate) fn read_u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+pub(crate) fn read_f32_at(data: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Size of the module metadata for `lines` lines, i.e. the offset of the first beam.
+pub(crate) fn module_metadata_size(lines: usize) -> usize {
+    MODULE_FIXED_SIZE + lines * MODULE_LINE_META_SIZE + MODULE_TRAILER_SIZE
+}
+
+/// Pulls `next_module_size` out of the raw bytes of a measurement module.
+pub(crate) fn module_next_size(module: &[u8]) -> Option<u32> {
+    let lines = read_u32_at(module.get(..MODULE_FIXED_SIZE)?, 20) as usize;
+    let offset = MODULE_FIXED_SIZE + lines.checked_mul(MODULE_LINE_META_SIZE)? + 4;
+    module.get(offset..offset + 4).map(|b| read_u32_at(b, 0))
+}
+
+/// Bytes taken by one beam on one line: its echoes, then optional properties and azimuth.
+pub(crate) fn beam_stride(
+    echoes: usize,
+    data_content_echoes: EchoContent,
+    data_content_beams: BeamContent,
+) -> usize {
+    echoes * data_content_echoes.echo_size() + data_content_beams.beam_size()
+}
+
+/// A Compact telegram borrowed straight out of a receive buffer.
+///
+/// All lengths are validated by [`CompactFrameRef::parse`], after which every accessor is a
+/// plain little-endian load from the underlying slice.
+#[derive(Clone, Copy, Debug)]
+pub enum CompactFrameRef<'a> {
+    Distance(DistanceFrameRef<'a>),
+    Imu(IMUData),
+}
+
+impl<'a> CompactFrameRef<'a> {
+    /// Parses a complete telegram, start marker and checksum included.
+    pub fn parse(telegram: &'a [u8]) -> StandardResult<Self> {
+        need(telegram, 12)?;
+        match read_u32_at(telegram, 0) {
+            COMPACT_STX => {}
+            a => return Err(CompactError::BadStartMarker(a)),
+        }
+        let body = &telegram[8..telegram.len() - 4];
+        match read_u32_at(telegram, 4) {
+            COMPACT_DISTANCE_COMMAND => DistanceFrameRef::parse(body).map(Self::Distance),
+            COMPACT_IMU_COMMAND => {
+                need(body, IMU_DATA_SIZE)?;
+                Ok(Self::Imu(IMUData::from_slice(body)))
+            }
+            i => Err(CompactError::UnknownCommand(i)),
+        }
+    }
+
+    /// Runs `verifier` over `telegram` before parsing it.
+    pub fn parse_verified(
+        telegram: &'a [u8],
+        verifier: &mut ChecksumVerifier,
+    ) -> StandardResult<Self> {
+        verifier.verify(telegram)?;
+        Self::parse(telegram)
+    }
+
+    pub fn to_message(&self) -> CompactMessage {
+        match self {
+            CompactFrameRef::Distance(d) => CompactMessage::DistanceMessage {
+                header: d.header,
+                data: Box::new(d.modules().map(|m| m.to_module()).collect()),
+            },
+            CompactFrameRef::Imu(imudata) => CompactMessage::IMUMessage { imudata: *imudata },
+        }
+    }
+}
+
+fn need(data: &[u8], needed: usize) -> StandardResult<()> {
+    if data.len() < needed {
+        return Err(CompactError::TruncatedTelegram {
+            needed,
+            available: data.len(),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DistanceFrameRef<'a> {
+    header: CompactHeader,
+    modules: &'a [u8],
+    module_count: usize,
+}
+
+impl<'a> DistanceFrameRef<'a> {
+    fn parse(body: &'a [u8]) -> StandardResult<Self> {
+        need(body, COMPACT_HEADER_SIZE)?;
+        let header = CompactHeader::from_slice(body);
+        let modules = &body[COMPACT_HEADER_SIZE..];
+        let mut offset = 0;
+        let mut module_count = 0;
+        let mut size = header.next_module_size();
+        while size != 0 {
+            check_count("module_count", module_count as u32 + 1, MAX_MODULES as u32)?;
+            let end = offset + size as usize;
+            if end > modules.len() {
+                return Err(CompactError::TruncatedModule {
+                    module: module_count,
+                    size,
+                });
+            }
+            let module = ModuleRef::parse(&modules[offset..end])
+                .map_err(|e| e.in_module(module_count, size))?;
+            size = module.next_module_size();
+            offset = end;
+            module_count += 1;
+        }
+        Ok(Self {
+            header,
+            modules: &modules[..offset],
+            module_count,
+        })
+    }
+
+    pub fn header(&self) -> &CompactHeader {
+        &self.header
+    }
+
+    pub fn module_count(&self) -> usize {
+        self.module_count
+    }
+
+    pub fn modules(&self) -> ModuleIter<'a> {
+        ModuleIter {
+            remaining: self.modules,
+            size: self.header.next_module_size(),
+        }
+    }
+}
+
+pub struct ModuleIter<'a> {
+    remaining: &'a [u8],
+    size: u32,
+}
+
+impl<'a> Iterator for ModuleIter<'a> {
+    type Item = ModuleRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.size == 0 || self.remaining.is_empty() {
+            return None;
+        }
+        let (module, rest) = self.remaining.split_at(self.size as usize);
+        let module = ModuleRef::parse(module).ok()?;
+        self.size = module.next_module_size();
+        self.remaining = rest;
+        Some(module)
+    }
+}
+
+/// One measurement module. Beams are stored beam-major, every beam holding one record per
+/// line, and each record only contains the fields flagged in the data content bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct ModuleRef<'a> {
+    bytes: &'a [u8],
+    lines: usize,
+    beams: usize,
+    echoes: usize,
+    data_content_echoes: EchoContent,
+    data_content_beams: BeamContent,
+}
+
+impl<'a> ModuleRef<'a> {
+    /// Parses the bytes of one module, checking that every beam it announces is present.
+    pub fn parse(bytes: &'a [u8]) -> StandardResult<Self> {
+        need(bytes, MODULE_FIXED_SIZE)?;
+        let lines = read_u32_at(bytes, 20);
+        let beams = read_u32_at(bytes, 24);
+        let echoes = read_u32_at(bytes, 28);
+        check_count("number_lines_in_module", lines, MAX_LINES_IN_MODULE)?;
+        check_count("number_of_beams_per_scan", beams, MAX_BEAMS_PER_SCAN)?;
+        check_count("number_of_echoes_per_beam", echoes, MAX_ECHOES_PER_BEAM)?;
+        let (lines, beams, echoes) = (lines as usize, beams as usize, echoes as usize);
+        let metadata = module_metadata_size(lines);
+        need(bytes, metadata)?;
+        let mut module = Self {
+            bytes,
+            lines,
+            beams,
+            echoes,
+            data_content_echoes: EchoContent::from_bits_retain(bytes[metadata - 3]),
+            data_content_beams: BeamContent::from_bits_retain(bytes[metadata - 2]),
+        };
+        need(bytes, metadata + lines * beams * module.beam_stride())?;
+        module.bytes = &bytes[..metadata + lines * beams * module.beam_stride()];
+        Ok(module)
+    }
+
+    /// Offset of `line` in the metadata array that starts `array_offset` bytes per line in.
+    fn line_meta(&self, array_offset: usize, size: usize, line: usize) -> usize {
+        MODULE_FIXED_SIZE + self.lines * array_offset + line * size
+    }
+
+    fn trailer(&self) -> usize {
+        MODULE_FIXED_SIZE + self.lines * MODULE_LINE_META_SIZE
+    }
+
+    fn beam_stride(&self) -> usize {
+        beam_stride(
+            self.echoes,
+            self.data_content_echoes,
+            self.data_content_beams,
+        )
+    }
+
+    pub fn segment_counter(&self) -> u64 {
+        read_u64_at(self.bytes, 0)
+    }
+    pub fn frame_number(&self) -> u64 {
+        read_u64_at(self.bytes, 8)
+    }
+    pub fn sender_id(&self) -> u32 {
+        read_u32_at(self.bytes, 16)
+    }
+    pub fn number_lines_in_module(&self) -> u32 {
+        self.lines as u32
+    }
+    pub fn number_of_beams_per_scan(&self) -> u32 {
+        self.beams as u32
+    }
+    pub fn number_of_echoes_per_beam(&self) -> u32 {
+        self.echoes as u32
+    }
+    pub fn time_stamp_start(&self, line: usize) -> u64 {
+        read_u64_at(self.bytes, self.line_meta(0, 8, line))
+    }
+    pub fn time_stamp_end(&self, line: usize) -> u64 {
+        read_u64_at(self.bytes, self.line_meta(8, 8, line))
+    }
+    pub fn phi(&self, line: usize) -> f32 {
+        read_f32_at(self.bytes, self.line_meta(16, 4, line))
+    }
+    pub fn theta_start(&self, line: usize) -> f32 {
+        read_f32_at(self.bytes, self.line_meta(20, 4, line))
+    }
+    pub fn theta_end(&self, line: usize) -> f32 {
+        read_f32_at(self.bytes, self.line_meta(24, 4, line))
+    }
+    pub fn distance_scale_factor(&self) -> f32 {
+        read_f32_at(self.bytes, self.trailer())
+    }
+    pub fn next_module_size(&self) -> u32 {
+        read_u32_at(self.bytes, self.trailer() + 4)
+    }
+    pub fn data_content_echoes(&self) -> EchoContent {
+        self.data_content_echoes
+    }
+    pub fn data_content_beams(&self) -> BeamContent {
+        self.data_content_beams
+    }
+
+    pub fn line(&self, line: usize) -> LineRef<'a> {
+        assert!(line < self.lines, "line {line} out of range");
+        LineRef {
+            module: *self,
+            line,
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = LineRef<'a>> + 'a {
+        let module = *self;
+        (0..self.lines).map(move |line| LineRef { module, line })
+    }
+
+    pub fn beam(&self, beam: usize, line: usize) -> BeamRef<'a> {
+        let stride = self.beam_stride();
+        let offset = module_metadata_size(self.lines) + (beam * self.lines + line) * stride;
+        BeamRef {
+            bytes: &self.bytes[offset..offset + stride],
+            echoes: self.echoes,
+            data_content_echoes: self.data_content_echoes,
+            data_content_beams: self.data_content_beams,
+        }
+    }
+
+    pub fn to_module(&self) -> MeasurementModule {
+        let mut data: SmallVec<[MeasurementLayerOutput; 16]> = SmallVec::new();
+        data.extend(self.lines().map(|l| l.to_layer()));
+        MeasurementModule {
+            segment_counter: self.segment_counter(),
+            frame_number: self.frame_number(),
+            sender_id: self.sender_id(),
+            number_lines_in_module: self.number_lines_in_module(),
+            number_of_beams_per_scan: self.number_of_beams_per_scan(),
+            number_of_echoes_per_beam: self.number_of_echoes_per_beam(),
+            time_stamp_start: (0..self.lines).map(|l| self.time_stamp_start(l)).collect(),
+            time_stamp_end: (0..self.lines).map(|l| self.time_stamp_end(l)).collect(),
+            phi: (0..self.lines).map(|l| self.phi(l)).collect(),
+            theta_start: (0..self.lines).map(|l| self.theta_start(l)).collect(),
+            theta_end: (0..self.lines).map(|l| self.theta_end(l)).collect(),
+            distance_scale_factor: self.distance_scale_factor(),
+            next_module_size: self.next_module_size(),
+            data_content_echoes: self.data_content_echoes,
+            data_content_beams: self.data_content_beams,
+            data,
+        }
+    }
+}
+
+/// One scan line of a module together with its metadata.
+#[derive(Clone, Copy, Debug)]
+pub struct LineRef<'a> {
+    module: ModuleRef<'a>,
+    line: usize,
+}
+
+impl<'a> LineRef<'a> {
+    pub fn index(&self) -> usize {
+        self.line
+    }
+    pub fn time_stamp_start(&self) -> u64 {
+        self.module.time_stamp_start(self.line)
+    }
+    pub fn time_stamp_end(&self) -> u64 {
+        self.module.time_stamp_end(self.line)
+    }
+    pub fn phi(&self) -> f32 {
+        self.module.phi(self.line)
+    }
+    pub fn theta_start(&self) -> f32 {
+        self.module.theta_start(self.line)
+    }
+    pub fn theta_end(&self) -> f32 {
+        self.module.theta_end(self.line)
+    }
+
+    pub fn beams(&self) -> impl Iterator<Item = BeamRef<'a>> + 'a {
+        let (module, line) = (self.module, self.line);
+        (0..module.beams).map(move |beam| module.beam(beam, line))
+    }
+
+    pub fn to_layer(&self) -> MeasurementLayerOutput {
+        MeasurementLayerOutput {
+            phi: self.phi(),
+            theta_start: self.theta_start(),
+            theta_end: self.theta_end(),
+            time_stamp_start: self.time_stamp_start(),
+            time_stamp_end: self.time_stamp_end(),
+            data: self.beams().map(|b| b.to_measurement()).collect(),
+        }
+    }
+}
+
+/// The record of a single beam on a single line.
+#[derive(Clone, Copy, Debug)]
+pub struct BeamRef<'a> {
+    bytes: &'a [u8],
+    echoes: usize,
+    data_content_echoes: EchoContent,
+    data_content_beams: BeamContent,
+}
+
+impl BeamRef<'_> {
+    fn echo_size(&self) -> usize {
+        self.data_content_echoes.echo_size()
+    }
+
+    pub fn echo_count(&self) -> usize {
+        self.echoes
+    }
+
+    pub fn echo(&self, echo: usize) -> Echo {
+        let mut offset = echo * self.echo_size();
+        let distance = self
+            .data_content_echoes
+            .contains(EchoContent::DISTANCE)
+            .then(|| {
+                offset += 2;
+                read_u16_at(self.bytes, offset - 2)
+            });
+        let rssi = self
+            .data_content_echoes
+            .contains(EchoContent::RSSI)
+            .then(|| read_u16_at(self.bytes, offset));
+        Echo { distance, rssi }
+    }
+
+    pub fn echoes(&self) -> impl Iterator<Item = Echo> + '_ {
+        (0..self.echoes).map(|e| self.echo(e))
+    }
+
+    pub fn beam_properties(&self) -> Option<BeamProperties> {
+        self.data_content_beams
+            .contains(BeamContent::PROPERTIES)
+            .then(|| BeamProperties::from_bits_retain(self.bytes[self.echoes * self.echo_size()]))
+    }
+
+    pub fn azimuth_angle(&self) -> Option<u16> {
+        let properties = self.data_content_beams.contains(BeamContent::PROPERTIES);
+        let offset = self.echoes * self.echo_size() + properties as usize;
+        self.data_content_beams
+            .contains(BeamContent::AZIMUTH)
+            .then(|| read_u16_at(self.bytes, offset))
+    }
+
+    pub fn to_measurement(&self) -> Measurement {
+        Measurement::Filled {
+            echoes: self.echoes().collect(),
+            beam_properties: self.beam_properties(),
+            azimuth_angle: self.azimuth_angle(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{checksum::compact_crc, ToBytes};
+
+    fn module(echoes: EchoContent, beams: BeamContent) -> MeasurementModule {
+        let layer = |line: u16| MeasurementLayerOutput {
+            phi: line as f32,
+            theta_start: -1.0,
+            theta_end: 1.0,
+            time_stamp_start: 10,
+            time_stamp_end: 20,
+            data: (0..5)
+                .map(|beam| Measurement::Filled {
+                    echoes: (0..2)
+                        .map(|echo| Echo {
+                            distance: Some(line * 100 + beam * 10 + echo),
+                            rssi: Some(beam + echo),
+                        })
+                        .collect(),
+                    beam_properties: Some(BeamProperties::REFLECTOR),
+                    azimuth_angle: Some(16384 + beam),
+                })
+                .collect(),
+        };
+        MeasurementModule::new(0, 1, 1, 1.0, echoes, beams, smallvec![layer(0), layer(1)])
+    }
+
+    fn telegram(modules: impl IntoIterator<Item = MeasurementModule>) -> Vec<u8> {
+        CompactMessage::distance(CompactHeader::new(1, 2, 4), modules).to_bytes()
+    }
+
+    #[test]
+    fn beams_follow_the_content_flags() {
+        let echo_flags = [
+            EchoContent::empty(),
+            EchoContent::DISTANCE,
+            EchoContent::RSSI,
+            EchoContent::all(),
+        ];
+        let beam_flags = [
+            BeamContent::empty(),
+            BeamContent::PROPERTIES,
+            BeamContent::AZIMUTH,
+            BeamContent::all(),
+        ];
+        for echoes in echo_flags {
+            for beams in beam_flags {
+                let module = module(echoes, beams);
+                let bytes = telegram([module.clone(), module]);
+                let CompactFrameRef::Distance(frame) = CompactFrameRef::parse(&bytes).unwrap()
+                else {
+                    unreachable!();
+                };
+                assert_eq!(frame.module_count(), 2);
+                for module in frame.modules() {
+                    assert_eq!(module.data_content_echoes(), echoes);
+                    assert_eq!(module.data_content_beams(), beams);
+                    for line in module.lines() {
+                        let l = line.index() as u16;
+                        assert_eq!(line.phi(), l as f32);
+                        for (b, beam) in line.beams().enumerate() {
+                            let b = b as u16;
+                            for (e, echo) in beam.echoes().enumerate() {
+                                let e = e as u16;
+                                let distance = echoes
+                                    .contains(EchoContent::DISTANCE)
+                                    .then_some(l * 100 + b * 10 + e);
+                                let rssi = echoes.contains(EchoContent::RSSI).then_some(b + e);
+                                assert_eq!(echo, Echo { distance, rssi });
+                            }
+                            assert_eq!(
+                                beam.beam_properties(),
+                                beams
+                                    .contains(BeamContent::PROPERTIES)
+                                    .then_some(BeamProperties::REFLECTOR)
+                            );
+                            assert_eq!(
+                                beam.azimuth_angle(),
+                                beams.contains(BeamContent::AZIMUTH).then_some(16384 + b)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_modules_are_reported() {
+        let module = module(EchoContent::all(), BeamContent::all());
+        let size = module.encoded_size() as u32;
+        let bytes = telegram([module.clone(), module]);
+        let cut = bytes.len() - 4 - 3;
+        let mut truncated = bytes[..cut].to_vec();
+        compact_crc(&truncated[4..]).write_to_data(&mut truncated);
+        assert!(matches!(
+            CompactFrameRef::parse(&truncated),
+            Err(CompactError::TruncatedModule { module: 1, size: s }) if s == size
+        ));
+    }
+
+    #[test]
+    fn module_count_is_limited() {
+        let empty = MeasurementModule::new(
+            0,
+            1,
+            1,
+            1.0,
+            EchoContent::all(),
+            BeamContent::empty(),
+            SmallVec::new(),
+        );
+        let bytes = telegram(std::iter::repeat_n(empty.clone(), MAX_MODULES));
+        assert!(CompactFrameRef::parse(&bytes).is_ok());
+        let bytes = telegram(std::iter::repeat_n(empty, MAX_MODULES + 1));
+        assert!(matches!(
+            CompactFrameRef::parse(&bytes),
+            Err(CompactError::OversizedCount {
+                field: "module_count",
+                ..
+            })
+        ));
+    }
+}
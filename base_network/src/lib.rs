@@ -1,56 +1,68 @@
-use core::panic;
-use std::{future::Future, io, ops::DerefMut, time::Duration};
+use std::future::Future;
 
-use array2d::Array2D;
 use smallvec::SmallVec;
-use tokio::{
-    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
-    net::{TcpStream, UdpSocket},
-    time::sleep,
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+
+pub mod assembler;
+pub mod buffers;
+pub mod capture;
+pub mod checksum;
+pub mod clock;
+pub mod codec;
+pub mod deskew;
+pub mod encode;
+pub mod error;
+pub mod flags;
+pub mod frame;
+pub mod msgpack;
+pub mod points;
+pub mod profile;
+pub mod receiver;
+pub mod record;
+pub mod replay;
+pub mod stats;
+pub mod transport;
+
+pub use assembler::{AssembledFrame, AssemblerConfig, FrameAssembler};
+pub use buffers::{LineMeta, LineView, ModuleBuffers, ScanBuffers};
+pub use capture::{Capture, CaptureFilter, CapturedPacket, Transport};
+pub use checksum::{ChecksumPolicy, ChecksumVerifier};
+pub use clock::ClockSync;
+pub use codec::CompactCodec;
+pub use deskew::Deskewer;
+pub use encode::ToBytes;
+pub use error::CompactError;
+pub use flags::{BeamContent, BeamProperties, EchoContent};
+pub use frame::{BeamRef, CompactFrameRef, DistanceFrameRef, LineRef, ModuleRef};
+pub use msgpack::ScanDataFormat;
+pub use points::{EchoPolicy, PointConverter, ScanPoint};
+pub use profile::{Device, DeviceProfile, Elevation};
+pub use receiver::{Binding, Datagram, SensorKey, SensorStream, UdpReceiver};
+pub use record::{Record, Recorder, RecorderHandle, Replayer};
+pub use replay::{Pacing, Recorded};
+pub use stats::{ArrivalTime, ReplayClock, StreamReport, StreamStatistics};
+pub use transport::{ByteTransport, DatagramReader, DatagramSource};
+
+use frame::{
+    module_metadata_size, module_next_size, read_f32_at, read_u32_at, read_u64_at,
+    COMPACT_HEADER_SIZE, IMU_DATA_SIZE, MODULE_FIXED_SIZE,
 };
 
-pub async fn read_sized_message(
-    stream: &mut TcpStream, /* (impl AsyncBufRead + AsyncRead + AsyncReadExt + Unpin + DerefMut) */
+/// Reads exactly `length` bytes.
+pub async fn read_sized_message<R: AsyncRead + Unpin>(
+    stream: &mut R,
     length: usize,
 ) -> Result<Vec<u8>, std::io::Error> {
-    let mut filled: usize = 0;
-    let mut data: Vec<u8> = Vec::new();
-    loop {
-        //let buf = BufReader::new(stream);
-
-        // stream.poll_read();
-        // stream.poll_read();
-        let _ = stream.readable().await;
-        match stream.try_read(&mut data[filled..length]) {
-            Ok(l) if l >= length - filled => break,
-            Ok(l) => {
-                filled += l;
-            }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                sleep(Duration::from_millis(1)).await;
-            }
-            Err(e) => return Err(e),
-        };
-    }
+    let mut data = vec![0; length];
+    stream.read_exact(&mut data).await?;
     Ok(data)
 }
 
-pub async fn read_const_sized<const N: usize>(stream: &mut TcpStream) -> StandardResult<[u8; N]> {
-    let mut filled: usize = 0;
+pub async fn read_const_sized<const N: usize, R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> StandardResult<[u8; N]> {
     let mut data: [u8; N] = [0_u8; N];
-    loop {
-        let _ = stream.readable().await;
-        match stream.try_read(&mut data[filled..N]) {
-            Ok(l) if l >= N - filled => break,
-            Ok(l) => {
-                filled += l;
-            }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                sleep(Duration::from_millis(1)).await;
-            }
-            Err(e) => return Err(e),
-        };
-    }
+    stream.read_exact(&mut data).await?;
     Ok(data)
 }
 
@@ -67,7 +79,25 @@ where
     // todo!()
 }
 
-pub type StandardResult<T> = Result<T, std::io::Error>;
+pub type StandardResult<T> = Result<T, CompactError>;
+
+pub const COMPACT_STX: u32 = 0x02020202;
+pub const COMPACT_DISTANCE_COMMAND: u32 = 0x1;
+pub const COMPACT_IMU_COMMAND: u32 = 0x2;
+const MAX_MODULES: usize = 100;
+const MAX_MODULE_SIZE: u32 = 1 << 20;
+
+async fn read_into<T: AsyncRead + Unpin + Send>(
+    buffer: &mut BufReader<T>,
+    out: &mut Vec<u8>,
+    length: usize,
+) -> StandardResult<()> {
+    let start = out.len();
+    out.resize(start + length, 0);
+    buffer.read_exact(&mut out[start..]).await?;
+    Ok(())
+}
+
 // pub type StandardAsyncResult<T> = impl Future<Output = StandardResult<T>> + Send;
 
 pub trait FromStream {
@@ -84,6 +114,7 @@ pub trait CompactModule {
     fn next_module_size(&self) -> u32;
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum CompactMessage {
     IMUMessage {
         imudata: IMUData,
@@ -94,63 +125,87 @@ pub enum CompactMessage {
     },
 }
 impl CompactMessage {
+    /// Reads one telegram with the default [`ChecksumPolicy`]. The verifier is thrown away
+    /// after every call, use [`CompactMessage::read_message_verified`] to keep its counts.
     pub async fn read_message<T: AsyncReadExt + Unpin + Send>(
         buffer: &mut BufReader<T>,
     ) -> StandardResult<CompactMessage> {
-        // println!("reading");
-        // let d = buffer.read_u8().await?;
-        // dbg!(d);
-        // dbg!(buffer);
-        match u32::from_stream(buffer).await? {
-            0x02020202 => {}
-            a => panic!("Incorrect start of message! {a}"),
-        }
-        // dbg!("stx");
-        // let _ = u32::from_stream(buffer).await?;
-        let msg = match u32::from_stream(buffer).await? {
-            0x1 => CompactMessage::read_distance_data(buffer).await?,
-            0x2 => CompactMessage::read_imu_data(buffer).await?,
-            i => {
-                let j = i.to_le_bytes();
-                panic!("Invalid msg type!: {i}, {j:?}")
-            }
-        };
-        // let msg = CompactMessage::read_distance_data(buffer).await?;
-
-        // dbg!("msg");
-        let _checksum = u32::from_stream(buffer).await?;
-        Ok(msg)
+        Self::read_message_verified(buffer, &mut ChecksumVerifier::default()).await
     }
 
-    async fn read_imu_data<R: AsyncRead + Unpin + Send>(
-        stream: &mut BufReader<R>,
+    /// Reads one telegram, checks its CRC32 according to `verifier` and decodes it.
+    pub async fn read_message_verified<T: AsyncReadExt + Unpin + Send>(
+        buffer: &mut BufReader<T>,
+        verifier: &mut ChecksumVerifier,
     ) -> StandardResult<CompactMessage> {
-        Ok(CompactMessage::IMUMessage {
-            imudata: IMUData::from_stream(stream).await?,
-        })
+        let telegram = Self::read_raw_telegram(buffer).await?;
+        Self::from_slice(&telegram, verifier)
     }
 
-    async fn read_distance_data<R: AsyncRead + Unpin + Send>(
-        stream: &mut BufReader<R>,
+    /// Decodes a telegram that is already in memory, such as a UDP datagram. Both the
+    /// Compact and the MSGPACK format are accepted.
+    pub fn from_slice(
+        telegram: &[u8],
+        verifier: &mut ChecksumVerifier,
     ) -> StandardResult<CompactMessage> {
-        let header = CompactHeader::from_stream(stream).await?;
-        let mut data: SmallVec<[MeasurementModule; 4]> = SmallVec::new();
-        let mut next_module_size = header.next_module_size();
-        let mut max_num_modules = 100;
-        while next_module_size != 0 && max_num_modules >= 0 {
-            let next = MeasurementModule::from_stream(stream).await?;
-            next_module_size = next.next_module_size();
-            data.push(next);
-            max_num_modules -= 1;
+        if ScanDataFormat::detect(telegram) == Some(ScanDataFormat::Msgpack) {
+            verifier.verify(telegram)?;
+            return msgpack::decode_msgpack(telegram);
         }
-        Ok(CompactMessage::DistanceMessage {
-            header,
-            data: Box::new(data),
-        })
+        Ok(CompactFrameRef::parse_verified(telegram, verifier)?.to_message())
+    }
+
+    /// Reads the bytes of one complete telegram, start marker and checksum included, by
+    /// following the module size chain. MSGPACK telegrams are read by their payload length.
+    pub async fn read_raw_telegram<T: AsyncReadExt + Unpin + Send>(
+        buffer: &mut BufReader<T>,
+    ) -> StandardResult<Vec<u8>> {
+        let mut telegram = Vec::with_capacity(16384);
+        match u32::from_stream(buffer).await? {
+            COMPACT_STX => {}
+            a => return Err(CompactError::BadStartMarker(a)),
+        }
+        telegram.extend_from_slice(&COMPACT_STX.to_le_bytes());
+        let command = u32::from_stream(buffer).await?;
+        telegram.extend_from_slice(&command.to_le_bytes());
+        match command {
+            COMPACT_DISTANCE_COMMAND => {
+                read_into(buffer, &mut telegram, COMPACT_HEADER_SIZE).await?;
+                let mut next_module_size = read_u32_at(&telegram, telegram.len() - 4);
+                let mut module = 0;
+                while next_module_size != 0 {
+                    check_count("module_count", module as u32 + 1, MAX_MODULES as u32)?;
+                    check_count("module_size", next_module_size, MAX_MODULE_SIZE)?;
+                    let start = telegram.len();
+                    read_into(buffer, &mut telegram, next_module_size as usize)
+                        .await
+                        .map_err(|e| e.in_module(module, next_module_size))?;
+                    next_module_size = module_next_size(&telegram[start..]).ok_or(
+                        CompactError::TruncatedModule {
+                            module,
+                            size: next_module_size,
+                        },
+                    )?;
+                    module += 1;
+                }
+            }
+            COMPACT_IMU_COMMAND => read_into(buffer, &mut telegram, IMU_DATA_SIZE).await?,
+            len @ 1..=msgpack::MSGPACK_MAX_PAYLOAD => {
+                // Only commit to reading the payload once it starts like one.
+                read_into(buffer, &mut telegram, 1).await?;
+                if !msgpack::is_map_marker(telegram[8]) {
+                    return Err(CompactError::UnknownCommand(len));
+                }
+                read_into(buffer, &mut telegram, len as usize - 1).await?;
+            }
+            i => return Err(CompactError::UnknownCommand(i)),
+        }
+        read_into(buffer, &mut telegram, 4).await?;
+        Ok(telegram)
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 // 0x02020202
 pub struct CompactHeader {
     pub telegram_counter: u64,
@@ -179,6 +234,26 @@ impl FromStream for CompactHeader {
         })
     }
 }
+impl CompactHeader {
+    /// The size of the first module is filled in by [`CompactMessage::distance`].
+    pub fn new(telegram_counter: u64, timestamp: u64, telegram_version: u32) -> Self {
+        Self {
+            telegram_counter,
+            timestamp,
+            telegram_version,
+            next_module_size: 0,
+        }
+    }
+
+    pub(crate) fn from_slice(data: &[u8]) -> Self {
+        Self {
+            telegram_counter: read_u64_at(data, 0),
+            timestamp: read_u64_at(data, 8),
+            telegram_version: read_u32_at(data, 16),
+            next_module_size: read_u32_at(data, 20),
+        }
+    }
+}
 impl CompactModule for CompactHeader {
     fn next_module_size(&self) -> u32 {
         self.next_module_size
@@ -186,7 +261,7 @@ impl CompactModule for CompactHeader {
 }
 //Followed by size of module
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct MeasurementModule {
     pub segment_counter: u64,
     pub frame_number: u64,
@@ -202,8 +277,8 @@ pub struct MeasurementModule {
     pub distance_scale_factor: f32,
     next_module_size: u32,
     //res u8,
-    pub data_content_echoes: u8,
-    pub data_content_beams: u8,
+    pub data_content_echoes: EchoContent,
+    pub data_content_beams: BeamContent,
     //res u8
     pub data: SmallVec<[MeasurementLayerOutput; 16]>,
 }
@@ -214,60 +289,86 @@ impl FromStream for MeasurementModule {
     where
         Self: Sized + Send,
     {
-        let segment_counter = u64::from_stream(stream).await?;
-        let frame_number = u64::from_stream(stream).await?;
-        let sender_id = u32::from_stream(stream).await?;
-        let number_lines_in_module = u32::from_stream(stream).await?;
-        let number_of_beams_per_scan = u32::from_stream(stream).await?;
-        let number_of_echoes_per_beam = u32::from_stream(stream).await?;
-        let time_stamp_start: Vec<u64> =
-            read_num_to_vec(stream, number_lines_in_module as usize).await?;
-        let time_stamp_end: Vec<u64> =
-            read_num_to_vec(stream, number_lines_in_module as usize).await?;
-        let phi: Vec<f32> = read_num_to_vec(stream, number_lines_in_module as usize).await?;
-        let theta_start: Vec<f32> =
-            read_num_to_vec(stream, number_lines_in_module as usize).await?;
-        let theta_end: Vec<f32> = read_num_to_vec(stream, number_lines_in_module as usize).await?;
-        let distance_scale_factor = f32::from_stream(stream).await?;
-        let next_module_size = u32::from_stream(stream).await?;
-        let _res = u8::from_stream(stream).await?;
-        let data_content_echoes = u8::from_stream(stream).await?;
-        let data_content_beams = u8::from_stream(stream).await?;
-        let _res = u8::from_stream(stream).await?;
-        let data = decode_data(
+        let mut module = Vec::with_capacity(4096);
+        read_into(stream, &mut module, MODULE_FIXED_SIZE).await?;
+        let lines = read_u32_at(&module, 20);
+        check_count("number_lines_in_module", lines, MAX_LINES_IN_MODULE)?;
+        read_into(
             stream,
-            number_lines_in_module,
-            number_of_beams_per_scan,
-            number_of_echoes_per_beam,
-            data_content_echoes,
-            data_content_beams,
-            (
-                time_stamp_start.clone(),
-                time_stamp_end.clone(),
-                phi.clone(),
-                theta_start.clone(),
-                theta_end.clone(),
-            ),
+            &mut module,
+            module_metadata_size(lines as usize) - MODULE_FIXED_SIZE,
         )
         .await?;
-        Ok(Self {
+        let metadata = module.len();
+        let echoes = read_u32_at(&module, 28) as usize;
+        let beams = read_u32_at(&module, 24) as usize;
+        check_count("number_of_beams_per_scan", beams as u32, MAX_BEAMS_PER_SCAN)?;
+        check_count(
+            "number_of_echoes_per_beam",
+            echoes as u32,
+            MAX_ECHOES_PER_BEAM,
+        )?;
+        let stride = frame::beam_stride(
+            echoes,
+            EchoContent::from_bits_retain(module[metadata - 3]),
+            BeamContent::from_bits_retain(module[metadata - 2]),
+        );
+        read_into(stream, &mut module, lines as usize * beams * stride).await?;
+        Ok(ModuleRef::parse(&module)?.to_module())
+    }
+}
+
+pub const MAX_LINES_IN_MODULE: u32 = 64;
+pub const MAX_BEAMS_PER_SCAN: u32 = 8192;
+pub const MAX_ECHOES_PER_BEAM: u32 = 3;
+
+fn check_count(field: &'static str, value: u32, max: u32) -> StandardResult<()> {
+    if value > max {
+        return Err(CompactError::OversizedCount { field, value, max });
+    }
+    Ok(())
+}
+
+impl MeasurementModule {
+    /// Builds a module whose per-line metadata and counts are taken from `data`. The size of
+    /// the following module is filled in by [`CompactMessage::distance`].
+    pub fn new(
+        segment_counter: u64,
+        frame_number: u64,
+        sender_id: u32,
+        distance_scale_factor: f32,
+        data_content_echoes: EchoContent,
+        data_content_beams: BeamContent,
+        data: SmallVec<[MeasurementLayerOutput; 16]>,
+    ) -> Self {
+        let beams = data.iter().map(|l| l.data.len()).max().unwrap_or(0);
+        let echoes = data
+            .iter()
+            .flat_map(|l| l.data.iter())
+            .map(|m| match m {
+                Measurement::Empty => 0,
+                Measurement::Filled { echoes, .. } => echoes.len(),
+            })
+            .max()
+            .unwrap_or(0);
+        Self {
             segment_counter,
             frame_number,
             sender_id,
-            number_lines_in_module,
-            number_of_beams_per_scan,
-            number_of_echoes_per_beam,
-            time_stamp_start,
-            time_stamp_end,
-            phi,
-            theta_start,
-            theta_end,
+            number_lines_in_module: data.len() as u32,
+            number_of_beams_per_scan: beams as u32,
+            number_of_echoes_per_beam: echoes as u32,
+            time_stamp_start: data.iter().map(|l| l.time_stamp_start).collect(),
+            time_stamp_end: data.iter().map(|l| l.time_stamp_end).collect(),
+            phi: data.iter().map(|l| l.phi).collect(),
+            theta_start: data.iter().map(|l| l.theta_start).collect(),
+            theta_end: data.iter().map(|l| l.theta_end).collect(),
             distance_scale_factor,
-            next_module_size,
+            next_module_size: 0,
             data_content_echoes,
             data_content_beams,
             data,
-        })
+        }
     }
 }
 
@@ -276,16 +377,16 @@ impl CompactModule for MeasurementModule {
         self.next_module_size
     }
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Measurement {
     Empty,
     Filled {
         echoes: SmallVec<[Echo; 3]>,
-        beam_properties: Option<u8>,
+        beam_properties: Option<BeamProperties>,
         azimuth_angle: Option<u16>,
     },
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct MeasurementLayerOutput {
     pub phi: f32,
     pub theta_start: f32,
@@ -294,93 +395,13 @@ pub struct MeasurementLayerOutput {
     pub time_stamp_end: u64,
     pub data: Vec<Measurement>,
 }
-impl Measurement {
-    async fn from_stream<R: AsyncRead + Unpin + Send>(
-        stream: &mut BufReader<R>,
-        number_of_echoes_per_beam: u32,
-        data_content_beams: u8,
-        data_content_echoes: u8,
-    ) -> StandardResult<Self> {
-        let mut echoes: SmallVec<[Echo; 3]> = SmallVec::new();
-        for i in 0..number_of_echoes_per_beam {
-            let mut distance = Some(u16::from_stream(stream).await?);
-            let mut rssi = Some(u16::from_stream(stream).await?);
-            if data_content_echoes & 0b1 == 0 {
-                distance = None;
-            }
-            if data_content_echoes & 0b10 == 0 {
-                rssi = None
-            }
-            echoes.push(Echo { distance, rssi })
-        }
-        let mut beam_properties = Some(u8::from_stream(stream).await?);
-        if data_content_beams & 0b1 == 0 {
-            beam_properties = None;
-        }
-        let mut azimuth_angle = Some(u16::from_stream(stream).await?);
-        if data_content_beams & 0b10 == 0 {
-            azimuth_angle = None;
-        }
-
-        Ok(Measurement::Filled {
-            echoes,
-            beam_properties,
-            azimuth_angle,
-        })
-    }
-}
-async fn decode_data<R: AsyncRead + Unpin + Send>(
-    stream: &mut BufReader<R>,
-    number_lines_in_module: u32,
-    number_of_beams_per_scan: u32,
-    number_of_echoes_per_beam: u32,
-    data_content_echoes: u8,
-    data_content_beams: u8,
-    mut layer_info: (Vec<u64>, Vec<u64>, Vec<f32>, Vec<f32>, Vec<f32>),
-) -> StandardResult<SmallVec<[MeasurementLayerOutput; 16]>> {
-    let mut out: SmallVec<[MeasurementLayerOutput; 16]> = SmallVec::new();
-    //column major
-    let mut array = Array2D::filled_with(
-        Measurement::Empty,
-        number_lines_in_module as usize,
-        number_of_beams_per_scan as usize,
-    );
-    for i in 0..number_of_beams_per_scan {
-        for j in 0..number_lines_in_module {
-            // dbg!(i, j,);
-            array[(j as usize, i as usize)] = Measurement::from_stream(
-                stream,
-                number_of_echoes_per_beam,
-                data_content_beams,
-                data_content_echoes,
-            )
-            .await?;
-        }
-    }
-    let mut rows = array.as_rows();
-    // let mut rows = array.as_columns();
-    rows.reverse();
-    // dbg!("{:?}", &rows);
-    for i in 0..number_lines_in_module {
-        out.push(MeasurementLayerOutput {
-            time_stamp_start: layer_info.0.pop().unwrap(),
-            time_stamp_end: layer_info.1.pop().unwrap(),
-            phi: layer_info.2.pop().unwrap(),
-            theta_start: layer_info.3.pop().unwrap(),
-            theta_end: layer_info.4.pop().unwrap(),
-            data: rows[i as usize].clone(), /* (number_lines_in_module - i - 1) */
-        });
-    }
-
-    Ok(out)
-}
-
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Echo {
     pub distance: Option<u16>,
     pub rssi: Option<u16>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct IMUData {
     pub telegram_version: u32,
     pub acceleration: (f32, f32, f32),
@@ -388,6 +409,18 @@ pub struct IMUData {
     pub orientation: (f32, f32, f32, f32),
     pub time_stamp: u64,
 }
+impl IMUData {
+    pub(crate) fn from_slice(data: &[u8]) -> Self {
+        let f = |i: usize| read_f32_at(data, 4 + i * 4);
+        Self {
+            telegram_version: read_u32_at(data, 0),
+            acceleration: (f(0), f(1), f(2)),
+            angular_velocity: (f(3), f(4), f(5)),
+            orientation: (f(6), f(7), f(8), f(9)),
+            time_stamp: read_u64_at(data, 44),
+        }
+    }
+}
 impl FromStream for IMUData {
     async fn from_stream<R: AsyncRead + Unpin + Send>(
         stream: &mut BufReader<R>,
@@ -430,7 +463,7 @@ impl FromStream for u8 {
     where
         Self: Sized + Send,
     {
-        stream.read_u8().await
+        Ok(stream.read_u8().await?)
     }
 }
 impl FromStream for u16 {
@@ -440,7 +473,7 @@ impl FromStream for u16 {
     where
         Self: Sized + Send,
     {
-        stream.read_u16_le().await
+        Ok(stream.read_u16_le().await?)
     }
 }
 impl FromStream for u32 {
@@ -450,7 +483,7 @@ impl FromStream for u32 {
     where
         Self: Sized + Send,
     {
-        stream.read_u32_le().await
+        Ok(stream.read_u32_le().await?)
     }
 }
 impl FromStream for u64 {
@@ -460,7 +493,7 @@ impl FromStream for u64 {
     where
         Self: Sized + Send,
     {
-        stream.read_u64_le().await
+        Ok(stream.read_u64_le().await?)
     }
 }
 impl FromStream for f32 {
@@ -470,7 +503,7 @@ impl FromStream for f32 {
     where
         Self: Sized + Send,
     {
-        stream.read_f32_le().await
+        Ok(stream.read_f32_le().await?)
     }
 }
 impl FromStream for f64 {
@@ -480,6 +513,96 @@ impl FromStream for f64 {
     where
         Self: Sized + Send,
     {
-        stream.read_f64_le().await
+        Ok(stream.read_f64_le().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+
+    fn telegram() -> Vec<u8> {
+        let layer = MeasurementLayerOutput {
+            phi: 0.1,
+            theta_start: -0.5,
+            theta_end: 0.5,
+            time_stamp_start: 1,
+            time_stamp_end: 2,
+            data: (0..8)
+                .map(|beam| Measurement::Filled {
+                    echoes: smallvec![Echo {
+                        distance: Some(beam),
+                        rssi: Some(beam),
+                    }],
+                    beam_properties: None,
+                    azimuth_angle: None,
+                })
+                .collect(),
+        };
+        let module = MeasurementModule::new(
+            0,
+            1,
+            1,
+            1.0,
+            EchoContent::all(),
+            BeamContent::empty(),
+            smallvec![layer.clone(), layer],
+        );
+        CompactMessage::distance(CompactHeader::new(1, 2, 4), [module.clone(), module]).to_bytes()
+    }
+
+    async fn read(bytes: &[u8]) -> StandardResult<CompactMessage> {
+        CompactMessage::read_message(&mut BufReader::new(bytes)).await
+    }
+
+    fn parse(bytes: &[u8]) -> StandardResult<CompactMessage> {
+        CompactMessage::from_slice(bytes, &mut ChecksumVerifier::new(ChecksumPolicy::Ignore))
+    }
+
+    #[tokio::test]
+    async fn every_truncation_is_an_error() {
+        let telegram = telegram();
+        assert!(read(&telegram).await.is_ok());
+        for length in 0..telegram.len() {
+            assert!(read(&telegram[..length]).await.is_err(), "{length} bytes");
+            assert!(parse(&telegram[..length]).is_err(), "{length} bytes");
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_counts_are_rejected() {
+        let mut telegram = telegram();
+        telegram[28..32].copy_from_slice(&(MAX_MODULE_SIZE + 1).to_le_bytes());
+        assert!(matches!(
+            read(&telegram).await,
+            Err(CompactError::OversizedCount {
+                field: "module_size",
+                ..
+            })
+        ));
+
+        let mut telegram = self::telegram();
+        telegram[52..56].copy_from_slice(&(MAX_LINES_IN_MODULE + 1).to_le_bytes());
+        assert!(read(&telegram).await.is_err());
+        assert!(matches!(
+            parse(&telegram),
+            Err(CompactError::OversizedCount {
+                field: "number_lines_in_module",
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_are_rejected_before_their_payload() {
+        let mut bytes = COMPACT_STX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&1000_u32.to_le_bytes());
+        bytes.push(0x00);
+        assert!(matches!(
+            read(&bytes).await,
+            Err(CompactError::UnknownCommand(1000))
+        ));
     }
 }
@@ -0,0 +1,484 @@
+//! Decoder for the MSGPACK scan data format of the multiScan and picoScan.
+//!
+//! A telegram is the usual `02 02 02 02` marker, a little-endian u32 payload length, the
+//! MSGPACK payload and a CRC32 of the payload. The payload is a `ScanSegment` whose
+//! `SegmentData` holds one `Scan` per line:
+//!
+//! ```text
+//! { class: "ScanSegment", data: {
+//!     TelegramCounter, TimestampTransmit, SegmentCounter, FrameNumber, SenderId,
+//!     SegmentData: [ { class: "Scan", data: {
+//!         TimestampStart, TimestampStop, ThetaStart, ThetaStop, BeamCount, EchoCount,
+//!         ChannelPhi, ChannelTheta, DistValues: [..], RssiValues: [..], PropertiesValues: [..],
+//!     } } ],
+//! } }
+//! ```
+//!
+//! Every channel is `{ class, data: { numOfElems, elemSz, endian, elemTypes, data } }` with
+//! the values packed into a binary blob. Segments decode into the same [`MeasurementModule`]
+//! the Compact decoder produces.
+
+use rmpv::Value;
+use smallvec::SmallVec;
+
+use crate::{
+    check_count, BeamContent, BeamProperties, CompactError, CompactHeader, CompactMessage, Echo,
+    EchoContent, Measurement, MeasurementLayerOutput, MeasurementModule, StandardResult,
+    COMPACT_DISTANCE_COMMAND, COMPACT_IMU_COMMAND, COMPACT_STX, MAX_BEAMS_PER_SCAN,
+};
+
+pub const MSGPACK_MAX_PAYLOAD: u32 = 1 << 20;
+
+/// Whether `byte` starts a MSGPACK map, which every payload does.
+pub(crate) fn is_map_marker(byte: u8) -> bool {
+    matches!(byte, 0x80..=0x8f | 0xde | 0xdf)
+}
+
+/// Compact encodes per-beam azimuths as `(value - AZIMUTH_OFFSET) / AZIMUTH_SCALE` radians.
+pub const AZIMUTH_OFFSET: f32 = 16384.0;
+pub const AZIMUTH_SCALE: f32 = 5215.0;
+
+pub fn azimuth_to_radians(azimuth: u16) -> f32 {
+    (azimuth as f32 - AZIMUTH_OFFSET) / AZIMUTH_SCALE
+}
+
+pub fn radians_to_azimuth(angle: f32) -> u16 {
+    (angle * AZIMUTH_SCALE + AZIMUTH_OFFSET)
+        .round()
+        .clamp(0.0, u16::MAX as f32) as u16
+}
+
+/// Wire format of a scan data telegram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanDataFormat {
+    Compact,
+    Msgpack,
+}
+
+impl ScanDataFormat {
+    /// Both formats start with the same marker. Compact follows it with a command id of 1 or
+    /// 2 where MSGPACK has the payload length, which must account for the whole telegram.
+    pub fn detect(telegram: &[u8]) -> Option<Self> {
+        if telegram.len() < 12 || telegram[..4] != COMPACT_STX.to_le_bytes() {
+            return None;
+        }
+        match u32::from_le_bytes(telegram[4..8].try_into().unwrap()) {
+            COMPACT_DISTANCE_COMMAND | COMPACT_IMU_COMMAND => Some(Self::Compact),
+            len if len as usize + 12 == telegram.len() => Some(Self::Msgpack),
+            _ => Some(Self::Compact),
+        }
+    }
+}
+
+/// What a MSGPACK channel carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MsgpackChannel {
+    /// Distance in millimetres of the given echo.
+    Distance(usize),
+    Rssi(usize),
+    Properties,
+    /// Per-beam azimuth in radians.
+    Theta,
+    /// Elevation of the line in radians.
+    Phi,
+}
+
+impl MsgpackChannel {
+    fn key(&self) -> &'static str {
+        match self {
+            MsgpackChannel::Distance(_) => "DistValues",
+            MsgpackChannel::Rssi(_) => "RssiValues",
+            MsgpackChannel::Properties => "PropertiesValues",
+            MsgpackChannel::Theta => "ChannelTheta",
+            MsgpackChannel::Phi => "ChannelPhi",
+        }
+    }
+}
+
+fn invalid(what: impl Into<String>) -> CompactError {
+    CompactError::InvalidMsgpack(what.into())
+}
+
+/// Looks `key` up in a MSGPACK map.
+fn get<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn require<'a>(map: &'a Value, key: &str) -> StandardResult<&'a Value> {
+    get(map, key).ok_or_else(|| invalid(format!("missing key {key}")))
+}
+
+fn require_u64(map: &Value, key: &str) -> StandardResult<u64> {
+    require(map, key)?
+        .as_u64()
+        .ok_or_else(|| invalid(format!("{key} is not an unsigned integer")))
+}
+
+fn require_f32(map: &Value, key: &str) -> StandardResult<f32> {
+    require(map, key)?
+        .as_f64()
+        .map(|v| v as f32)
+        .ok_or_else(|| invalid(format!("{key} is not a number")))
+}
+
+/// Unwraps the `{ class, data }` envelope every MSGPACK object comes in.
+fn data_of<'a>(object: &'a Value, class: &str) -> StandardResult<&'a Value> {
+    match get(object, "class").and_then(|c| c.as_str()) {
+        Some(c) if c.starts_with(class) => require(object, "data"),
+        Some(c) => Err(invalid(format!("expected a {class}, got {c}"))),
+        None => Err(invalid(format!("{class} without a class"))),
+    }
+}
+
+/// The decoded values of one channel.
+struct ChannelValues(Vec<f64>);
+
+impl ChannelValues {
+    fn read(channel: &Value, tag: MsgpackChannel) -> StandardResult<Self> {
+        let data = data_of(channel, "Channel").map_err(|_| invalid(format!("bad {tag:?}")))?;
+        let count = require_u64(data, "numOfElems")? as usize;
+        let size = require_u64(data, "elemSz")? as usize;
+        let big_endian = get(data, "endian").and_then(|e| e.as_str()) == Some("big");
+        let kind = match require(data, "elemTypes")? {
+            Value::Array(types) => types.first().and_then(|t| t.as_str()),
+            t => t.as_str(),
+        }
+        .ok_or_else(|| invalid(format!("{tag:?} has no element type")))?;
+        let blob = match require(data, "data")? {
+            Value::Binary(b) => b.as_slice(),
+            _ => return Err(invalid(format!("{tag:?} data is not binary"))),
+        };
+        let expected = match kind {
+            "uint8" | "int8" => 1,
+            "uint16" | "int16" => 2,
+            "uint32" | "int32" | "float32" => 4,
+            "uint64" | "float64" => 8,
+            _ => 0,
+        };
+        if size != expected {
+            return Err(invalid(format!(
+                "{tag:?} has unsupported type {kind}/{size}"
+            )));
+        }
+        if count
+            .checked_mul(size)
+            .is_none_or(|needed| needed > blob.len())
+        {
+            return Err(invalid(format!(
+                "{tag:?} announces {count} elements of {size} bytes in {} bytes",
+                blob.len()
+            )));
+        }
+        let values = blob
+            .chunks_exact(size)
+            .take(count)
+            .map(|raw| {
+                let mut bytes = [0_u8; 8];
+                bytes[..size].copy_from_slice(raw);
+                if big_endian {
+                    bytes[..size].reverse();
+                }
+                match kind {
+                    "float32" => f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+                    "float64" => f64::from_le_bytes(bytes),
+                    "int8" => bytes[0] as i8 as f64,
+                    "int16" => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    "int32" => i32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+                    _ => u64::from_le_bytes(bytes) as f64,
+                }
+            })
+            .collect();
+        Ok(Self(values))
+    }
+
+    /// Reads the channel list stored under the key of `tag`, one channel per echo.
+    fn read_echoes(scan: &Value, tag: fn(usize) -> MsgpackChannel) -> StandardResult<Vec<Self>> {
+        match get(scan, tag(0).key()) {
+            Some(Value::Array(channels)) => channels
+                .iter()
+                .enumerate()
+                .map(|(i, c)| Self::read(c, tag(i)))
+                .collect(),
+            Some(_) => Err(invalid(format!("{} is not a list", tag(0).key()))),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn read_optional(scan: &Value, tag: MsgpackChannel) -> StandardResult<Option<Self>> {
+        match get(scan, tag.key()) {
+            Some(Value::Array(channels)) if channels.is_empty() => Ok(None),
+            Some(Value::Array(channels)) => Self::read(&channels[0], tag).map(Some),
+            Some(channel) => Self::read(channel, tag).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// One `Scan` entry, i.e. one line of the segment.
+struct ScanLine {
+    layer: MeasurementLayerOutput,
+    distances: Vec<ChannelValues>,
+    rssi: Vec<ChannelValues>,
+    properties: Option<ChannelValues>,
+    theta: Option<ChannelValues>,
+    beams: usize,
+}
+
+impl ScanLine {
+    fn read(scan: &Value) -> StandardResult<Self> {
+        let data = data_of(scan, "Scan")?;
+        let phi = match ChannelValues::read_optional(data, MsgpackChannel::Phi)? {
+            Some(c) => c.0.first().copied().unwrap_or(0.0) as f32,
+            None => 0.0,
+        };
+        let distances = ChannelValues::read_echoes(data, MsgpackChannel::Distance)?;
+        let rssi = ChannelValues::read_echoes(data, MsgpackChannel::Rssi)?;
+        let properties = ChannelValues::read_optional(data, MsgpackChannel::Properties)?;
+        let theta = ChannelValues::read_optional(data, MsgpackChannel::Theta)?;
+        let beams = match get(data, "BeamCount").and_then(|b| b.as_u64()) {
+            Some(b) => {
+                check_count(
+                    "BeamCount",
+                    b.try_into().unwrap_or(u32::MAX),
+                    MAX_BEAMS_PER_SCAN,
+                )?;
+                b as usize
+            }
+            None => distances.first().map(|d| d.0.len()).unwrap_or(0),
+        };
+        Ok(Self {
+            layer: MeasurementLayerOutput {
+                phi,
+                theta_start: require_f32(data, "ThetaStart")?,
+                theta_end: require_f32(data, "ThetaStop")?,
+                time_stamp_start: require_u64(data, "TimestampStart")?,
+                time_stamp_end: require_u64(data, "TimestampStop")?,
+                data: Vec::new(),
+            },
+            distances,
+            rssi,
+            properties,
+            theta,
+            beams,
+        })
+    }
+
+    fn echoes(&self) -> usize {
+        self.distances.len().max(self.rssi.len())
+    }
+
+    fn max_distance(&self) -> f64 {
+        self.distances
+            .iter()
+            .flat_map(|c| c.0.iter())
+            .fold(0.0, |a, b| b.max(a))
+    }
+
+    fn into_layer(mut self, echoes: usize, scale: f64) -> MeasurementLayerOutput {
+        let value =
+            |c: &[ChannelValues], e: usize, b: usize| c.get(e).and_then(|c| c.0.get(b)).copied();
+        self.layer.data = (0..self.beams)
+            .map(|b| Measurement::Filled {
+                echoes: (0..echoes)
+                    .map(|e| Echo {
+                        distance: value(&self.distances, e, b)
+                            .map(|d| (d / scale).round().clamp(0.0, u16::MAX as f64) as u16),
+                        rssi: value(&self.rssi, e, b).map(|r| r.clamp(0.0, u16::MAX as f64) as u16),
+                    })
+                    .collect(),
+                beam_properties: self
+                    .properties
+                    .as_ref()
+                    .and_then(|p| p.0.get(b))
+                    .map(|p| BeamProperties::from_bits_retain(*p as u8)),
+                azimuth_angle: self
+                    .theta
+                    .as_ref()
+                    .and_then(|t| t.0.get(b))
+                    .map(|t| radians_to_azimuth(*t as f32)),
+            })
+            .collect();
+        self.layer
+    }
+}
+
+/// Decodes a complete MSGPACK telegram into the same message the Compact decoder returns.
+/// The checksum is not checked here, [`CompactMessage::from_slice`] does that.
+pub fn decode_msgpack(telegram: &[u8]) -> StandardResult<CompactMessage> {
+    if ScanDataFormat::detect(telegram) != Some(ScanDataFormat::Msgpack) {
+        return Err(invalid("not a MSGPACK telegram"));
+    }
+    let mut payload = &telegram[8..telegram.len() - 4];
+    let root = rmpv::decode::read_value(&mut payload).map_err(|e| invalid(e.to_string()))?;
+    let segment = data_of(&root, "ScanSegment")?;
+
+    let scans = match require(segment, "SegmentData")? {
+        Value::Array(scans) => scans
+            .iter()
+            .map(ScanLine::read)
+            .collect::<StandardResult<Vec<ScanLine>>>()?,
+        _ => return Err(invalid("SegmentData is not a list")),
+    };
+    let echoes = scans.iter().map(|s| s.echoes()).max().unwrap_or(0);
+    // Compact distances are u16 multiples of the scale factor, pick the finest scale that
+    // still fits the longest distance of this segment.
+    let max_distance = scans.iter().map(|s| s.max_distance()).fold(0.0, f64::max);
+    let scale = (max_distance / u16::MAX as f64).ceil().max(1.0);
+    let has = |f: fn(&ScanLine) -> bool| scans.iter().any(f);
+    let mut data_content_echoes = EchoContent::empty();
+    data_content_echoes.set(EchoContent::DISTANCE, has(|s| !s.distances.is_empty()));
+    data_content_echoes.set(EchoContent::RSSI, has(|s| !s.rssi.is_empty()));
+    let mut data_content_beams = BeamContent::empty();
+    data_content_beams.set(BeamContent::PROPERTIES, has(|s| s.properties.is_some()));
+    data_content_beams.set(BeamContent::AZIMUTH, has(|s| s.theta.is_some()));
+    let layers: SmallVec<[MeasurementLayerOutput; 16]> = scans
+        .into_iter()
+        .map(|s| s.into_layer(echoes, scale))
+        .collect();
+
+    let module = MeasurementModule::new(
+        require_u64(segment, "SegmentCounter")?,
+        require_u64(segment, "FrameNumber")?,
+        require_u64(segment, "SenderId")? as u32,
+        scale as f32,
+        data_content_echoes,
+        data_content_beams,
+        layers,
+    );
+    let header = CompactHeader::new(
+        get(segment, "TelegramCounter")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0),
+        get(segment, "TimestampTransmit")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0),
+        0,
+    );
+    Ok(CompactMessage::distance(header, [module]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::compact_crc;
+
+    fn object(class: &str, data: Vec<(&str, Value)>) -> Value {
+        Value::Map(vec![
+            (Value::from("class"), Value::from(class)),
+            (
+                Value::from("data"),
+                Value::Map(data.into_iter().map(|(k, v)| (Value::from(k), v)).collect()),
+            ),
+        ])
+    }
+
+    fn channel(kind: &str, count: u64, size: u64, blob: Vec<u8>) -> Value {
+        object(
+            "ChannelUint16",
+            vec![
+                ("numOfElems", Value::from(count)),
+                ("elemSz", Value::from(size)),
+                ("endian", Value::from("little")),
+                ("elemTypes", Value::Array(vec![Value::from(kind)])),
+                ("data", Value::Binary(blob)),
+            ],
+        )
+    }
+
+    fn telegram(distances: Value) -> Vec<u8> {
+        let scan = object(
+            "Scan",
+            vec![
+                ("TimestampStart", Value::from(100)),
+                ("TimestampStop", Value::from(200)),
+                ("ThetaStart", Value::F32(-1.0)),
+                ("ThetaStop", Value::F32(1.0)),
+                ("DistValues", Value::Array(vec![distances])),
+            ],
+        );
+        let segment = object(
+            "ScanSegment",
+            vec![
+                ("TelegramCounter", Value::from(1)),
+                ("TimestampTransmit", Value::from(2)),
+                ("SegmentCounter", Value::from(3)),
+                ("FrameNumber", Value::from(4)),
+                ("SenderId", Value::from(5)),
+                ("SegmentData", Value::Array(vec![scan])),
+            ],
+        );
+        let mut payload = Vec::new();
+        rmpv::encode::write_value(&mut payload, &segment).unwrap();
+        let mut telegram = COMPACT_STX.to_le_bytes().to_vec();
+        telegram.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        telegram.extend_from_slice(&payload);
+        telegram.extend_from_slice(&compact_crc(&payload).to_le_bytes());
+        telegram
+    }
+
+    fn is_invalid(telegram: &[u8]) -> bool {
+        matches!(
+            decode_msgpack(telegram),
+            Err(CompactError::InvalidMsgpack(_))
+        )
+    }
+
+    #[test]
+    fn a_well_formed_segment_decodes() {
+        let blob = [1000_u16, 2000, 3000]
+            .iter()
+            .flat_map(|d| d.to_le_bytes())
+            .collect();
+        let msg = decode_msgpack(&telegram(channel("uint16", 3, 2, blob))).unwrap();
+        let CompactMessage::DistanceMessage { data, .. } = msg else {
+            unreachable!();
+        };
+        assert_eq!((data[0].frame_number, data[0].sender_id), (4, 5));
+        let distances: Vec<Option<u16>> = data[0].data[0]
+            .data
+            .iter()
+            .map(|m| match m {
+                Measurement::Filled { echoes, .. } => echoes[0].distance,
+                Measurement::Empty => None,
+            })
+            .collect();
+        assert_eq!(distances, [Some(1000), Some(2000), Some(3000)]);
+    }
+
+    #[test]
+    fn zero_sized_elements_are_rejected() {
+        assert!(is_invalid(&telegram(channel("uint16", 3, 0, vec![0; 6]))));
+    }
+
+    #[test]
+    fn sizes_that_do_not_match_the_type_are_rejected() {
+        assert!(is_invalid(&telegram(channel("uint16", 3, 4, vec![0; 12]))));
+        assert!(is_invalid(&telegram(channel("uint16", 1, 9, vec![0; 9]))));
+        assert!(is_invalid(&telegram(channel("complex", 1, 2, vec![0; 2]))));
+    }
+
+    #[test]
+    fn overflowing_element_counts_are_rejected() {
+        assert!(is_invalid(&telegram(channel(
+            "uint64",
+            u64::MAX,
+            8,
+            vec![0; 8]
+        ))));
+        assert!(is_invalid(&telegram(channel(
+            "uint16",
+            u64::MAX / 2 + 1,
+            2,
+            vec![0; 8]
+        ))));
+    }
+
+    #[test]
+    fn short_blobs_are_rejected() {
+        assert!(is_invalid(&telegram(channel("uint16", 4, 2, vec![0; 7]))));
+        assert!(is_invalid(&telegram(channel("float32", 1, 4, Vec::new()))));
+    }
+}
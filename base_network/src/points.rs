@@ -0,0 +1,400 @@
+//! Conversion of measurements into Cartesian points.
+//!
+//! Follows the sensor coordinate system of the multiScan and picoScan: `phi` is the
+//! elevation of a line and `theta` the azimuth of a beam, both in radians, giving
+//!
+//! ```text
+//! x = r * cos(phi) * cos(theta)
+//! y = r * cos(phi) * sin(theta)
+//! z = r * sin(phi)
+//! ```
+//!
+//! Distances are scaled by `distance_scale_factor`, so coordinates are in millimetres. picoScans
+//! scan a single plane whatever their `phi`, which [`PointConverter::device`] accounts for.
+
+use smallvec::SmallVec;
+
+use crate::{
+    msgpack::azimuth_to_radians, profile::Device, BeamProperties, Echo, Measurement,
+    MeasurementLayerOutput, MeasurementModule,
+};
+
+/// A distance of zero means the beam got no return.
+fn has_distance(echo: &Echo) -> bool {
+    echo.distance.is_some_and(|d| d > 0)
+}
+
+/// Which echoes of a multi-echo beam become points.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EchoPolicy {
+    /// The nearest return.
+    #[default]
+    First,
+    /// The furthest return, which looks through rain, dust and vegetation.
+    Last,
+    /// The return with the highest RSSI.
+    Strongest,
+    /// Every echo as a separate point. Echoes without a distance count as skipped.
+    All,
+    /// Every echo that has a distance, the others are dropped without being counted.
+    DropMissing,
+}
+
+impl EchoPolicy {
+    /// Indices of the selected echoes and the number of points skipped for lack of a
+    /// distance. A beam without any distance is one skipped point for the single echo
+    /// policies.
+    pub fn select(&self, echoes: &[Echo]) -> (SmallVec<[usize; 3]>, usize) {
+        let mut valid = echoes
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| has_distance(e))
+            .map(|(i, _)| i);
+        let single = match self {
+            EchoPolicy::First => valid.next(),
+            EchoPolicy::Last => valid.next_back(),
+            EchoPolicy::Strongest => valid.max_by_key(|i| echoes[*i].rssi.unwrap_or(0)),
+            EchoPolicy::All => {
+                let selected: SmallVec<[usize; 3]> = valid.collect();
+                let skipped = echoes.len() - selected.len();
+                return (selected, skipped);
+            }
+            EchoPolicy::DropMissing => return (valid.collect(), 0),
+        };
+        match single {
+            Some(i) => (SmallVec::from_slice(&[i]), 0),
+            None => (SmallVec::new(), 1),
+        }
+    }
+}
+
+/// Converts measurements to points with an [`EchoPolicy`], counting what it skips.
+#[derive(Clone, Debug, Default)]
+pub struct PointConverter {
+    pub policy: EchoPolicy,
+    /// Decides how the `phi` of a line is read.
+    pub device: Device,
+    pub points: u64,
+    pub skipped: u64,
+}
+
+impl PointConverter {
+    pub fn new(policy: EchoPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Keeps every echo that has a distance, as the `points` helpers do.
+    fn for_device(device: Device) -> Self {
+        Self {
+            policy: EchoPolicy::DropMissing,
+            device,
+            ..Default::default()
+        }
+    }
+
+    /// Appends the points of one line to `out`.
+    pub fn convert_layer(
+        &mut self,
+        data: &MeasurementLayerOutput,
+        layer: usize,
+        distance_scale_factor: f32,
+        out: &mut Vec<ScanPoint>,
+    ) {
+        let phi = self.device.profile().elevation(data.phi);
+        for (beam, m) in data.data.iter().enumerate() {
+            let Measurement::Filled { echoes, .. } = m else {
+                continue;
+            };
+            let (selected, skipped) = self.policy.select(echoes);
+            self.skipped += skipped as u64;
+            self.points += selected.len() as u64;
+            out.extend(
+                selected
+                    .into_iter()
+                    .filter_map(|echo| data.point(layer, phi, distance_scale_factor, beam, echo)),
+            );
+        }
+    }
+
+    /// Appends the points of every line of `module` to `out`.
+    pub fn convert_module(&mut self, module: &MeasurementModule, out: &mut Vec<ScanPoint>) {
+        for (layer, data) in module.data.iter().enumerate() {
+            self.convert_layer(data, layer, module.distance_scale_factor, out);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanPoint {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    /// RSSI of the echo, `None` when the telegram carries no RSSI.
+    pub intensity: Option<u16>,
+    /// Index of the line within its module.
+    pub layer: usize,
+    pub beam: usize,
+    pub echo: usize,
+    /// Interpolated time the beam was measured, in µs of the sensor clock.
+    pub time_stamp: u64,
+    /// `None` when the module carries no beam properties.
+    pub properties: Option<BeamProperties>,
+}
+
+impl ScanPoint {
+    pub fn is_reflector(&self) -> bool {
+        self.properties
+            .is_some_and(|p| p.contains(BeamProperties::REFLECTOR))
+    }
+
+    pub fn distance(&self) -> f32 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+}
+
+impl MeasurementLayerOutput {
+    /// Azimuth of `beam` in radians, taken from the beam itself when it carries one and
+    /// interpolated between `theta_start` and `theta_end` otherwise.
+    pub fn beam_azimuth(&self, beam: usize) -> f32 {
+        if let Some(Measurement::Filled {
+            azimuth_angle: Some(azimuth),
+            ..
+        }) = self.data.get(beam)
+        {
+            return azimuth_to_radians(*azimuth);
+        }
+        let steps = self.data.len().saturating_sub(1).max(1) as f32;
+        self.theta_start + (self.theta_end - self.theta_start) * beam as f32 / steps
+    }
+
+    /// Time `beam` was measured, interpolated between the line's start and end timestamps.
+    pub fn beam_time_stamp(&self, beam: usize) -> u64 {
+        let steps = self.data.len().saturating_sub(1).max(1) as u64;
+        let span = self.time_stamp_end.saturating_sub(self.time_stamp_start);
+        self.time_stamp_start + span * beam as u64 / steps
+    }
+
+    fn point(
+        &self,
+        layer: usize,
+        phi: f32,
+        distance_scale_factor: f32,
+        beam: usize,
+        echo: usize,
+    ) -> Option<ScanPoint> {
+        let (e, properties) = match self.data.get(beam)? {
+            Measurement::Filled {
+                echoes,
+                beam_properties,
+                ..
+            } => (*echoes.get(echo)?, *beam_properties),
+            Measurement::Empty => return None,
+        };
+        let r = e.distance.unwrap_or(0) as f32 * distance_scale_factor;
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = self.beam_azimuth(beam).sin_cos();
+        Some(ScanPoint {
+            x: r * cos_phi * cos_theta,
+            y: r * cos_phi * sin_theta,
+            z: r * sin_phi,
+            intensity: e.rssi,
+            layer,
+            beam,
+            echo,
+            time_stamp: self.beam_time_stamp(beam),
+            properties,
+        })
+    }
+
+    /// Every echo with a distance as a point, placed as `device` measures it. `layer` is
+    /// the index stored in the points and `distance_scale_factor` comes from the module.
+    pub fn points(
+        &self,
+        layer: usize,
+        distance_scale_factor: f32,
+        device: Device,
+    ) -> impl Iterator<Item = ScanPoint> {
+        let mut out = Vec::new();
+        PointConverter::for_device(device).convert_layer(
+            self,
+            layer,
+            distance_scale_factor,
+            &mut out,
+        );
+        out.into_iter()
+    }
+}
+
+impl MeasurementModule {
+    /// Points of every line in this module as `device` measures them, line by line.
+    pub fn points(&self, device: Device) -> impl Iterator<Item = ScanPoint> {
+        let mut out = Vec::new();
+        PointConverter::for_device(device).convert_module(self, &mut out);
+        out.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{BeamContent, EchoContent};
+
+    fn echo(distance: u16, rssi: u16) -> Echo {
+        Echo {
+            distance: Some(distance),
+            rssi: Some(rssi),
+        }
+    }
+
+    fn line(phi: f32, beams: Vec<Measurement>) -> MeasurementLayerOutput {
+        MeasurementLayerOutput {
+            phi,
+            theta_start: 0.0,
+            theta_end: std::f32::consts::FRAC_PI_2,
+            time_stamp_start: 1_000,
+            time_stamp_end: 1_200,
+            data: beams,
+        }
+    }
+
+    fn filled(echoes: &[Echo]) -> Measurement {
+        Measurement::Filled {
+            echoes: SmallVec::from_slice(echoes),
+            beam_properties: None,
+            azimuth_angle: None,
+        }
+    }
+
+    fn module(phi: f32) -> MeasurementModule {
+        let beams = (0..3).map(|_| filled(&[echo(1000, 5)])).collect();
+        MeasurementModule::new(
+            0,
+            1,
+            1,
+            2.0,
+            EchoContent::all(),
+            BeamContent::empty(),
+            smallvec![line(0.0, Vec::new()), line(phi, beams)],
+        )
+    }
+
+    fn selected(policy: EchoPolicy, echoes: &[Echo]) -> (Vec<usize>, usize) {
+        let (selected, skipped) = policy.select(echoes);
+        (selected.to_vec(), skipped)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-2
+    }
+
+    #[test]
+    fn multiscan_points_use_phi_as_elevation() {
+        let phi = 0.3_f32;
+        let points: Vec<ScanPoint> = module(phi).points(Device::MultiScan136).collect();
+        assert_eq!(points.len(), 3);
+        let (first, last) = (points[0], points[2]);
+        assert_eq!((first.layer, first.beam, first.echo), (1, 0, 0));
+        assert!(close(first.x, 2000.0 * phi.cos()));
+        assert!(close(first.y, 0.0));
+        assert!(close(first.z, 2000.0 * phi.sin()));
+        assert!(close(last.x, 0.0));
+        assert!(close(last.y, 2000.0 * phi.cos()));
+        assert_eq!(
+            (first.time_stamp, points[1].time_stamp, last.time_stamp),
+            (1_000, 1_100, 1_200)
+        );
+        assert_eq!(first.intensity, Some(5));
+    }
+
+    #[test]
+    fn picoscan_points_stay_in_the_scan_plane() {
+        for device in [Device::PicoScan100, Device::PicoScan150] {
+            let points: Vec<ScanPoint> = module(0.3).points(device).collect();
+            assert_eq!(points.len(), 3);
+            assert!(points.iter().all(|p| p.z == 0.0));
+            assert!(points.iter().all(|p| close(p.distance(), 2000.0)));
+        }
+    }
+
+    #[test]
+    fn layer_points_match_module_points() {
+        let module = module(0.1);
+        let from_layer: Vec<ScanPoint> = module.data[1]
+            .points(1, module.distance_scale_factor, Device::PicoScan150)
+            .collect();
+        let from_module: Vec<ScanPoint> = module.points(Device::PicoScan150).collect();
+        assert_eq!(from_layer, from_module);
+    }
+
+    #[test]
+    fn single_echo_policies_pick_one_echo_with_a_distance() {
+        let echoes = [echo(0, 90), echo(1500, 10), echo(3000, 40)];
+        assert_eq!(selected(EchoPolicy::First, &echoes), (vec![1], 0));
+        assert_eq!(selected(EchoPolicy::Last, &echoes), (vec![2], 0));
+        assert_eq!(selected(EchoPolicy::Strongest, &echoes), (vec![2], 0));
+
+        let missing = [
+            Echo {
+                distance: None,
+                rssi: Some(99),
+            },
+            echo(2000, 1),
+        ];
+        assert_eq!(selected(EchoPolicy::First, &missing), (vec![1], 0));
+        assert_eq!(selected(EchoPolicy::Strongest, &missing), (vec![1], 0));
+    }
+
+    #[test]
+    fn a_beam_without_any_distance_is_one_skipped_point() {
+        let none = [
+            echo(0, 10),
+            Echo {
+                distance: None,
+                rssi: None,
+            },
+        ];
+        for policy in [EchoPolicy::First, EchoPolicy::Last, EchoPolicy::Strongest] {
+            assert_eq!(selected(policy, &none), (vec![], 1));
+        }
+        assert_eq!(selected(EchoPolicy::All, &none), (vec![], 2));
+        assert_eq!(selected(EchoPolicy::DropMissing, &none), (vec![], 0));
+        assert_eq!(selected(EchoPolicy::First, &[]), (vec![], 1));
+    }
+
+    #[test]
+    fn multi_echo_policies_keep_every_echo_with_a_distance() {
+        let echoes = [echo(1000, 1), echo(0, 2), echo(3000, 3)];
+        assert_eq!(selected(EchoPolicy::All, &echoes), (vec![0, 2], 1));
+        assert_eq!(selected(EchoPolicy::DropMissing, &echoes), (vec![0, 2], 0));
+    }
+
+    #[test]
+    fn the_converter_counts_points_and_skips() {
+        let beams = vec![
+            filled(&[echo(1000, 1), echo(0, 2)]),
+            Measurement::Empty,
+            filled(&[echo(0, 1), echo(0, 2)]),
+        ];
+        let line = line(0.0, beams);
+        let mut out = Vec::new();
+        let mut converter = PointConverter::new(EchoPolicy::All);
+        converter.convert_layer(&line, 0, 1.0, &mut out);
+        assert_eq!((converter.points, converter.skipped), (1, 3));
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].beam, out[0].echo), (0, 0));
+    }
+
+    #[test]
+    fn points_of_empty_or_missing_beams_are_none() {
+        let line = line(0.0, vec![Measurement::Empty, filled(&[echo(10, 0)])]);
+        assert_eq!(line.point(0, 0.0, 1.0, 0, 0), None);
+        assert_eq!(line.point(0, 0.0, 1.0, 1, 1), None);
+        assert_eq!(line.point(0, 0.0, 1.0, 2, 0), None);
+        assert!(line.point(0, 0.0, 1.0, 1, 0).is_some());
+    }
+}
@@ -0,0 +1,310 @@
+//! What the Compact telegrams of each supported sensor look like.
+//!
+//! The decoder itself accepts any layout within the format limits. A [`DeviceProfile`]
+//! narrows that down to what one sensor family actually sends, so a telegram from the wrong
+//! sensor or a misconfigured port is reported instead of turning into odd points.
+
+use crate::{
+    assembler::MULTISCAN_SEGMENTS_PER_FRAME, checksum::ChecksumVerifier, AssemblerConfig,
+    CompactError, CompactMessage, MeasurementModule, StandardResult,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Device {
+    #[default]
+    MultiScan136,
+    PicoScan100,
+    PicoScan150,
+}
+
+/// How the elevation of a line is found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Elevation {
+    /// `phi` of the line is its elevation.
+    FromPhi,
+    /// Every line lies in one plane, whatever `phi` says.
+    Fixed(f32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceProfile {
+    pub name: &'static str,
+    pub elevation: Elevation,
+    /// Segments one frame is sent in. `None` when that depends on the sensor configuration,
+    /// frames then end when the frame number rolls over.
+    pub segments_per_frame: Option<u32>,
+    pub max_lines_per_module: u32,
+    pub max_beams_per_line: u32,
+    pub max_echoes: u32,
+    /// UDP port the Compact data is sent to out of the box.
+    pub data_port: u16,
+}
+
+const MULTISCAN136: DeviceProfile = DeviceProfile {
+    name: "multiScan136",
+    elevation: Elevation::FromPhi,
+    segments_per_frame: Some(MULTISCAN_SEGMENTS_PER_FRAME),
+    max_lines_per_module: 16,
+    // 360° at the 0.125° of the high resolution layers.
+    max_beams_per_line: 2880,
+    max_echoes: 3,
+    data_port: 2115,
+};
+
+const PICOSCAN100: DeviceProfile = DeviceProfile {
+    name: "picoScan100",
+    elevation: Elevation::Fixed(0.0),
+    // The scan can be split into a configurable number of segments.
+    segments_per_frame: None,
+    max_lines_per_module: 1,
+    // 276° at 0.1°.
+    max_beams_per_line: 2761,
+    max_echoes: 3,
+    data_port: 2115,
+};
+
+const PICOSCAN150: DeviceProfile = DeviceProfile {
+    name: "picoScan150",
+    // 276° at 0.05°.
+    max_beams_per_line: 5521,
+    ..PICOSCAN100
+};
+
+impl Device {
+    pub const ALL: [Device; 3] = [
+        Device::MultiScan136,
+        Device::PicoScan100,
+        Device::PicoScan150,
+    ];
+
+    pub fn profile(self) -> &'static DeviceProfile {
+        match self {
+            Device::MultiScan136 => &MULTISCAN136,
+            Device::PicoScan100 => &PICOSCAN100,
+            Device::PicoScan150 => &PICOSCAN150,
+        }
+    }
+
+    /// Looks a device up by its profile name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Device> {
+        Self::ALL
+            .into_iter()
+            .find(|d| d.profile().name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl DeviceProfile {
+    /// Elevation in radians of a line reporting `phi`.
+    pub fn elevation(&self, phi: f32) -> f32 {
+        match self.elevation {
+            Elevation::FromPhi => phi,
+            Elevation::Fixed(elevation) => elevation,
+        }
+    }
+
+    /// An [`AssemblerConfig`] that groups the segments of this sensor into frames.
+    pub fn assembler_config(&self) -> AssemblerConfig {
+        AssemblerConfig {
+            segments_per_frame: self.segments_per_frame,
+            ..AssemblerConfig::default()
+        }
+    }
+
+    pub fn check_module(&self, module: &MeasurementModule) -> StandardResult<()> {
+        let beams = module.data.iter().map(|l| l.data.len()).max().unwrap_or(0) as u32;
+        [
+            (
+                "number_lines_in_module",
+                module.data.len() as u32,
+                self.max_lines_per_module,
+            ),
+            ("number_of_beams_per_scan", beams, self.max_beams_per_line),
+            (
+                "number_of_echoes_per_beam",
+                module.number_of_echoes_per_beam,
+                self.max_echoes,
+            ),
+        ]
+        .into_iter()
+        .try_for_each(|(field, value, max)| match value > max {
+            true => Err(CompactError::UnexpectedLayout {
+                device: self.name,
+                field,
+                value,
+                max,
+            }),
+            false => Ok(()),
+        })
+    }
+
+    /// Checks every module of a distance telegram. IMU telegrams always pass.
+    pub fn check(&self, msg: &CompactMessage) -> StandardResult<()> {
+        match msg {
+            CompactMessage::DistanceMessage { data, .. } => {
+                data.iter().try_for_each(|m| self.check_module(m))
+            }
+            CompactMessage::IMUMessage { .. } => Ok(()),
+        }
+    }
+
+    /// [`CompactMessage::from_slice`] followed by [`DeviceProfile::check`].
+    pub fn decode(
+        &self,
+        telegram: &[u8],
+        verifier: &mut ChecksumVerifier,
+    ) -> StandardResult<CompactMessage> {
+        let msg = CompactMessage::from_slice(telegram, verifier)?;
+        self.check(&msg)?;
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{
+        BeamContent, ChecksumPolicy, CompactHeader, Echo, EchoContent, EchoPolicy, Measurement,
+        MeasurementLayerOutput, PointConverter,
+    };
+
+    fn line(phi: f32, beams: u16, azimuth: bool) -> MeasurementLayerOutput {
+        MeasurementLayerOutput {
+            phi,
+            theta_start: -0.5,
+            theta_end: 0.5,
+            time_stamp_start: 1_000,
+            time_stamp_end: 2_000,
+            data: (0..beams)
+                .map(|beam| Measurement::Filled {
+                    echoes: smallvec![Echo {
+                        distance: Some(1_000 + beam),
+                        rssi: Some(beam),
+                    }],
+                    beam_properties: None,
+                    azimuth_angle: azimuth
+                        .then(|| crate::msgpack::radians_to_azimuth(-0.5 + beam as f32 * 0.01)),
+                })
+                .collect(),
+        }
+    }
+
+    /// One multiScan136 segment: every layer in one module, `phi` is the elevation.
+    fn multiscan_fixture() -> Vec<u8> {
+        let module = MeasurementModule::new(
+            0,
+            1,
+            1,
+            1.0,
+            EchoContent::all(),
+            BeamContent::empty(),
+            (0..16)
+                .map(|layer| line((layer as f32 - 7.5).to_radians() * 3.0, 240, false))
+                .collect(),
+        );
+        CompactMessage::distance(CompactHeader::new(1, 0, 4), [module]).to_bytes()
+    }
+
+    /// One picoScan150 segment: a single line with per-beam azimuth and a `phi` that is
+    /// not an elevation.
+    fn picoscan_fixture() -> Vec<u8> {
+        let module = MeasurementModule::new(
+            0,
+            1,
+            1,
+            1.0,
+            EchoContent::all(),
+            BeamContent::AZIMUTH,
+            smallvec![line(0.3, 100, true)],
+        );
+        CompactMessage::distance(CompactHeader::new(1, 0, 4), [module]).to_bytes()
+    }
+
+    fn points(device: Device, telegram: &[u8]) -> StandardResult<Vec<crate::ScanPoint>> {
+        let mut verifier = ChecksumVerifier::new(ChecksumPolicy::Reject);
+        let msg = device.profile().decode(telegram, &mut verifier)?;
+        let CompactMessage::DistanceMessage { data, .. } = msg else {
+            unreachable!();
+        };
+        let mut converter = PointConverter::new(EchoPolicy::First);
+        converter.device = device;
+        let mut out = Vec::new();
+        data.iter()
+            .for_each(|m| converter.convert_module(m, &mut out));
+        Ok(out)
+    }
+
+    #[test]
+    fn multiscan_layers_keep_their_elevation() {
+        let points = points(Device::MultiScan136, &multiscan_fixture()).unwrap();
+        assert_eq!(points.len(), 16 * 240);
+        let top = points.iter().find(|p| p.layer == 15).unwrap();
+        let elevation = (top.z / top.distance()).asin();
+        assert!((elevation - (7.5_f32).to_radians() * 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn picoscan_is_planar_and_uses_the_beam_azimuth() {
+        for device in [Device::PicoScan100, Device::PicoScan150] {
+            let points = points(device, &picoscan_fixture()).unwrap();
+            assert_eq!(points.len(), 100);
+            assert!(points.iter().all(|p| p.z == 0.0));
+            let last = points.last().unwrap();
+            let azimuth = last.y.atan2(last.x);
+            assert!((azimuth - (-0.5 + 99.0 * 0.01)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn layouts_of_the_other_family_are_rejected() {
+        assert!(matches!(
+            points(Device::PicoScan150, &multiscan_fixture()),
+            Err(CompactError::UnexpectedLayout {
+                field: "number_lines_in_module",
+                value: 16,
+                max: 1,
+                ..
+            })
+        ));
+        assert!(points(Device::MultiScan136, &picoscan_fixture()).is_ok());
+    }
+
+    #[test]
+    fn picoscan_frames_are_not_cut_after_twelve_segments() {
+        let multiscan = Device::MultiScan136.profile().assembler_config();
+        assert_eq!(multiscan.segments_per_frame, Some(12));
+        let mut assembler =
+            crate::FrameAssembler::new(Device::PicoScan150.profile().assembler_config());
+        let now = std::time::Instant::now();
+        let mut frames = Vec::new();
+        for frame in 1..=3 {
+            for segment in 0..16 {
+                let module = MeasurementModule::new(
+                    frame * 16 + segment,
+                    frame,
+                    1,
+                    1.0,
+                    EchoContent::DISTANCE,
+                    BeamContent::empty(),
+                    smallvec![line(0.0, 4, false)],
+                );
+                frames.extend(assembler.push(module, now));
+            }
+        }
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].frame_number, 1);
+        assert_eq!(frames[0].segments.len(), 16);
+        assert!(frames[0].is_complete());
+    }
+
+    #[test]
+    fn devices_are_found_by_name() {
+        assert_eq!(Device::from_name("picoscan150"), Some(Device::PicoScan150));
+        assert_eq!(
+            Device::from_name("multiScan136"),
+            Some(Device::MultiScan136)
+        );
+        assert_eq!(Device::from_name("tim781"), None);
+    }
+}
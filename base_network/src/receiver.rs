@@ -0,0 +1,325 @@
+//! UDP reception of the Compact data port of several sensors on one socket.
+//!
+//! [`UdpReceiver`] reads every datagram of a unicast, broadcast or multicast binding and
+//! routes it by source address and the `sender_id` of its first module. Each sensor gets
+//! its own channel, announced on the [`SensorStream`] channel the first time it is seen.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use tokio::{net::UdpSocket, sync::mpsc};
+
+use crate::{frame::CompactFrameRef, msgpack, ArrivalTime, CompactMessage, ScanDataFormat};
+
+/// Largest UDP payload.
+const MAX_DATAGRAM: usize = 65535;
+
+/// What a [`UdpReceiver`] binds to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    /// A local address, e.g. the host address the sensor is configured to send to.
+    Unicast(SocketAddr),
+    /// Every IPv4 datagram to `port`, broadcasts included.
+    Broadcast { port: u16 },
+    /// Joins `group` on the interface with the address `interface`,
+    /// [`Ipv4Addr::UNSPECIFIED`] lets the system choose.
+    MulticastV4 {
+        group: Ipv4Addr,
+        port: u16,
+        interface: Ipv4Addr,
+    },
+    /// Joins `group` on the interface with the index `interface`, 0 lets the system choose.
+    MulticastV6 {
+        group: Ipv6Addr,
+        port: u16,
+        interface: u32,
+    },
+}
+
+impl Binding {
+    /// Binds a socket that shares its port with other receivers of the same binding.
+    pub fn bind(&self) -> io::Result<UdpSocket> {
+        let address = match *self {
+            Binding::Unicast(address) => address,
+            Binding::Broadcast { port } | Binding::MulticastV4 { port, .. } => {
+                (Ipv4Addr::UNSPECIFIED, port).into()
+            }
+            Binding::MulticastV6 { port, .. } => (Ipv6Addr::UNSPECIFIED, port).into(),
+        };
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        socket.set_reuse_address(true)?;
+        match self {
+            Binding::Unicast(_) => {}
+            Binding::Broadcast { .. } => socket.set_broadcast(true)?,
+            Binding::MulticastV4 {
+                group, interface, ..
+            } => socket.join_multicast_v4(group, interface)?,
+            Binding::MulticastV6 {
+                group, interface, ..
+            } => {
+                socket.set_only_v6(true)?;
+                socket.join_multicast_v6(group, *interface)?;
+            }
+        }
+        socket.bind(&SockAddr::from(address))?;
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket.into())
+    }
+}
+
+/// Identifies one sensor on a shared port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SensorKey {
+    pub address: IpAddr,
+    pub sender_id: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Datagram {
+    pub source: SocketAddr,
+    pub arrival: ArrivalTime,
+    pub bytes: Vec<u8>,
+}
+
+/// The datagrams of one sensor.
+#[derive(Debug)]
+pub struct SensorStream {
+    pub key: SensorKey,
+    pub datagrams: mpsc::Receiver<Datagram>,
+}
+
+/// Sender id of the first module of a distance telegram, Compact or MSGPACK.
+pub fn telegram_sender_id(telegram: &[u8]) -> Option<u32> {
+    match ScanDataFormat::detect(telegram)? {
+        ScanDataFormat::Compact => match CompactFrameRef::parse(telegram).ok()? {
+            CompactFrameRef::Distance(frame) => frame.modules().next().map(|m| m.sender_id()),
+            CompactFrameRef::Imu(_) => None,
+        },
+        ScanDataFormat::Msgpack => match msgpack::decode_msgpack(telegram).ok()? {
+            CompactMessage::DistanceMessage { data, .. } => data.first().map(|m| m.sender_id),
+            CompactMessage::IMUMessage { .. } => None,
+        },
+    }
+}
+
+pub struct UdpReceiver {
+    socket: UdpSocket,
+    /// Capacity of each sensor channel.
+    capacity: usize,
+    sensors: HashMap<SensorKey, mpsc::Sender<Datagram>>,
+    /// Sender id of the latest distance telegram per address, for IMU telegrams.
+    last_sender: HashMap<IpAddr, u32>,
+    announce: mpsc::UnboundedSender<SensorStream>,
+    /// Datagrams dropped because their sensor channel was full or closed.
+    pub dropped: u64,
+}
+
+impl UdpReceiver {
+    /// Binds `binding`. New sensors are announced on the returned channel, each with a
+    /// channel holding up to `capacity` datagrams.
+    pub fn bind(
+        binding: &Binding,
+        capacity: usize,
+    ) -> io::Result<(Self, mpsc::UnboundedReceiver<SensorStream>)> {
+        Ok(Self::new(binding.bind()?, capacity))
+    }
+
+    pub fn new(
+        socket: UdpSocket,
+        capacity: usize,
+    ) -> (Self, mpsc::UnboundedReceiver<SensorStream>) {
+        let (announce, sensors) = mpsc::unbounded_channel();
+        let receiver = Self {
+            socket,
+            capacity: capacity.max(1),
+            sensors: HashMap::new(),
+            last_sender: HashMap::new(),
+            announce,
+            dropped: 0,
+        };
+        (receiver, sensors)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Routes datagrams until the socket fails or nobody listens for new sensors anymore.
+    pub async fn run(mut self) -> io::Result<()> {
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        while !self.announce.is_closed() {
+            let (n, source) = self.socket.recv_from(&mut buffer).await?;
+            self.route(Datagram {
+                source,
+                arrival: ArrivalTime::now(),
+                bytes: buffer[..n].to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Hands `datagram` to the channel of its sensor, creating it when needed.
+    pub fn route(&mut self, datagram: Datagram) {
+        let address = datagram.source.ip();
+        let sender_id = match telegram_sender_id(&datagram.bytes) {
+            Some(id) => {
+                self.last_sender.insert(address, id);
+                id
+            }
+            None => self.last_sender.get(&address).copied().unwrap_or(0),
+        };
+        let key = SensorKey { address, sender_id };
+        let channel = self.sensors.entry(key).or_insert_with(|| {
+            let (sender, datagrams) = mpsc::channel(self.capacity);
+            let _ = self.announce.send(SensorStream { key, datagrams });
+            sender
+        });
+        if channel.try_send(datagram).is_err() {
+            self.dropped += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::SmallVec;
+
+    use super::*;
+    use crate::{BeamContent, CompactHeader, EchoContent, IMUData, MeasurementModule};
+
+    fn distance(sender_id: u32) -> Vec<u8> {
+        let module = MeasurementModule::new(
+            0,
+            1,
+            sender_id,
+            1.0,
+            EchoContent::DISTANCE,
+            BeamContent::empty(),
+            SmallVec::new(),
+        );
+        CompactMessage::distance(CompactHeader::new(1, 2, 4), [module]).to_bytes()
+    }
+
+    fn imu() -> Vec<u8> {
+        CompactMessage::IMUMessage {
+            imudata: IMUData {
+                telegram_version: 1,
+                acceleration: (0.0, 0.0, 9.81),
+                angular_velocity: (0.0, 0.0, 0.0),
+                orientation: (1.0, 0.0, 0.0, 0.0),
+                time_stamp: 1,
+            },
+        }
+        .to_bytes()
+    }
+
+    fn datagram(source: &str, bytes: Vec<u8>) -> Datagram {
+        Datagram {
+            source: source.parse().unwrap(),
+            arrival: ArrivalTime::now(),
+            bytes,
+        }
+    }
+
+    async fn receiver(capacity: usize) -> (UdpReceiver, mpsc::UnboundedReceiver<SensorStream>) {
+        let socket = Binding::Unicast("127.0.0.1:0".parse().unwrap())
+            .bind()
+            .unwrap();
+        UdpReceiver::new(socket, capacity)
+    }
+
+    fn key(address: &str, sender_id: u32) -> SensorKey {
+        SensorKey {
+            address: address.parse().unwrap(),
+            sender_id,
+        }
+    }
+
+    #[test]
+    fn sender_ids_come_from_the_first_module() {
+        assert_eq!(telegram_sender_id(&distance(42)), Some(42));
+        assert_eq!(telegram_sender_id(&imu()), None);
+        assert_eq!(telegram_sender_id(b"not a telegram"), None);
+    }
+
+    #[tokio::test]
+    async fn datagrams_are_routed_by_address_and_sender_id() {
+        let (mut receiver, mut sensors) = receiver(8).await;
+        receiver.route(datagram("10.0.0.1:2115", distance(1)));
+        receiver.route(datagram("10.0.0.1:2115", distance(2)));
+        receiver.route(datagram("10.0.0.2:2115", distance(1)));
+        receiver.route(datagram("10.0.0.1:2116", distance(1)));
+
+        let mut streams = Vec::new();
+        while let Ok(stream) = sensors.try_recv() {
+            streams.push(stream);
+        }
+        let keys: Vec<SensorKey> = streams.iter().map(|s| s.key).collect();
+        assert_eq!(
+            keys,
+            [key("10.0.0.1", 1), key("10.0.0.1", 2), key("10.0.0.2", 1)]
+        );
+        let counts: Vec<usize> = streams.iter().map(|s| s.datagrams.len()).collect();
+        assert_eq!(counts, [2, 1, 1]);
+        assert_eq!(receiver.dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn imu_telegrams_follow_the_last_sender_of_their_address() {
+        let (mut receiver, mut sensors) = receiver(8).await;
+        receiver.route(datagram("10.0.0.1:2115", imu()));
+        receiver.route(datagram("10.0.0.1:2115", distance(3)));
+        receiver.route(datagram("10.0.0.2:2115", distance(4)));
+        receiver.route(datagram("10.0.0.1:2115", imu()));
+        receiver.route(datagram("10.0.0.2:2115", imu()));
+
+        let mut unknown = sensors.try_recv().unwrap();
+        assert_eq!(unknown.key, key("10.0.0.1", 0));
+        assert_eq!(unknown.datagrams.try_recv().unwrap().bytes, imu());
+        for (address, sender_id) in [("10.0.0.1", 3), ("10.0.0.2", 4)] {
+            let mut stream = sensors.try_recv().unwrap();
+            assert_eq!(stream.key, key(address, sender_id));
+            assert_eq!(
+                stream.datagrams.try_recv().unwrap().bytes,
+                distance(sender_id)
+            );
+            assert_eq!(stream.datagrams.try_recv().unwrap().bytes, imu());
+        }
+        assert!(sensors.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn full_channels_drop_datagrams() {
+        let (mut receiver, mut sensors) = receiver(1).await;
+        receiver.route(datagram("10.0.0.1:2115", distance(1)));
+        receiver.route(datagram("10.0.0.1:2115", distance(1)));
+        assert_eq!(receiver.dropped, 1);
+        drop(sensors.try_recv().unwrap());
+        receiver.route(datagram("10.0.0.1:2115", distance(1)));
+        assert_eq!(receiver.dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn running_receivers_announce_sensors_sending_to_their_socket() {
+        let (receiver, mut sensors) = receiver(8).await;
+        let address = receiver.local_addr().unwrap();
+        let task = tokio::spawn(receiver.run());
+        let sensor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sensor.send_to(&distance(9), address).await.unwrap();
+
+        let mut stream = sensors.recv().await.unwrap();
+        assert_eq!(stream.key, key("127.0.0.1", 9));
+        let received = stream.datagrams.recv().await.unwrap();
+        assert_eq!(received.source, sensor.local_addr().unwrap());
+        assert_eq!(received.bytes, distance(9));
+        task.abort();
+    }
+}
@@ -0,0 +1,444 @@
+//! A minimal recording format for raw telegrams, to reproduce sessions without a sensor.
+//!
+//! A recording is the magic `SICKRAW1` followed by records of
+//!
+//! ```text
+//! host time µs since the unix epoch: u64, source: u32, transport: u8, length: u32, bytes
+//! ```
+//!
+//! all little-endian. `source` is chosen by whoever records, e.g. one id per connection.
+
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream, ReadBuf},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    capture::Transport,
+    replay::{Pacer, Pacing, Recorded, REPLAY_PIPE_SIZE},
+    ToBytes,
+};
+
+const RECORDING_MAGIC: &[u8; 8] = b"SICKRAW1";
+const RECORD_HEADER_SIZE: usize = 17;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Host time since the unix epoch.
+    pub timestamp: Duration,
+    pub source: u32,
+    pub transport: Transport,
+    pub bytes: Vec<u8>,
+}
+
+impl Recorded for Record {
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+    fn payload(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Record {
+    pub fn now(source: u32, transport: Transport, bytes: &[u8]) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            source,
+            transport,
+            bytes: bytes.to_vec(),
+        }
+    }
+}
+
+impl ToBytes for Record {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        (self.timestamp.as_micros() as u64).write_to_data(data);
+        self.source.write_to_data(data);
+        match self.transport {
+            Transport::Udp => 0_u8,
+            Transport::Tcp => 1_u8,
+        }
+        .write_to_data(data);
+        (self.bytes.len() as u32).write_to_data(data);
+        data.extend_from_slice(&self.bytes);
+    }
+}
+
+/// Parses a complete recording.
+pub fn parse_recording(file: &[u8]) -> io::Result<Vec<Record>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    if !file.starts_with(RECORDING_MAGIC) {
+        return Err(invalid("not a raw telegram recording"));
+    }
+    let mut records = Vec::new();
+    let mut rest = &file[RECORDING_MAGIC.len()..];
+    while !rest.is_empty() {
+        let header = rest
+            .get(..RECORD_HEADER_SIZE)
+            .ok_or_else(|| invalid("record header truncated"))?;
+        let micros = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let source = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let transport = match header[12] {
+            0 => Transport::Udp,
+            1 => Transport::Tcp,
+            t => return Err(invalid(&format!("unknown transport {t}"))),
+        };
+        let length = u32::from_le_bytes(header[13..17].try_into().unwrap()) as usize;
+        let bytes = rest
+            .get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length)
+            .ok_or_else(|| invalid("record truncated"))?;
+        records.push(Record {
+            timestamp: Duration::from_micros(micros),
+            source,
+            transport,
+            bytes: bytes.to_vec(),
+        });
+        rest = &rest[RECORD_HEADER_SIZE + length..];
+    }
+    Ok(records)
+}
+
+/// Cheap to clone handle that queues records for a running recorder.
+#[derive(Clone, Debug)]
+pub struct RecorderHandle {
+    sender: mpsc::UnboundedSender<Record>,
+}
+
+impl RecorderHandle {
+    /// Queues `bytes` with the current host time. Does nothing once the recorder stopped.
+    pub fn record(&self, source: u32, transport: Transport, bytes: &[u8]) {
+        let _ = self.sender.send(Record::now(source, transport, bytes));
+    }
+
+    /// Wraps a reader so everything read through it is recorded as data of `source` that
+    /// arrived over `transport`, e.g. [`Transport::Udp`] for a
+    /// [`DatagramReader`](crate::DatagramReader).
+    pub fn attach<R: AsyncRead + Unpin>(
+        &self,
+        source: u32,
+        transport: Transport,
+        inner: R,
+    ) -> RecordingReader<R> {
+        RecordingReader {
+            inner,
+            source,
+            transport,
+            recorder: self.clone(),
+        }
+    }
+}
+
+/// Writes the records of its handles to a file until every handle is dropped.
+pub struct Recorder;
+
+impl Recorder {
+    /// Creates the recording at `path` and starts the task writing to it. The task returns
+    /// the number of records written.
+    pub async fn spawn(
+        path: impl AsRef<Path>,
+    ) -> io::Result<(RecorderHandle, JoinHandle<io::Result<u64>>)> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        file.write_all(RECORDING_MAGIC).await?;
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Record>();
+        let task = tokio::spawn(async move {
+            let mut written = 0;
+            let mut data = Vec::new();
+            while let Some(record) = receiver.recv().await {
+                data.clear();
+                record.write_to_data(&mut data);
+                file.write_all(&data).await?;
+                written += 1;
+            }
+            file.flush().await?;
+            Ok(written)
+        });
+        Ok((RecorderHandle { sender }, task))
+    }
+}
+
+/// Reader that records whatever passes through it. Writes go to the inner stream unrecorded,
+/// so a request/answer connection records the answers only.
+pub struct RecordingReader<R> {
+    inner: R,
+    source: u32,
+    transport: Transport,
+    recorder: RecorderHandle,
+}
+
+impl<R> RecordingReader<R> {
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for RecordingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            self.recorder
+                .record(self.source, self.transport, &buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<R: AsyncWrite + Unpin> AsyncWrite for RecordingReader<R> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Plays a recording back with seeking and looping.
+pub struct Replayer {
+    records: Vec<Record>,
+    position: usize,
+    /// Start over at the first record after the last one.
+    pub looping: bool,
+    pacer: Pacer,
+}
+
+impl Replayer {
+    pub fn new(records: Vec<Record>, pacing: Pacing) -> Self {
+        Self {
+            records,
+            position: 0,
+            looping: false,
+            pacer: Pacer::new(pacing),
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>, pacing: Pacing) -> io::Result<Self> {
+        Ok(Self::new(parse_recording(&std::fs::read(path)?)?, pacing))
+    }
+
+    /// Keeps only the records of `source`.
+    pub fn only_source(mut self, source: u32) -> Self {
+        self.records.retain(|r| r.source == source);
+        self.position = 0;
+        self
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    fn start(&self) -> Duration {
+        self.records.first().map_or(Duration::ZERO, |r| r.timestamp)
+    }
+
+    /// Time between the first and the last record.
+    pub fn duration(&self) -> Duration {
+        self.records
+            .last()
+            .map_or(Duration::ZERO, |r| r.timestamp.saturating_sub(self.start()))
+    }
+
+    /// Time of the next record relative to the first one.
+    pub fn position(&self) -> Duration {
+        self.records
+            .get(self.position)
+            .map_or(self.duration(), |r| {
+                r.timestamp.saturating_sub(self.start())
+            })
+    }
+
+    /// Continues with the first record at or after `offset` from the start.
+    pub fn seek(&mut self, offset: Duration) {
+        let target = self.start() + offset;
+        self.position = self.records.partition_point(|r| r.timestamp < target);
+        self.pacer.restart();
+    }
+
+    /// The next record once it is due.
+    pub async fn next(&mut self) -> Option<&Record> {
+        if self.position >= self.records.len() {
+            if !self.looping || self.records.is_empty() {
+                return None;
+            }
+            self.position = 0;
+            self.pacer.restart();
+        }
+        let record = &self.records[self.position];
+        self.position += 1;
+        self.pacer.wait(record.timestamp).await;
+        Some(record)
+    }
+
+    /// Returns a reader yielding the bytes of every remaining record as one stream, for
+    /// the parsers that read from a connection.
+    pub fn stream_reader(mut self) -> DuplexStream {
+        let (reader, mut writer) = tokio::io::duplex(REPLAY_PIPE_SIZE);
+        tokio::spawn(async move {
+            while let Some(record) = self.next().await {
+                if writer.write_all(&record.bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+        reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn record(millis: u64, bytes: &[u8]) -> Record {
+        Record {
+            timestamp: Duration::from_millis(millis),
+            source: 1,
+            transport: Transport::Udp,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    async fn next_bytes(replayer: &mut Replayer) -> Option<Vec<u8>> {
+        replayer.next().await.map(|r| r.bytes.clone())
+    }
+
+    #[tokio::test]
+    async fn requests_written_through_a_recorded_stream_are_not_recorded() {
+        let path = std::env::temp_dir().join(format!("sick_requests_{}.raw", std::process::id()));
+        let (handle, task) = Recorder::spawn(&path).await.unwrap();
+        let (mut sensor, connection) = tokio::io::duplex(64);
+        let mut stream = handle.attach(1, Transport::Tcp, connection);
+        stream.write_all(b"request").await.unwrap();
+        let mut request = [0; 7];
+        sensor.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"request");
+        sensor.write_all(b"answer").await.unwrap();
+        drop(sensor);
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer).await.unwrap();
+        assert_eq!(answer, b"answer");
+        drop((handle, stream));
+        task.await.unwrap().unwrap();
+
+        let replayer = Replayer::open(&path, Pacing::AsFastAsPossible).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let recorded: Vec<u8> = replayer
+            .records()
+            .iter()
+            .flat_map(|r| r.bytes.clone())
+            .collect();
+        assert_eq!(recorded, b"answer");
+    }
+
+    #[tokio::test]
+    async fn recordings_replay_with_their_source_and_transport() {
+        let path = std::env::temp_dir().join(format!("sick_record_{}.raw", std::process::id()));
+        let (handle, task) = Recorder::spawn(&path).await.unwrap();
+        handle.record(1, Transport::Udp, b"datagram");
+
+        let (mut sensor, connection) = tokio::io::duplex(64);
+        sensor.write_all(b"stream").await.unwrap();
+        drop(sensor);
+        let mut reader = handle.attach(2, Transport::Tcp, connection);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"stream");
+        drop(reader);
+
+        let (mut sensor, datagrams) = tokio::io::duplex(64);
+        sensor.write_all(b"udp").await.unwrap();
+        drop(sensor);
+        let mut reader = handle.attach(3, Transport::Udp, datagrams);
+        reader.read_to_end(&mut Vec::new()).await.unwrap();
+        drop((handle, reader));
+        assert!(task.await.unwrap().unwrap() >= 3);
+
+        let replayer = Replayer::open(&path, Pacing::AsFastAsPossible).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records = replayer.records();
+        assert_eq!(records[0].bytes, b"datagram");
+        assert_eq!(records[0].transport, Transport::Udp);
+        for (source, transport, bytes) in [
+            (2, Transport::Tcp, b"stream".as_slice()),
+            (3, Transport::Udp, b"udp"),
+        ] {
+            let of_source: Vec<&Record> = records.iter().filter(|r| r.source == source).collect();
+            assert!(of_source.iter().all(|r| r.transport == transport));
+            let replayed: Vec<u8> = of_source.iter().flat_map(|r| r.bytes.clone()).collect();
+            assert_eq!(replayed, bytes);
+        }
+    }
+
+    #[tokio::test]
+    async fn seeking_starts_at_the_next_record() {
+        let records = vec![
+            record(0, b"a"),
+            record(10, b"b"),
+            record(20, b"c"),
+            record(30, b"d"),
+        ];
+        let mut replayer = Replayer::new(records, Pacing::AsFastAsPossible);
+        assert_eq!(replayer.duration(), Duration::from_millis(30));
+        replayer.seek(Duration::from_millis(15));
+        assert_eq!(replayer.position(), Duration::from_millis(20));
+        assert_eq!(next_bytes(&mut replayer).await.unwrap(), b"c");
+        assert_eq!(next_bytes(&mut replayer).await.unwrap(), b"d");
+        assert_eq!(next_bytes(&mut replayer).await, None);
+
+        replayer.seek(Duration::ZERO);
+        assert_eq!(next_bytes(&mut replayer).await.unwrap(), b"a");
+        replayer.seek(Duration::from_secs(1));
+        assert_eq!(replayer.position(), replayer.duration());
+        assert_eq!(next_bytes(&mut replayer).await, None);
+    }
+
+    #[tokio::test]
+    async fn looping_wraps_around_to_the_first_record() {
+        let records = vec![record(0, b"a"), record(10, b"b"), record(20, b"c")];
+        let mut replayer = Replayer::new(records, Pacing::AsFastAsPossible);
+        replayer.looping = true;
+        replayer.seek(Duration::from_millis(20));
+        let mut replayed = Vec::new();
+        for _ in 0..5 {
+            replayed.extend(next_bytes(&mut replayer).await.unwrap());
+        }
+        assert_eq!(replayed, b"cabca");
+        assert_eq!(replayer.position(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn truncated_recordings_are_rejected() {
+        let mut file = RECORDING_MAGIC.to_vec();
+        record(5, b"payload").write_to_data(&mut file);
+        assert_eq!(parse_recording(&file).unwrap(), [record(5, b"payload")]);
+        assert!(parse_recording(&file[..file.len() - 1]).is_err());
+        assert!(parse_recording(&file[..RECORDING_MAGIC.len() + 3]).is_err());
+        assert!(parse_recording(b"SICKRAW0").is_err());
+    }
+}
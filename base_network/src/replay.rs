@@ -0,0 +1,139 @@
+//! Replay of recorded traffic into the parsers.
+//!
+//! Datagrams are handed to a callback, byte streams are written into an in-memory pipe or a
+//! loopback TCP connection, so [`CompactMessage::read_message`](crate::CompactMessage) and
+//! anything that reads from a `TcpStream` work unchanged.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream},
+    net::TcpListener,
+    time::sleep_until,
+};
+
+use crate::capture::CapturedPacket;
+
+/// Pipe buffer of the stream readers.
+pub(crate) const REPLAY_PIPE_SIZE: usize = 1 << 16;
+
+/// A recorded chunk of bytes with the time it was seen.
+pub trait Recorded {
+    fn timestamp(&self) -> Duration;
+    fn payload(&self) -> &[u8];
+}
+
+impl<T: Recorded> Recorded for &T {
+    fn timestamp(&self) -> Duration {
+        (**self).timestamp()
+    }
+    fn payload(&self) -> &[u8] {
+        (**self).payload()
+    }
+}
+
+impl Recorded for CapturedPacket {
+    fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Pacing {
+    /// Keep the gaps between packets as they were recorded.
+    #[default]
+    RealTime,
+    /// Like [`Pacing::RealTime`], but this many times faster.
+    Speed(f64),
+    AsFastAsPossible,
+}
+
+/// Sleeps until each recorded timestamp is due relative to the first one.
+#[derive(Clone, Debug)]
+pub struct Pacer {
+    pub pacing: Pacing,
+    start: Option<(Duration, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing) -> Self {
+        Self {
+            pacing,
+            start: None,
+        }
+    }
+
+    /// Makes the next timestamp the start of the replay again, e.g. after a seek.
+    pub fn restart(&mut self) {
+        self.start = None;
+    }
+
+    pub async fn wait(&mut self, timestamp: Duration) {
+        let speed = match self.pacing {
+            Pacing::RealTime => 1.0,
+            Pacing::Speed(speed) if speed > 0.0 => speed,
+            _ => return,
+        };
+        let (first, started) = *self.start.get_or_insert((timestamp, Instant::now()));
+        let offset = timestamp.saturating_sub(first).div_f64(speed);
+        sleep_until((started + offset).into()).await;
+    }
+}
+
+/// Hands every datagram to `f` at its recorded time.
+pub async fn replay_datagrams<R: Recorded>(
+    packets: impl IntoIterator<Item = R>,
+    pacing: Pacing,
+    mut f: impl FnMut(&R),
+) {
+    let mut pacer = Pacer::new(pacing);
+    for packet in packets {
+        pacer.wait(packet.timestamp()).await;
+        f(&packet);
+    }
+}
+
+/// Writes the payloads as one continuous byte stream into `writer`.
+pub async fn replay_stream<R: Recorded, W: AsyncWriteExt + Unpin>(
+    packets: impl IntoIterator<Item = R>,
+    pacing: Pacing,
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut pacer = Pacer::new(pacing);
+    for packet in packets {
+        pacer.wait(packet.timestamp()).await;
+        writer.write_all(packet.payload()).await?;
+    }
+    writer.flush().await
+}
+
+/// Returns a reader that yields the payloads as one byte stream. The stream ends after the
+/// last packet.
+pub fn stream_reader<R: Recorded + Send + 'static>(
+    packets: Vec<R>,
+    pacing: Pacing,
+) -> DuplexStream {
+    let (reader, mut writer) = tokio::io::duplex(REPLAY_PIPE_SIZE);
+    tokio::spawn(async move {
+        let _ = replay_stream(packets, pacing, &mut writer).await;
+    });
+    reader
+}
+
+/// Accepts one connection on `listener` and replays the payloads to it, for code that
+/// connects to the sensor itself. Whatever the client sends is ignored.
+pub async fn serve_tcp<R: Recorded>(
+    listener: TcpListener,
+    packets: Vec<R>,
+    pacing: Pacing,
+) -> io::Result<()> {
+    let (mut stream, _) = listener.accept().await?;
+    replay_stream(packets, pacing, &mut stream).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{CompactHeader, CompactMessage, MeasurementModule};
+
+/// How far behind the newest counter a packet may arrive and still be told apart from a
+/// duplicate. Counters further behind are treated as a restart of the sequence.
+const REORDER_WINDOW: u64 = 64;
+
+/// Host time at which a telegram was received.
+#[derive(Clone, Copy, Debug)]
+pub struct ArrivalTime {
+    pub instant: Instant,
+    pub system: SystemTime,
+}
+
+impl ArrivalTime {
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+        }
+    }
+
+    /// Wall clock time in microseconds since the unix epoch.
+    pub fn system_micros(&self) -> u64 {
+        self.system
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Arrival times of replayed telegrams, taken from their recorded timestamps rather than
+/// from when the replay hands them over.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayClock {
+    /// Recorded timestamp and host instant of the previous telegram.
+    last: Option<(Duration, Instant)>,
+}
+
+impl ReplayClock {
+    /// Arrival of a telegram recorded `timestamp` after the unix epoch. Instants keep the
+    /// recorded gaps and never go back, also when a looping replay starts over.
+    pub fn arrival(&mut self, timestamp: Duration) -> ArrivalTime {
+        let instant = match self.last {
+            Some((previous, at)) => at + timestamp.saturating_sub(previous),
+            None => Instant::now(),
+        };
+        self.last = Some((timestamp, instant));
+        ArrivalTime {
+            instant,
+            system: UNIX_EPOCH + timestamp,
+        }
+    }
+}
+
+/// Result of feeding one counter to a [`SequenceTracker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceEvent {
+    First,
+    InOrder,
+    /// The counter jumped ahead, skipping this many values.
+    Gap(u64),
+    /// A counter that was previously counted as lost arrived late.
+    Reordered,
+    Duplicate,
+    /// The counter went back further than the reorder window, e.g. after a sensor restart.
+    Wraparound,
+}
+
+/// Classifies a monotonic counter such as `telegram_counter` or `segment_counter`.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceTracker {
+    highest: Option<u64>,
+    /// Bit `n` is set when `highest - n` has been seen.
+    seen: u64,
+    pub received: u64,
+    pub lost: u64,
+    pub duplicates: u64,
+    pub reordered: u64,
+    pub wraparounds: u64,
+}
+
+impl SequenceTracker {
+    pub fn push(&mut self, counter: u64) -> SequenceEvent {
+        let Some(highest) = self.highest else {
+            self.restart(counter);
+            return SequenceEvent::First;
+        };
+        if counter > highest {
+            let gap = counter - highest - 1;
+            self.seen = if gap + 1 >= REORDER_WINDOW {
+                1
+            } else {
+                (self.seen << (gap + 1)) | 1
+            };
+            self.highest = Some(counter);
+            self.received += 1;
+            self.lost += gap;
+            return match gap {
+                0 => SequenceEvent::InOrder,
+                g => SequenceEvent::Gap(g),
+            };
+        }
+        let behind = highest - counter;
+        if behind >= REORDER_WINDOW {
+            self.wraparounds += 1;
+            self.restart(counter);
+            return SequenceEvent::Wraparound;
+        }
+        if self.seen & (1 << behind) != 0 {
+            self.duplicates += 1;
+            return SequenceEvent::Duplicate;
+        }
+        self.seen |= 1 << behind;
+        self.received += 1;
+        self.reordered += 1;
+        self.lost = self.lost.saturating_sub(1);
+        SequenceEvent::Reordered
+    }
+
+    fn restart(&mut self, counter: u64) {
+        self.highest = Some(counter);
+        self.seen = 1;
+        self.received += 1;
+    }
+
+    /// Fraction of the expected counters that never arrived, over the whole stream.
+    pub fn loss_rate(&self) -> f64 {
+        let expected = self.received + self.lost;
+        if expected == 0 {
+            return 0.0;
+        }
+        self.lost as f64 / expected as f64
+    }
+}
+
+/// Sliding window of (arrival, received, lost) samples used for the rolling figures.
+#[derive(Clone, Debug, Default)]
+struct RollingLoss {
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl RollingLoss {
+    fn push(&mut self, at: Instant, event: SequenceEvent) {
+        match event {
+            SequenceEvent::Duplicate => {}
+            SequenceEvent::Gap(g) => self.samples.push_back((at, 1, g)),
+            SequenceEvent::Reordered => {
+                // The late packet was already counted as lost in an earlier sample.
+                if let Some(s) = self.samples.iter_mut().rev().find(|s| s.2 > 0) {
+                    s.2 -= 1;
+                }
+                self.samples.push_back((at, 1, 0));
+            }
+            _ => self.samples.push_back((at, 1, 0)),
+        }
+    }
+
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| now.duration_since(s.0) > window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        let (received, lost) = self
+            .samples
+            .iter()
+            .fold((0, 0), |(r, l), s| (r + s.1, l + s.2));
+        if received + lost == 0 {
+            return 0.0;
+        }
+        lost as f64 / (received + lost) as f64
+    }
+}
+
+/// Statistics for the telegrams and segments of one sender.
+#[derive(Clone, Debug, Default)]
+pub struct SenderStatistics {
+    pub telegrams: SequenceTracker,
+    pub segments: SequenceTracker,
+    telegram_window: RollingLoss,
+    segment_window: RollingLoss,
+    /// (arrival, arrival wall clock minus header timestamp in µs)
+    latencies: VecDeque<(Instant, i64)>,
+}
+
+impl SenderStatistics {
+    pub fn rolling_telegram_loss(&self) -> f64 {
+        self.telegram_window.rate()
+    }
+
+    pub fn rolling_segment_loss(&self) -> f64 {
+        self.segment_window.rate()
+    }
+
+    /// Segments received per second over the statistics window.
+    pub fn segments_per_second(&self) -> f64 {
+        let samples = &self.segment_window.samples;
+        match (samples.front(), samples.back()) {
+            (Some(first), Some(last)) if last.0 > first.0 => {
+                (samples.len() - 1) as f64 / last.0.duration_since(first.0).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Latency percentile in microseconds, `p` in `0.0..=1.0`.
+    ///
+    /// This is the host arrival time minus the header timestamp, so it only means an
+    /// absolute latency when the sensor clock is synchronised with the host.
+    pub fn latency_percentile(&self, p: f64) -> Option<i64> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<i64> = self.latencies.iter().map(|l| l.1).collect();
+        sorted.sort_unstable();
+        let index = ((sorted.len() - 1) as f64 * p.clamp(0.0, 1.0)).round() as usize;
+        Some(sorted[index])
+    }
+
+    fn expire(&mut self, now: Instant, window: Duration) {
+        self.telegram_window.expire(now, window);
+        self.segment_window.expire(now, window);
+        while self
+            .latencies
+            .front()
+            .is_some_and(|l| now.duration_since(l.0) > window)
+        {
+            self.latencies.pop_front();
+        }
+    }
+}
+
+/// Per-sender packet loss, reordering and latency figures for a Compact stream.
+pub struct StreamStatistics {
+    /// Length of the window the rolling figures are computed over.
+    pub window: Duration,
+    senders: HashMap<u32, SenderStatistics>,
+}
+
+impl Default for StreamStatistics {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10))
+    }
+}
+
+impl StreamStatistics {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Records a decoded telegram. The telegram counter is attributed to the sender of its
+    /// first module, IMU telegrams carry no counters and are ignored.
+    pub fn record(&mut self, msg: &CompactMessage, arrival: ArrivalTime) {
+        if let CompactMessage::DistanceMessage { header, data } = msg {
+            if let Some(first) = data.first() {
+                self.record_telegram(first.sender_id, header, arrival);
+            }
+            data.iter().for_each(|m| self.record_segment(m, arrival));
+        }
+    }
+
+    pub fn record_telegram(
+        &mut self,
+        sender_id: u32,
+        header: &CompactHeader,
+        arrival: ArrivalTime,
+    ) {
+        let window = self.window;
+        let stats = self.senders.entry(sender_id).or_default();
+        let event = stats.telegrams.push(header.telegram_counter);
+        stats.telegram_window.push(arrival.instant, event);
+        let latency = arrival.system_micros() as i64 - header.timestamp as i64;
+        stats.latencies.push_back((arrival.instant, latency));
+        stats.expire(arrival.instant, window);
+    }
+
+    pub fn record_segment(&mut self, module: &MeasurementModule, arrival: ArrivalTime) {
+        let window = self.window;
+        let stats = self.senders.entry(module.sender_id).or_default();
+        let event = stats.segments.push(module.segment_counter);
+        stats.segment_window.push(arrival.instant, event);
+        stats.expire(arrival.instant, window);
+    }
+
+    pub fn sender(&self, sender_id: u32) -> Option<&SenderStatistics> {
+        self.senders.get(&sender_id)
+    }
+
+    pub fn senders(&self) -> impl Iterator<Item = (&u32, &SenderStatistics)> {
+        self.senders.iter()
+    }
+
+    pub fn report(&self, sender_id: u32) -> Option<StreamReport> {
+        self.sender(sender_id).map(|s| StreamReport {
+            sender_id,
+            telegrams_received: s.telegrams.received,
+            telegrams_lost: s.telegrams.lost,
+            segments_received: s.segments.received,
+            segments_lost: s.segments.lost,
+            duplicates: s.telegrams.duplicates + s.segments.duplicates,
+            reordered: s.telegrams.reordered + s.segments.reordered,
+            wraparounds: s.telegrams.wraparounds,
+            rolling_telegram_loss: s.rolling_telegram_loss(),
+            rolling_segment_loss: s.rolling_segment_loss(),
+            segments_per_second: s.segments_per_second(),
+            latency_p50: s.latency_percentile(0.5),
+            latency_p90: s.latency_percentile(0.9),
+            latency_p99: s.latency_percentile(0.99),
+        })
+    }
+}
+
+/// Snapshot of the statistics of one sender, printable for field diagnosis.
+#[derive(Clone, Debug)]
+pub struct StreamReport {
+    pub sender_id: u32,
+    pub telegrams_received: u64,
+    pub telegrams_lost: u64,
+    pub segments_received: u64,
+    pub segments_lost: u64,
+    pub duplicates: u64,
+    pub reordered: u64,
+    pub wraparounds: u64,
+    pub rolling_telegram_loss: f64,
+    pub rolling_segment_loss: f64,
+    pub segments_per_second: f64,
+    pub latency_p50: Option<i64>,
+    pub latency_p90: Option<i64>,
+    pub latency_p99: Option<i64>,
+}
+
+impl fmt::Display for StreamReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sender {}: {} telegrams ({} lost, {:.2}% recent), {} segments ({} lost, {:.2}% recent), \
+             {:.1} segments/s, {} duplicates, {} reordered, {} restarts",
+            self.sender_id,
+            self.telegrams_received,
+            self.telegrams_lost,
+            self.rolling_telegram_loss * 100.0,
+            self.segments_received,
+            self.segments_lost,
+            self.rolling_segment_loss * 100.0,
+            self.segments_per_second,
+            self.duplicates,
+            self.reordered,
+            self.wraparounds,
+        )?;
+        if let (Some(p50), Some(p90), Some(p99)) =
+            (self.latency_p50, self.latency_p90, self.latency_p99)
+        {
+            write!(f, ", latency p50/p90/p99 {p50}/{p90}/{p99} µs")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_all(tracker: &mut SequenceTracker, counters: &[u64]) -> Vec<SequenceEvent> {
+        counters.iter().map(|c| tracker.push(*c)).collect()
+    }
+
+    #[test]
+    fn gaps_are_counted_as_lost() {
+        let mut tracker = SequenceTracker::default();
+        let events = push_all(&mut tracker, &[10, 11, 14, 15, 115]);
+        assert_eq!(
+            events,
+            [
+                SequenceEvent::First,
+                SequenceEvent::InOrder,
+                SequenceEvent::Gap(2),
+                SequenceEvent::InOrder,
+                SequenceEvent::Gap(99),
+            ]
+        );
+        assert_eq!((tracker.received, tracker.lost), (5, 101));
+        assert_eq!(tracker.loss_rate(), 101.0 / 106.0);
+    }
+
+    #[test]
+    fn duplicates_are_not_received_twice() {
+        let mut tracker = SequenceTracker::default();
+        let events = push_all(&mut tracker, &[1, 2, 2, 3, 1]);
+        assert_eq!(events[2], SequenceEvent::Duplicate);
+        assert_eq!(events[4], SequenceEvent::Duplicate);
+        assert_eq!((tracker.received, tracker.duplicates), (3, 2));
+    }
+
+    #[test]
+    fn late_packets_are_taken_back_from_the_lost_count() {
+        let mut tracker = SequenceTracker::default();
+        let events = push_all(&mut tracker, &[1, 2, 5, 3, 4, 3]);
+        assert_eq!(
+            events[2..],
+            [
+                SequenceEvent::Gap(2),
+                SequenceEvent::Reordered,
+                SequenceEvent::Reordered,
+                SequenceEvent::Duplicate,
+            ]
+        );
+        assert_eq!(
+            (tracker.received, tracker.lost, tracker.reordered),
+            (5, 0, 2)
+        );
+    }
+
+    #[test]
+    fn a_wrapping_u16_counter_is_not_counted_as_loss() {
+        let mut tracker = SequenceTracker::default();
+        let events = push_all(&mut tracker, &[65534, 65535, 0, 1]);
+        assert_eq!(
+            events[1..],
+            [
+                SequenceEvent::InOrder,
+                SequenceEvent::Wraparound,
+                SequenceEvent::InOrder,
+            ]
+        );
+        assert_eq!((tracker.received, tracker.lost), (4, 0));
+        assert_eq!(tracker.wraparounds, 1);
+    }
+
+    #[test]
+    fn a_short_restart_is_not_taken_for_reordering() {
+        let mut tracker = SequenceTracker::default();
+        push_all(&mut tracker, &(1000..1100).collect::<Vec<_>>());
+        let events = push_all(&mut tracker, &[0, 1, 3]);
+        assert_eq!(
+            events,
+            [
+                SequenceEvent::Wraparound,
+                SequenceEvent::InOrder,
+                SequenceEvent::Gap(1),
+            ]
+        );
+        assert_eq!(
+            (tracker.lost, tracker.reordered, tracker.duplicates),
+            (1, 0, 0)
+        );
+    }
+
+    #[test]
+    fn a_restart_just_past_the_reorder_window_is_a_restart() {
+        let mut tracker = SequenceTracker::default();
+        push_all(&mut tracker, &(1..=100).collect::<Vec<_>>());
+        assert_eq!(tracker.push(36), SequenceEvent::Wraparound);
+        assert_eq!(tracker.push(37), SequenceEvent::InOrder);
+        assert_eq!(tracker.duplicates, 0);
+    }
+
+    #[test]
+    fn replayed_arrivals_keep_the_recorded_gaps() {
+        let mut clock = ReplayClock::default();
+        let first = clock.arrival(Duration::from_secs(1_700_000_000));
+        let later = clock.arrival(Duration::from_millis(1_700_000_000_250));
+        assert_eq!(later.instant - first.instant, Duration::from_millis(250));
+        assert_eq!(first.system_micros(), 1_700_000_000_000_000);
+        assert_eq!(later.system_micros(), 1_700_000_000_250_000);
+        let restarted = clock.arrival(Duration::from_secs(1_700_000_000));
+        assert_eq!(restarted.instant, later.instant);
+    }
+}
@@ -0,0 +1,249 @@
+//! What the parsers read from.
+//!
+//! Byte streams are anything [`AsyncRead`] + [`AsyncWrite`]: a `TcpStream`, a `UnixStream`,
+//! a serial tty opened with [`open_serial`] or a [`tokio::io::duplex`] pipe in tests.
+//! Datagram sources hand out one telegram at a time and can be turned into a byte stream
+//! with [`DatagramReader`], so the stream parsers run over UDP as well.
+
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::UdpSocket,
+    sync::mpsc,
+};
+
+use crate::receiver::Datagram;
+
+/// Largest datagram a source is asked to receive.
+const MAX_DATAGRAM: usize = 65535;
+
+/// A bidirectional byte stream the CoLa and Compact parsers can run over.
+pub trait ByteTransport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ByteTransport for T {}
+
+/// Something that receives whole datagrams.
+pub trait DatagramSource {
+    /// Replaces the contents of `buf` with the next datagram. Returns `false` once the
+    /// source is exhausted.
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<io::Result<bool>>;
+}
+
+/// Receives into `buf` through a [`ReadBuf`] over its spare capacity.
+fn poll_recv_into(
+    buf: &mut Vec<u8>,
+    recv: impl FnOnce(&mut ReadBuf<'_>) -> Poll<io::Result<()>>,
+) -> Poll<io::Result<bool>> {
+    buf.resize(MAX_DATAGRAM, 0);
+    let mut read = ReadBuf::new(buf);
+    let result = recv(&mut read);
+    let filled = read.filled().len();
+    buf.truncate(if result.is_ready() { filled } else { 0 });
+    result.map_ok(|()| true)
+}
+
+impl DatagramSource for UdpSocket {
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<io::Result<bool>> {
+        poll_recv_into(buf, |read| self.poll_recv(cx, read))
+    }
+}
+
+#[cfg(unix)]
+impl DatagramSource for tokio::net::UnixDatagram {
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<io::Result<bool>> {
+        poll_recv_into(buf, |read| self.poll_recv(cx, read))
+    }
+}
+
+/// The channel of one sensor of a [`UdpReceiver`](crate::UdpReceiver).
+impl DatagramSource for mpsc::Receiver<Datagram> {
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<io::Result<bool>> {
+        Poll::Ready(Ok(match ready!(self.poll_recv(cx)) {
+            Some(datagram) => {
+                *buf = datagram.bytes;
+                true
+            }
+            None => false,
+        }))
+    }
+}
+
+impl DatagramSource for mpsc::Receiver<Vec<u8>> {
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<io::Result<bool>> {
+        Poll::Ready(Ok(match ready!(self.poll_recv(cx)) {
+            Some(datagram) => {
+                *buf = datagram;
+                true
+            }
+            None => false,
+        }))
+    }
+}
+
+/// Reads the datagrams of a source back to back as one byte stream. A lost datagram leaves
+/// the reader in the middle of a telegram, so parsers on top should resynchronise on the
+/// start marker.
+pub struct DatagramReader<D> {
+    source: D,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl<D: DatagramSource> DatagramReader<D> {
+    pub fn new(source: D) -> Self {
+        Self {
+            source,
+            current: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.source
+    }
+}
+
+impl<D: DatagramSource + Unpin> AsyncRead for DatagramReader<D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.position >= this.current.len() {
+            if !ready!(this.source.poll_recv_datagram(cx, &mut this.current))? {
+                return Poll::Ready(Ok(()));
+            }
+            this.position = 0;
+        }
+        let n = buf.remaining().min(this.current.len() - this.position);
+        buf.put_slice(&this.current[this.position..this.position + n]);
+        this.position += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Opens a serial tty for reading and writing. Baud rate and framing are whatever the
+/// device is configured to, e.g. with `stty -F /dev/ttyUSB0 115200 raw`.
+pub async fn open_serial(path: impl AsRef<Path>) -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open(path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+    use super::*;
+    use crate::{read_sized_message, CompactHeader, CompactMessage};
+
+    fn reader(datagrams: &[&[u8]]) -> DatagramReader<mpsc::Receiver<Vec<u8>>> {
+        let (sender, receiver) = mpsc::channel(datagrams.len().max(1));
+        for datagram in datagrams {
+            sender.try_send(datagram.to_vec()).unwrap();
+        }
+        DatagramReader::new(receiver)
+    }
+
+    #[tokio::test]
+    async fn sized_messages_are_read_across_partial_writes() {
+        let (mut sensor, mut connection) = tokio::io::duplex(4);
+        let writer = tokio::spawn(async move {
+            for chunk in [&b"ab"[..], b"cdefg", b"hij"] {
+                sensor.write_all(chunk).await.unwrap();
+            }
+        });
+        assert_eq!(
+            read_sized_message(&mut connection, 6).await.unwrap(),
+            b"abcdef"
+        );
+        assert_eq!(read_sized_message(&mut connection, 0).await.unwrap(), b"");
+        assert_eq!(
+            read_sized_message(&mut connection, 4).await.unwrap(),
+            b"ghij"
+        );
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sized_messages_cut_short_are_an_error() {
+        let (mut sensor, mut connection) = tokio::io::duplex(16);
+        sensor.write_all(b"abc").await.unwrap();
+        drop(sensor);
+        let error = read_sized_message(&mut connection, 4).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn datagrams_are_read_back_to_back() {
+        let mut reader = reader(&[b"abc", b"", b"de", b"fghij"]);
+        let mut first = [0; 4];
+        reader.read_exact(&mut first).await.unwrap();
+        assert_eq!(&first, b"abcd");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"efghij");
+    }
+
+    #[tokio::test]
+    async fn reads_never_span_two_datagrams() {
+        let mut reader = reader(&[b"abc", b"de"]);
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(reader.read(&mut buf[..1]).await.unwrap(), 1);
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 1);
+        assert_eq!(&buf[..1], b"e");
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn telegrams_split_over_datagrams_are_parsed() {
+        let telegram = CompactMessage::distance(CompactHeader::new(1, 2, 4), []);
+        let bytes = telegram.to_bytes();
+        let (head, tail) = bytes.split_at(5);
+        let mut buffer = BufReader::new(reader(&[head, tail, &bytes]));
+        for _ in 0..2 {
+            let message = CompactMessage::read_message(&mut buffer).await.unwrap();
+            assert_eq!(message, telegram);
+        }
+    }
+
+    #[tokio::test]
+    async fn udp_sockets_are_datagram_sources() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sensor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sensor.connect(socket.local_addr().unwrap()).await.unwrap();
+        sensor.send(b"first").await.unwrap();
+        sensor.send(b"second").await.unwrap();
+        let mut reader = DatagramReader::new(socket);
+        let mut buf = [0; 11];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"firstsecond");
+    }
+}
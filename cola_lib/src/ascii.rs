@@ -0,0 +1,234 @@
+//! Token level CoLa-A encoding.
+//!
+//! A CoLa-A payload is a list of space-separated tokens. Unsigned integers are hex, signed
+//! integers are decimals with an explicit `+` or `-`, floats are the hex of their IEEE 754
+//! bits and strings are their hex length followed by the raw characters, which may contain
+//! spaces.
+
+use std::str;
+
+use crate::error::CoLaError;
+
+pub(crate) const SPC: u8 = 0x20;
+
+/// Appends ` {value:X}`.
+pub fn write_hex(value: u64, data: &mut Vec<u8>) {
+    data.push(SPC);
+    data.extend_from_slice(format!("{value:X}").as_bytes());
+}
+
+/// Appends ` +{value}` or ` -{value}`.
+pub fn write_signed(value: i64, data: &mut Vec<u8>) {
+    data.push(SPC);
+    data.extend_from_slice(format!("{value:+}").as_bytes());
+}
+
+/// Forward reader over the tokens of a CoLa-A payload.
+#[derive(Clone, Debug)]
+pub struct AsciiReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> AsciiReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Offset of the next unread byte.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    /// Moves to the start of the next token.
+    pub fn skip_spaces(&mut self) {
+        while self.data.get(self.position) == Some(&SPC) {
+            self.position += 1;
+        }
+    }
+
+    /// The next space-separated token.
+    pub fn token(&mut self) -> Result<&'a str, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        while self.data.get(self.position).is_some_and(|b| *b != SPC) {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(CoLaError::truncated(1, 0).offset_by(start));
+        }
+        str::from_utf8(&self.data[start..self.position])
+            .map_err(|_| CoLaError::invalid(start, "token that is not ASCII"))
+    }
+
+    /// `length` raw bytes after a single separating space.
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], CoLaError> {
+        if length == 0 {
+            return Ok(&[]);
+        }
+        if self.data.get(self.position) == Some(&SPC) {
+            self.position += 1;
+        }
+        let bytes = self
+            .data
+            .get(self.position..self.position + length)
+            .ok_or_else(|| {
+                CoLaError::truncated(length, self.data.len() - self.position)
+                    .offset_by(self.position)
+            })?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    /// An unsigned integer, hex or a `+` decimal.
+    pub fn unsigned(&mut self) -> Result<u64, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        let token = self.token()?;
+        match token.strip_prefix('+') {
+            Some(decimal) => decimal.parse().ok(),
+            None => u64::from_str_radix(token, 16).ok(),
+        }
+        .ok_or_else(|| CoLaError::invalid(start, format!("{token:?} is not an unsigned integer")))
+    }
+
+    /// A signed integer, a `+`/`-` decimal or hex in two's complement of `bits` bits.
+    pub fn signed(&mut self, bits: u32) -> Result<i64, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        let token = self.token()?;
+        let value = match token.as_bytes()[0] {
+            b'+' | b'-' => token.parse().ok(),
+            _ => u64::from_str_radix(token, 16).ok().map(|v| {
+                let shift = 64 - bits;
+                ((v << shift) as i64) >> shift
+            }),
+        };
+        value.ok_or_else(|| CoLaError::invalid(start, format!("{token:?} is not a signed integer")))
+    }
+
+    /// [`AsciiReader::unsigned`] narrowed to `T`.
+    pub fn read_unsigned<T: TryFrom<u64>>(&mut self) -> Result<T, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        let value = self.unsigned()?;
+        T::try_from(value)
+            .map_err(|_| CoLaError::invalid(start, format!("{value} is out of range")))
+    }
+
+    /// [`AsciiReader::signed`] narrowed to `T`.
+    pub fn read_signed<T: TryFrom<i64>>(&mut self) -> Result<T, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        let value = self.signed(8 * size_of::<T>() as u32)?;
+        T::try_from(value)
+            .map_err(|_| CoLaError::invalid(start, format!("{value} is out of range")))
+    }
+
+    /// A float, the hex of its bits or a `+`/`-` decimal.
+    pub fn read_float(&mut self) -> Result<f32, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        let token = self.token()?;
+        match token.as_bytes()[0] {
+            b'+' | b'-' => token.parse().ok(),
+            _ => u32::from_str_radix(token, 16).ok().map(f32::from_bits),
+        }
+        .ok_or_else(|| CoLaError::invalid(start, format!("{token:?} is not a float")))
+    }
+
+    /// [`AsciiReader::read_float`] for doubles.
+    pub fn read_double(&mut self) -> Result<f64, CoLaError> {
+        self.skip_spaces();
+        let start = self.position;
+        let token = self.token()?;
+        match token.as_bytes()[0] {
+            b'+' | b'-' => token.parse().ok(),
+            _ => u64::from_str_radix(token, 16).ok().map(f64::from_bits),
+        }
+        .ok_or_else(|| CoLaError::invalid(start, format!("{token:?} is not a double")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsigned_integers_are_uppercase_hex() {
+        let mut data = Vec::new();
+        for value in [0, 0xA, 0x1F4, u64::MAX] {
+            write_hex(value, &mut data);
+        }
+        assert_eq!(data, b" 0 A 1F4 FFFFFFFFFFFFFFFF");
+        let mut input = AsciiReader::new(&data);
+        for value in [0, 0xA, 0x1F4, u64::MAX] {
+            assert_eq!(input.unsigned().unwrap(), value);
+        }
+        assert!(input.rest().is_empty());
+        assert!(input.unsigned().is_err());
+    }
+
+    #[test]
+    fn unsigned_integers_accept_plus_decimals_and_lowercase_hex() {
+        let mut input = AsciiReader::new(b"+500 ff  +0");
+        assert_eq!(input.unsigned().unwrap(), 500);
+        assert_eq!(input.unsigned().unwrap(), 0xFF);
+        assert_eq!(input.unsigned().unwrap(), 0);
+        assert!(AsciiReader::new(b"-1").unsigned().is_err());
+        assert!(AsciiReader::new(b"G").unsigned().is_err());
+    }
+
+    #[test]
+    fn signed_integers_are_signed_decimals() {
+        let mut data = Vec::new();
+        for value in [0, 7, -450000, i64::MIN] {
+            write_signed(value, &mut data);
+        }
+        assert_eq!(data, b" +0 +7 -450000 -9223372036854775808");
+        let mut input = AsciiReader::new(&data);
+        for value in [0, 7, -450000, i64::MIN] {
+            assert_eq!(input.signed(64).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn signed_hex_is_twos_complement_of_the_width() {
+        let mut input = AsciiReader::new(b"FFF92230 FFF92230 FF 7F");
+        assert_eq!(input.signed(32).unwrap(), -450000);
+        assert_eq!(input.signed(64).unwrap(), 0xFFF92230);
+        assert_eq!(input.read_signed::<i8>().unwrap(), -1);
+        assert_eq!(input.read_signed::<i8>().unwrap(), 127);
+    }
+
+    #[test]
+    fn narrowing_rejects_values_out_of_range() {
+        assert_eq!(AsciiReader::new(b"FF").read_unsigned::<u8>().unwrap(), 255);
+        assert!(AsciiReader::new(b"100").read_unsigned::<u8>().is_err());
+        assert!(AsciiReader::new(b"+128").read_signed::<i8>().is_err());
+        assert!(AsciiReader::new(b"-129").read_signed::<i8>().is_err());
+    }
+
+    #[test]
+    fn floats_are_their_bits_or_decimals() {
+        let mut input = AsciiReader::new(b"3F800000 -2.5 +0.25 4004000000000000");
+        assert_eq!(input.read_float().unwrap(), 1.0);
+        assert_eq!(input.read_float().unwrap(), -2.5);
+        assert_eq!(input.read_double().unwrap(), 0.25);
+        assert_eq!(input.read_double().unwrap(), 2.5);
+        assert!(AsciiReader::new(b"+x").read_float().is_err());
+    }
+
+    #[test]
+    fn raw_bytes_may_contain_spaces() {
+        let mut input = AsciiReader::new(b" 5 a b c 1");
+        assert_eq!(input.unsigned().unwrap(), 5);
+        assert_eq!(input.bytes(5).unwrap(), b"a b c");
+        assert_eq!(input.unsigned().unwrap(), 1);
+        assert!(AsciiReader::new(b" ab").bytes(3).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Forward reading of CoLa-B payloads.
+
+use crate::error::CoLaError;
+
+/// Cursor over a CoLa-B payload. Every error it returns carries the offset of the failing
+/// value from the start of the payload.
+#[derive(Clone, Debug)]
+pub struct BinaryReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Offset of the next unread byte.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    /// The next `length` bytes.
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], CoLaError> {
+        let rest = self.rest();
+        if rest.len() < length {
+            return Err(CoLaError::truncated(length, rest.len()).offset_by(self.position));
+        }
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], CoLaError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// The bytes up to the next `delimiter`, which is consumed but not returned, or up to
+    /// the end.
+    pub fn until(&mut self, delimiter: u8) -> &'a [u8] {
+        let rest = self.rest();
+        let length = rest
+            .iter()
+            .position(|b| *b == delimiter)
+            .unwrap_or(rest.len());
+        self.position += (length + 1).min(rest.len());
+        &rest[..length]
+    }
+
+    /// The next space-terminated token, e.g. the command type or name.
+    pub fn token(&mut self) -> Result<&'a str, CoLaError> {
+        let start = self.position;
+        let token = self.until(b' ');
+        if token.is_empty() {
+            return Err(CoLaError::truncated(1, 0).offset_by(start));
+        }
+        std::str::from_utf8(token).map_err(|_| CoLaError::invalid(start, "token that is not ASCII"))
+    }
+
+    pub fn skip(&mut self, length: usize) -> Result<(), CoLaError> {
+        self.take(length).map(|_| ())
+    }
+}
@@ -0,0 +1,467 @@
+//! CoLa-B and CoLa-A framing as tokio-util codecs.
+//!
+//! A CoLa-B frame is `02 02 02 02`, the payload length as a big-endian u32, the payload and
+//! an XOR checksum over the payload. Bytes that do not start a frame are skipped up to the
+//! next `02 02 02 02`, so a stream recovers from garbage without the caller doing anything.
+//! A frame that fails its checksum is handed out as a [`CoLaError::ChecksumMismatch`] item
+//! rather than a decoder error, because `Framed` stops reading after a decoder error.
+//!
+//! A CoLa-A frame is the ASCII payload between an STX (`02`) and an ETX (`03`).
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{cola_a::ColaMessageRaw, error::CoLaError};
+
+pub const COLA_B_STX: [u8; 4] = [0x02; 4];
+pub const COLA_A_STX: u8 = 0x02;
+pub const COLA_A_ETX: u8 = 0x03;
+const COLA_B_HEADER_SIZE: usize = 8;
+
+/// Largest payload accepted before the length is assumed to be garbage, in either dialect.
+pub const COLA_B_MAX_PAYLOAD: usize = 1 << 20;
+
+/// XOR of every payload byte. The SICK CoLa-B telegram definition leaves the start marker and
+/// the length out of the checksum.
+pub fn cola_b_checksum(payload: &[u8]) -> u8 {
+    payload.iter().fold(0, |check, b| check ^ b)
+}
+
+/// Writes `payload` as one CoLa-B frame.
+pub fn write_cola_b_frame(payload: &[u8], out: &mut impl BufMut) {
+    out.put_slice(&COLA_B_STX);
+    out.put_u32(payload.len() as u32);
+    out.put_slice(payload);
+    out.put_u8(cola_b_checksum(payload));
+}
+
+/// Writes `payload` as one CoLa-A frame.
+pub fn write_cola_a_frame(payload: &[u8], out: &mut impl BufMut) {
+    out.put_u8(COLA_A_STX);
+    out.put_slice(payload);
+    out.put_u8(COLA_A_ETX);
+}
+
+/// A frame payload, or why a frame was dropped.
+pub type CoLaFrame = Result<ColaMessageRaw, CoLaError>;
+
+#[derive(Clone, Debug, Default)]
+pub struct CoLaBCodec {
+    /// Bytes skipped while looking for the start of a frame.
+    pub discarded: u64,
+    /// Frames dropped because their checksum did not match.
+    pub mismatches: u64,
+    /// Stream offset of the first byte not yet consumed.
+    pub position: u64,
+}
+
+impl CoLaBCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops everything before the next start marker, keeping a partial marker at the end.
+    fn synchronise(&mut self, src: &mut BytesMut) {
+        let start = src
+            .windows(COLA_B_STX.len())
+            .position(|w| w == COLA_B_STX)
+            .unwrap_or_else(|| {
+                let partial = src.iter().rev().take_while(|b| **b == 0x02).count();
+                src.len() - partial.min(COLA_B_STX.len() - 1)
+            });
+        self.discarded += start as u64;
+        self.position += start as u64;
+        src.advance(start);
+    }
+}
+
+impl Decoder for CoLaBCodec {
+    type Item = CoLaFrame;
+    type Error = CoLaError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<CoLaFrame>, CoLaError> {
+        loop {
+            self.synchronise(src);
+            if src.len() < COLA_B_HEADER_SIZE {
+                return Ok(None);
+            }
+            let length = u32::from_be_bytes(src[4..8].try_into().unwrap()) as usize;
+            if length > COLA_B_MAX_PAYLOAD {
+                // Not a real header, look for the next one.
+                self.discarded += 1;
+                self.position += 1;
+                src.advance(1);
+                continue;
+            }
+            let total = COLA_B_HEADER_SIZE + length + 1;
+            if src.len() < total {
+                src.reserve(total - src.len());
+                return Ok(None);
+            }
+            let payload = &src[COLA_B_HEADER_SIZE..total - 1];
+            let (expected, computed) = (src[total - 1], cola_b_checksum(payload));
+            if expected == computed {
+                let payload = payload.to_vec();
+                self.position += total as u64;
+                src.advance(total);
+                return Ok(Some(Ok(payload)));
+            }
+            let error = CoLaError::ChecksumMismatch {
+                offset: self.position,
+                expected,
+                computed,
+            };
+            self.mismatches += 1;
+            // Drop the whole frame when another one follows right after it. Otherwise the
+            // length may be what got corrupted, so only drop the marker and look again.
+            let skip = match src[total..]
+                .iter()
+                .take(COLA_B_STX.len())
+                .all(|b| *b == 0x02)
+            {
+                true => total,
+                false => COLA_B_STX.len(),
+            };
+            self.discarded += skip as u64;
+            self.position += skip as u64;
+            src.advance(skip);
+            return Ok(Some(Err(error)));
+        }
+    }
+}
+
+impl Encoder<ColaMessageRaw> for CoLaBCodec {
+    type Error = CoLaError;
+
+    fn encode(&mut self, item: ColaMessageRaw, dst: &mut BytesMut) -> Result<(), CoLaError> {
+        self.encode(item.as_slice(), dst)
+    }
+}
+
+impl Encoder<&[u8]> for CoLaBCodec {
+    type Error = CoLaError;
+
+    fn encode(&mut self, item: &[u8], dst: &mut BytesMut) -> Result<(), CoLaError> {
+        dst.reserve(COLA_B_HEADER_SIZE + item.len() + 1);
+        write_cola_b_frame(item, dst);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CoLaACodec {
+    /// Bytes skipped while looking for the start of a frame.
+    pub discarded: u64,
+    /// Stream offset of the first byte not yet consumed.
+    pub position: u64,
+}
+
+impl CoLaACodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Decoder for CoLaACodec {
+    type Item = ColaMessageRaw;
+    type Error = CoLaError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ColaMessageRaw>, CoLaError> {
+        loop {
+            let start = src
+                .iter()
+                .position(|b| *b == COLA_A_STX)
+                .unwrap_or(src.len());
+            self.discarded += start as u64;
+            self.position += start as u64;
+            src.advance(start);
+            let Some(end) = src.iter().position(|b| *b == COLA_A_ETX) else {
+                if src.len() > COLA_B_MAX_PAYLOAD {
+                    self.discarded += 1;
+                    self.position += 1;
+                    src.advance(1);
+                    continue;
+                }
+                return Ok(None);
+            };
+            let frame = src.split_to(end + 1);
+            self.position += frame.len() as u64;
+            // A second STX means the first frame was cut short, keep the newer one.
+            let payload = match frame[1..].iter().rposition(|b| *b == COLA_A_STX) {
+                Some(restart) => {
+                    self.discarded += restart as u64 + 1;
+                    &frame[restart + 2..end]
+                }
+                None => &frame[1..end],
+            };
+            return Ok(Some(payload.to_vec()));
+        }
+    }
+}
+
+impl Encoder<ColaMessageRaw> for CoLaACodec {
+    type Error = CoLaError;
+
+    fn encode(&mut self, item: ColaMessageRaw, dst: &mut BytesMut) -> Result<(), CoLaError> {
+        self.encode(item.as_slice(), dst)
+    }
+}
+
+impl Encoder<&[u8]> for CoLaACodec {
+    type Error = CoLaError;
+
+    fn encode(&mut self, item: &[u8], dst: &mut BytesMut) -> Result<(), CoLaError> {
+        dst.reserve(item.len() + 2);
+        write_cola_a_frame(item, dst);
+        Ok(())
+    }
+}
+
+/// Which CoLa encoding a connection speaks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CoLaDialect {
+    /// CoLa-B, binary payloads.
+    #[default]
+    Binary,
+    /// CoLa-A, ASCII payloads.
+    Ascii,
+}
+
+/// Frames either dialect, picked per connection.
+#[derive(Clone, Debug, Default)]
+pub struct CoLaCodec {
+    pub dialect: CoLaDialect,
+    pub binary: CoLaBCodec,
+    pub ascii: CoLaACodec,
+}
+
+impl CoLaCodec {
+    pub fn new(dialect: CoLaDialect) -> Self {
+        Self {
+            dialect,
+            ..Default::default()
+        }
+    }
+
+    /// Stream offset of the first byte not yet consumed.
+    pub fn position(&self) -> u64 {
+        match self.dialect {
+            CoLaDialect::Binary => self.binary.position,
+            CoLaDialect::Ascii => self.ascii.position,
+        }
+    }
+}
+
+impl Decoder for CoLaCodec {
+    type Item = CoLaFrame;
+    type Error = CoLaError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<CoLaFrame>, CoLaError> {
+        match self.dialect {
+            CoLaDialect::Binary => self.binary.decode(src),
+            CoLaDialect::Ascii => Ok(self.ascii.decode(src)?.map(Ok)),
+        }
+    }
+}
+
+impl Encoder<ColaMessageRaw> for CoLaCodec {
+    type Error = CoLaError;
+
+    fn encode(&mut self, item: ColaMessageRaw, dst: &mut BytesMut) -> Result<(), CoLaError> {
+        self.encode(item.as_slice(), dst)
+    }
+}
+
+impl Encoder<&[u8]> for CoLaCodec {
+    type Error = CoLaError;
+
+    fn encode(&mut self, item: &[u8], dst: &mut BytesMut) -> Result<(), CoLaError> {
+        match self.dialect {
+            CoLaDialect::Binary => self.binary.encode(item, dst),
+            CoLaDialect::Ascii => self.ascii.encode(item, dst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_cola_b_frame(payload, &mut out);
+        out
+    }
+
+    /// Payloads of every complete frame in `src`, which must all pass their checksum.
+    fn decode_all(codec: &mut CoLaBCodec, src: &mut BytesMut) -> Vec<ColaMessageRaw> {
+        let mut payloads = Vec::new();
+        while let Some(frame) = codec.decode(src).unwrap() {
+            payloads.push(frame.unwrap());
+        }
+        payloads
+    }
+
+    #[test]
+    fn frames_split_across_calls_are_decoded_once_complete() {
+        let bytes = frame(b"sRA LMDscandata");
+        for split in 1..bytes.len() {
+            let mut codec = CoLaBCodec::new();
+            let mut src = BytesMut::from(&bytes[..split]);
+            assert!(codec.decode(&mut src).unwrap().is_none());
+            src.extend_from_slice(&bytes[split..]);
+            let payload = codec.decode(&mut src).unwrap().unwrap().unwrap();
+            assert_eq!(payload, b"sRA LMDscandata");
+            assert!(src.is_empty());
+            assert_eq!(codec.position, bytes.len() as u64);
+        }
+    }
+
+    #[test]
+    fn garbage_before_the_start_marker_is_discarded() {
+        let garbage = b"\x02\x02junk\x02\x02\x02";
+        let mut src = BytesMut::from(&garbage[..]);
+        src.extend_from_slice(&frame(b"sAN Run"));
+        let mut codec = CoLaBCodec::new();
+        assert_eq!(decode_all(&mut codec, &mut src), [b"sAN Run"]);
+        assert_eq!(codec.discarded, garbage.len() as u64);
+        assert_eq!(codec.mismatches, 0);
+    }
+
+    #[test]
+    fn garbage_lengths_are_skipped() {
+        let mut src = BytesMut::from(&COLA_B_STX[..]);
+        src.extend_from_slice(&u32::MAX.to_be_bytes());
+        src.extend_from_slice(&frame(b"sAN Run"));
+        let mut codec = CoLaBCodec::new();
+        assert_eq!(decode_all(&mut codec, &mut src), [b"sAN Run"]);
+        assert_eq!(codec.discarded, 8);
+    }
+
+    #[test]
+    fn frames_sharing_a_buffer_are_decoded_in_order() {
+        let mut src = BytesMut::new();
+        for payload in [&b"first"[..], b"", b"third"] {
+            src.extend_from_slice(&frame(payload));
+        }
+        let mut codec = CoLaBCodec::new();
+        assert_eq!(
+            decode_all(&mut codec, &mut src),
+            [&b"first"[..], b"", b"third"]
+        );
+        assert_eq!(codec.discarded, 0);
+    }
+
+    #[test]
+    fn encoded_frames_decode_to_themselves() {
+        let payloads = [
+            b"sMN SetAccessMode \x03\xf4\x72\x47\x44".to_vec(),
+            vec![0x02; 9],
+        ];
+        let mut codec = CoLaCodec::new(CoLaDialect::Binary);
+        let mut dst = BytesMut::new();
+        for payload in payloads.clone() {
+            codec.encode(payload, &mut dst).unwrap();
+        }
+        assert_eq!(&dst[..4], COLA_B_STX);
+        assert_eq!(&dst[4..8], 23_u32.to_be_bytes());
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut dst).unwrap() {
+            frames.push(frame.unwrap());
+        }
+        assert_eq!(frames, payloads);
+        assert_eq!(codec.position(), 23 + 9 + 2 * 9);
+    }
+
+    fn decode_ascii(codec: &mut CoLaACodec, src: &mut BytesMut) -> Vec<ColaMessageRaw> {
+        let mut payloads = Vec::new();
+        while let Some(payload) = codec.decode(src).unwrap() {
+            payloads.push(payload);
+        }
+        payloads
+    }
+
+    #[test]
+    fn ascii_frames_are_wrapped_in_stx_and_etx() {
+        let mut codec = CoLaCodec::new(CoLaDialect::Ascii);
+        let mut dst = BytesMut::new();
+        codec.encode(&b"sRN LMPscancfg"[..], &mut dst).unwrap();
+        assert_eq!(&dst[..], b"\x02sRN LMPscancfg\x03");
+        let frame = codec.decode(&mut dst).unwrap().unwrap().unwrap();
+        assert_eq!(frame, b"sRN LMPscancfg");
+        assert_eq!(codec.position(), 16);
+    }
+
+    #[test]
+    fn ascii_frames_split_across_calls_are_decoded_once_complete() {
+        let mut bytes = Vec::new();
+        write_cola_a_frame(b"sAN mLMPsetscancfg 0 1 1388", &mut bytes);
+        for split in 1..bytes.len() {
+            let mut codec = CoLaACodec::new();
+            let mut src = BytesMut::from(&bytes[..split]);
+            assert_eq!(codec.decode(&mut src).unwrap(), None);
+            src.extend_from_slice(&bytes[split..]);
+            assert_eq!(
+                codec.decode(&mut src).unwrap().unwrap(),
+                b"sAN mLMPsetscancfg 0 1 1388"
+            );
+            assert_eq!(codec.discarded, 0);
+        }
+    }
+
+    #[test]
+    fn ascii_garbage_and_cut_frames_are_discarded() {
+        let mut src =
+            BytesMut::from(&b"noise\x03\x02sRA cut\x02sRA LMDscandata\x03\x02sEA x\x03"[..]);
+        let mut codec = CoLaACodec::new();
+        assert_eq!(
+            decode_ascii(&mut codec, &mut src),
+            [&b"sRA LMDscandata"[..], b"sEA x"]
+        );
+        assert_eq!(codec.discarded, 6 + 8);
+        assert_eq!(codec.position, 38);
+    }
+
+    #[test]
+    fn corrupted_frames_are_reported_before_the_next_one() {
+        let mut corrupted = frame(b"sRA LMDscandata 1");
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0x40;
+        let mut src = BytesMut::from(&corrupted[..]);
+        src.extend_from_slice(&frame(b"sRA LMDscandata 2"));
+        let mut codec = CoLaBCodec::new();
+        match codec.decode(&mut src) {
+            Ok(Some(Err(CoLaError::ChecksumMismatch {
+                offset: 0,
+                expected,
+                computed,
+            }))) => assert_eq!(expected ^ computed, 0x40),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(decode_all(&mut codec, &mut src), [b"sRA LMDscandata 2"]);
+        assert_eq!(codec.mismatches, 1);
+        assert_eq!(codec.discarded, corrupted.len() as u64);
+    }
+
+    #[test]
+    fn corrupted_lengths_resync_on_the_next_marker() {
+        let mut corrupted = frame(b"sRA LMDscandata 1");
+        corrupted[7] -= 1;
+        let mut src = BytesMut::from(&corrupted[..]);
+        src.extend_from_slice(&frame(b"sRA LMDscandata 2"));
+        let mut codec = CoLaBCodec::new();
+        assert!(matches!(
+            codec.decode(&mut src),
+            Ok(Some(Err(CoLaError::ChecksumMismatch { .. })))
+        ));
+        assert_eq!(decode_all(&mut codec, &mut src), [b"sRA LMDscandata 2"]);
+    }
+
+    #[test]
+    fn checksums_leave_out_the_marker_and_length() {
+        let bytes = frame(b"sMN Run");
+        assert_eq!(bytes[8..bytes.len() - 1], *b"sMN Run");
+        assert_eq!(bytes[bytes.len() - 1], cola_b_checksum(b"sMN Run"));
+        assert_eq!(cola_b_checksum(b"sMN Run"), 0x19);
+    }
+}
@@ -1,22 +1,18 @@
-use std::{
-    clone,
-    collections::HashSet,
-    future::Future,
-    io::{Read, Write},
-    marker::PhantomData,
-    pin::Pin,
-    str,
-    thread::sleep,
-    time::Duration,
-};
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
 
-use tokio::{io::AsyncWriteExt, net::TcpStream, sync::broadcast::error::RecvError};
+use crate::{
+    ascii::{write_hex, write_signed, AsciiReader},
+    binary::BinaryReader,
+    codec::{write_cola_b_frame, CoLaCodec, CoLaDialect, CoLaFrame},
+    error::CoLaError,
+};
 // use tower::Service;
 
 // Sec 2.4
-const STX: u8 = 0x02; // Start
 const SPC: u8 = 0x20; // Space
-const ETX: u8 = 0x03; //End
+const SFA: [u8; 3] = *b"sFA"; // Error answer
 
 pub struct CoLaUtil;
 impl CoLaUtil {
@@ -27,7 +23,7 @@ impl CoLaUtil {
         cmd_type[1].write_to_data(&mut out);
         cmd_type[2].write_to_data(&mut out);
         SPC.write_to_data(&mut out);
-        cmd.to_string().write_to_data(&mut out);
+        out.extend_from_slice(cmd.as_bytes());
         SPC.write_to_data(&mut out);
         // dbg!(&out);
         out
@@ -37,40 +33,50 @@ impl CoLaUtil {
         CoLaUtil::vec_from_command(input.0, input.1)
     }
 
-    pub fn setup_vec(input: &mut Vec<u8>) -> Vec<u8> {
-        let len: u32 = input.len().try_into().unwrap();
-        let mut output: Vec<u8> = vec![2, 2, 2, 2];
-        let len_str: [u8; 4] = len.to_be_bytes();
-        output.append(&mut len_str.to_vec());
-        let mut check: u8 = 0x00;
-        input.iter().for_each(|x| check ^= x);
-        output.append(input);
-        output.push(check);
+    /// CoLa-A counterpart of [`CoLaUtil::vec_from_command`]. The arguments follow with a
+    /// leading space each.
+    pub fn ascii_from_command(cmd_type: [u8; 3], cmd: &str) -> Vec<u8> {
+        let mut out = cmd_type.to_vec();
+        out.push(SPC);
+        out.extend_from_slice(cmd.as_bytes());
+        out
+    }
+
+    /// Wraps `input` in a CoLa-B frame.
+    pub fn setup_vec(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len() + 9);
+        write_cola_b_frame(input, &mut output);
         output
     }
 
-    pub async fn read_message(stream: &mut TcpStream) -> Option<ColaMessageRaw> {
-        let _ = stream.readable().await;
-        let mut data: [u8; 8] = [0; 8];
-        match stream.try_read(&mut data) {
-            Ok(8) => {}
-            _ => return None,
-        }
-        let bytes: [u8; 4] = data[4..].try_into().unwrap();
-        let length: usize = u32::from_be_bytes(bytes).try_into().unwrap();
-        let mut data = vec![0_u8; length];
-        let out = stream.try_read(&mut data);
-        let _ = stream.try_read(&mut [0_u8; 1]); //Checksum
-        match out {
-            Ok(l) => {
-                if l == length {
-                    Some(data)
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
+    /// Frames a byte stream, e.g. a `TcpStream` or a serial tty, in the given dialect.
+    pub fn framed<T: AsyncRead + AsyncWrite>(io: T, dialect: CoLaDialect) -> Framed<T, CoLaCodec> {
+        Framed::new(io, CoLaCodec::new(dialect))
+    }
+
+    /// Next frame of a framed stream, `None` once the stream ends.
+    pub async fn read_message<S>(stream: &mut S) -> Option<CoLaFrame>
+    where
+        S: Stream<Item = Result<CoLaFrame, CoLaError>> + Unpin,
+    {
+        Some(stream.next().await?.and_then(|frame| frame))
+    }
+
+    /// Fails with the error of an `sFA` answer.
+    pub fn check_answer(msg: &[u8]) -> Result<(), CoLaError> {
+        let Some(rest) = msg.strip_prefix(&SFA) else {
+            return Ok(());
+        };
+        // Exactly one space separates the code, which may itself be 0x20 or 0x0A.
+        let rest = rest.strip_prefix(&[SPC]).unwrap_or(rest);
+        let mut input = BinaryReader::new(msg);
+        input.skip(msg.len() - rest.len())?;
+        let offset = input.position();
+        let code = match input.rest().len() {
+            1 => u8::read_from(&mut input)? as u16,
+            _ => u16::read_from(&mut input)?,
+        };
+        Err(CoLaError::from_device_code(offset, code))
     }
 }
 
@@ -80,7 +86,35 @@ pub type ColaMessageRaw = Vec<u8>;
 //collect data until the message is finished
 pub trait CoLaDataType {
     fn write_to_data(&self, data: &mut Vec<u8>);
-    fn get_from_data(input: &mut Vec<u8>) -> Result<Self, Box<dyn std::error::Error>>
+    /// Decodes the value at the cursor.
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError>
+    where
+        Self: Sized;
+    /// Adapter for a payload stored in reverse and consumed with `Vec::pop`. It copies the
+    /// rest of the payload for every value, so nested decodes are quadratic.
+    #[deprecated(note = "decode with `read_from` over a `BinaryReader` instead")]
+    fn get_from_data(input: &mut Vec<u8>) -> Result<Self, CoLaError>
+    where
+        Self: Sized,
+    {
+        let forward: Vec<u8> = input.iter().rev().copied().collect();
+        let mut reader = BinaryReader::new(&forward);
+        let value = Self::read_from(&mut reader)?;
+        input.truncate(input.len() - reader.position());
+        Ok(value)
+    }
+    /// Decodes a value from the start of `data` and returns it with the bytes after it.
+    fn from_bytes(data: &[u8]) -> Result<(Self, &[u8]), CoLaError>
+    where
+        Self: Sized,
+    {
+        let mut input = BinaryReader::new(data);
+        let value = Self::read_from(&mut input)?;
+        Ok((value, input.rest()))
+    }
+    /// Appends the CoLa-A tokens of the value, each preceded by a space.
+    fn write_ascii(&self, data: &mut Vec<u8>);
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError>
     where
         Self: Sized;
 }
@@ -90,190 +124,269 @@ impl CoLaDataType for bool {
         data.push(if *self { 1 } else { 0 });
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<bool, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        match input.pop().map(|b| b == 1) {
-            Some(b) => Ok(b),
-            None => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize boolean",
-            ))),
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<bool, CoLaError> {
+        let start = input.position();
+        match input.array::<1>()? {
+            [1] => Ok(true),
+            [0] => Ok(false),
+            [value] => Err(CoLaError::invalid(start, format!("bool {value}"))),
+        }
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(*self as u64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<bool, CoLaError> {
+        input.skip_spaces();
+        let start = input.position();
+        match input.read_unsigned::<u8>()? {
+            1 => Ok(true),
+            0 => Ok(false),
+            value => Err(CoLaError::invalid(start, format!("bool {value}"))),
         }
     }
 }
 impl CoLaDataType for u8 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.push(*self);
-    }
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<u8, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        match input.pop() {
-            Some(b) => Ok(b),
-            None => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize u8",
-            ))),
-        }
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<u8, CoLaError> {
+        input.array().map(u8::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(*self as u64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<u8, CoLaError> {
+        input.read_unsigned()
     }
 }
 impl CoLaDataType for u16 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.append(&mut self.to_be_bytes().to_vec());
-    }
-
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<u16, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        // Some(u16::from_be_bytes([input.pop()?, input.pop()?]))
-        match (input.pop(), input.pop()) {
-            (Some(u1), Some(u2)) => Ok(u16::from_be_bytes([u1, u2])),
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Failed to serialize u16: {}", input.len()),
-            ))),
-        }
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<u16, CoLaError> {
+        input.array().map(u16::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(*self as u64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<u16, CoLaError> {
+        input.read_unsigned()
     }
 }
 impl CoLaDataType for u32 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.append(&mut self.to_be_bytes().to_vec());
-    }
-
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<u32, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        match (input.pop(), input.pop(), input.pop(), input.pop()) {
-            (Some(u1), Some(u2), Some(u3), Some(u4)) => Ok(u32::from_be_bytes([u1, u2, u3, u4])),
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize u32",
-            ))),
-        }
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<u32, CoLaError> {
+        input.array().map(u32::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(*self as u64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<u32, CoLaError> {
+        input.read_unsigned()
     }
 }
 impl CoLaDataType for i8 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.push(self.to_be_bytes()[0]);
-    }
-
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<i8, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        // input.pop().map(|u| [u]).map(i8::from_be_bytes)
-        match input.pop().map(|u| [u]).map(i8::from_be_bytes) {
-            Some(b) => Ok(b),
-            None => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize i8",
-            ))),
-        }
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<i8, CoLaError> {
+        input.array().map(i8::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_signed(*self as i64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<i8, CoLaError> {
+        input.read_signed()
     }
 }
 impl CoLaDataType for i16 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.append(&mut self.to_be_bytes().to_vec());
-    }
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<i16, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        match (input.pop(), input.pop()) {
-            (Some(u1), Some(u2)) => Ok(i16::from_be_bytes([u1, u2])),
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize i16",
-            ))),
-        }
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<i16, CoLaError> {
+        input.array().map(i16::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_signed(*self as i64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<i16, CoLaError> {
+        input.read_signed()
     }
 }
 impl CoLaDataType for i32 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.append(&mut self.to_be_bytes().to_vec());
-    }
-
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<i32, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        match (input.pop(), input.pop(), input.pop(), input.pop()) {
-            (Some(u1), Some(u2), Some(u3), Some(u4)) => Ok(i32::from_be_bytes([u1, u2, u3, u4])),
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize i32",
-            ))),
-        }
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<i32, CoLaError> {
+        input.array().map(i32::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_signed(*self as i64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<i32, CoLaError> {
+        input.read_signed()
+    }
+}
+impl CoLaDataType for u64 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<u64, CoLaError> {
+        input.array().map(u64::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(*self, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<u64, CoLaError> {
+        input.read_unsigned()
+    }
+}
+impl CoLaDataType for i64 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_be_bytes());
     }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<i64, CoLaError> {
+        input.array().map(i64::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_signed(*self, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<i64, CoLaError> {
+        input.read_signed()
+    }
+}
+/// `length` as the count in front of a string or array.
+///
+/// # Panics
+///
+/// When `length` does not fit `T`, because the value cannot be encoded.
+fn length_prefix<T: TryFrom<usize>>(length: usize) -> T {
+    T::try_from(length).unwrap_or_else(|_| {
+        panic!(
+            "{length} elements do not fit a {} count",
+            std::any::type_name::<T>()
+        )
+    })
 }
+
+/// A u16 length followed by the bytes, the same as [`FlexString`]. Encoding panics for
+/// strings longer than `u16::MAX` bytes.
 impl CoLaDataType for String {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.append(&mut self.as_bytes().to_vec());
+        length_prefix::<u16>(self.len()).write_to_data(data);
+        data.extend_from_slice(self.as_bytes());
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<std::string::String, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    {
-        let mut data: Vec<u8> = Vec::new();
-        let mut temp: Option<u8> = input.pop();
-        while temp.is_some() && temp != Some(SPC) {
-            data.push(temp.unwrap());
-            temp = input.pop();
-        }
-        match String::from_utf8(data) {
-            Ok(s) => Ok(s),
-            Err(e) => Err(Box::new(e)),
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<String, CoLaError> {
+        let length = u16::read_from(input)?;
+        let start = input.position();
+        String::from_utf8(input.take(length as usize)?.to_vec())
+            .map_err(|e| CoLaError::invalid(start + e.utf8_error().valid_up_to(), "invalid UTF-8"))
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(length_prefix::<u16>(self.len()).into(), data);
+        if !self.is_empty() {
+            data.push(SPC);
+            data.extend_from_slice(self.as_bytes());
         }
     }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<String, CoLaError> {
+        let length = input.read_unsigned::<u16>()?;
+        let start = input.position() + 1;
+        String::from_utf8(input.bytes(length as usize)?.to_vec())
+            .map_err(|e| CoLaError::invalid(start + e.utf8_error().valid_up_to(), "invalid UTF-8"))
+    }
 }
 impl CoLaDataType for f32 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        data.append(&mut self.to_be_bytes().to_vec());
+        data.extend_from_slice(&self.to_be_bytes());
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<f32, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        match (input.pop(), input.pop(), input.pop(), input.pop()) {
-            (Some(u1), Some(u2), Some(u3), Some(u4)) => {
-                let from_be_bytes = f32::from_be_bytes([u1, u2, u3, u4]);
-                // dbg!(&from_be_bytes);
-                Ok(from_be_bytes)
-            }
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize f32",
-            ))),
-        }
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<f32, CoLaError> {
+        input.array().map(f32::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(self.to_bits() as u64, data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<f32, CoLaError> {
+        input.read_float()
+    }
+}
+
+impl CoLaDataType for f64 {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<f64, CoLaError> {
+        input.array().map(f64::from_be_bytes)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(self.to_bits(), data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<f64, CoLaError> {
+        input.read_double()
     }
 }
 
+/// A u16 count followed by the elements, the same as [`U16Array`]. Encoding panics for more
+/// than `u16::MAX` elements.
 impl<T> CoLaDataType for Vec<T>
 where
     T: CoLaDataType + std::fmt::Debug,
 {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        (data.len() as u16).write_to_data(data);
+        length_prefix::<u16>(self.len()).write_to_data(data);
         self.iter().for_each(|c| c.write_to_data(data));
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<std::vec::Vec<T>, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    where
-        Self: Sized,
-    {
-        // dbg!(std::any::type_name::<T>());
-        let mut out = Vec::new();
-        let len = u16::get_from_data(input)?;
-        // dbg!(&len);
-        // dbg!(std::any::type_name::<T>());
-        for _ in 0..len {
-            let a = T::get_from_data(input)?;
-            // dbg!(&a);
-            out.push(a);
-        }
-        // dbg!(&out);
-        Ok(out)
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Vec<T>, CoLaError> {
+        let len = u16::read_from(input)?;
+        (0..len).map(|_| T::read_from(input)).collect()
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(length_prefix::<u16>(self.len()).into(), data);
+        self.iter().for_each(|c| c.write_ascii(data));
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Vec<T>, CoLaError> {
+        let len = input.read_unsigned::<u16>()?;
+        (0..len).map(|_| T::get_from_ascii(input)).collect()
     }
 }
 
@@ -297,29 +410,241 @@ where
 impl<T: CoLaDataType> CoLaDataType for Option<T> {
     fn write_to_data(&self, data: &mut Vec<u8>) {
         match self {
-            Some(a) => a.write_to_data(data),
-            None => {}
+            Some(a) => {
+                1u16.write_to_data(data);
+                a.write_to_data(data);
+            }
+            None => 0u16.write_to_data(data),
         }
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<
-        std::option::Option<T>,
-        std::boxed::Box<(dyn std::error::Error + 'static)>,
-    >
-    where
-        Self: Sized,
-    {
-        let ctrl = u16::get_from_data(input)?;
-        match ctrl {
-            1 => T::get_from_data(input).map(Some),
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Option<T>, CoLaError> {
+        let start = input.position();
+        match u16::read_from(input)? {
+            1 => T::read_from(input).map(Some),
+            0 => Ok(None),
+            flag => Err(CoLaError::invalid(start, format!("option flag {flag}"))),
+        }
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        match self {
+            Some(a) => {
+                write_hex(1, data);
+                a.write_ascii(data);
+            }
+            None => write_hex(0, data),
+        }
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Option<T>, CoLaError> {
+        input.skip_spaces();
+        let start = input.position();
+        match input.read_unsigned::<u16>()? {
+            1 => T::get_from_ascii(input).map(Some),
             0 => Ok(None),
-            _ => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to serialize option!",
-            ))),
+            flag => Err(CoLaError::invalid(start, format!("option flag {flag}"))),
+        }
+    }
+}
+
+/// The elements back to back, without a count.
+impl<T: CoLaDataType, const N: usize> CoLaDataType for [T; N] {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        self.iter().for_each(|c| c.write_to_data(data));
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<[T; N], CoLaError> {
+        let elements = (0..N)
+            .map(|_| T::read_from(input))
+            .collect::<Result<Vec<T>, CoLaError>>()?;
+        Ok(elements.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        self.iter().for_each(|c| c.write_ascii(data));
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<[T; N], CoLaError> {
+        let elements = (0..N)
+            .map(|_| T::get_from_ascii(input))
+            .collect::<Result<Vec<T>, CoLaError>>()?;
+        Ok(elements.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+/// Tuples are their fields in order.
+macro_rules! tuple_impl {
+    ($($name:ident)+) => {
+        impl<$($name: CoLaDataType),+> CoLaDataType for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn write_to_data(&self, data: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.write_to_data(data);)+
+            }
+
+            fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError> {
+                Ok(($($name::read_from(input)?,)+))
+            }
+
+            #[allow(non_snake_case)]
+            fn write_ascii(&self, data: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.write_ascii(data);)+
+            }
+
+            fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError> {
+                Ok(($($name::get_from_ascii(input)?,)+))
+            }
         }
+    };
+}
+
+tuple_impl!(A);
+tuple_impl!(A B);
+tuple_impl!(A B C);
+tuple_impl!(A B C D);
+tuple_impl!(A B C D E);
+tuple_impl!(A B C D E F);
+
+/// A string of exactly `N` bytes without a length, padded with spaces when shorter.
+/// Decoding drops all trailing spaces, so trailing spaces of the string itself are not
+/// preserved. Encoding panics for strings longer than `N` bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FixedString<const N: usize>(pub String);
+
+impl<const N: usize> FixedString<N> {
+    /// # Panics
+    ///
+    /// When the string is longer than `N` bytes, because the value cannot be encoded.
+    fn padded(&self) -> Vec<u8> {
+        let mut bytes = self.0.as_bytes().to_vec();
+        assert!(
+            bytes.len() <= N,
+            "{} bytes do not fit a {N} byte string",
+            bytes.len()
+        );
+        bytes.resize(N, SPC);
+        bytes
+    }
+
+    fn unpadded(bytes: &[u8]) -> &[u8] {
+        let end = bytes
+            .iter()
+            .rposition(|b| *b != SPC)
+            .map_or(0, |last| last + 1);
+        &bytes[..end]
+    }
+}
+
+impl<const N: usize> CoLaDataType for FixedString<N> {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.padded());
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError> {
+        let start = input.position();
+        String::from_utf8(Self::unpadded(input.take(N)?).to_vec())
+            .map(FixedString)
+            .map_err(|e| CoLaError::invalid(start + e.utf8_error().valid_up_to(), "invalid UTF-8"))
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        data.push(SPC);
+        data.extend_from_slice(&self.padded());
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError> {
+        let start = input.position() + 1;
+        String::from_utf8(Self::unpadded(input.bytes(N)?).to_vec())
+            .map(FixedString)
+            .map_err(|e| CoLaError::invalid(start + e.utf8_error().valid_up_to(), "invalid UTF-8"))
+    }
+}
+
+/// A string with a u16 length.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FlexString(pub String);
+
+impl CoLaDataType for FlexString {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        self.0.write_to_data(data);
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError> {
+        String::read_from(input).map(FlexString)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        self.0.write_ascii(data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError> {
+        String::get_from_ascii(input).map(FlexString)
+    }
+}
+
+/// An array with a u8 count. Encoding panics for more than `u8::MAX` elements.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct U8Array<T>(pub Vec<T>);
+
+impl<T: CoLaDataType> CoLaDataType for U8Array<T> {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        length_prefix::<u8>(self.0.len()).write_to_data(data);
+        self.0.iter().for_each(|c| c.write_to_data(data));
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError> {
+        let len = u8::read_from(input)?;
+        (0..len)
+            .map(|_| T::read_from(input))
+            .collect::<Result<_, _>>()
+            .map(U8Array)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(length_prefix::<u8>(self.0.len()).into(), data);
+        self.0.iter().for_each(|c| c.write_ascii(data));
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError> {
+        let len = input.read_unsigned::<u8>()?;
+        (0..len)
+            .map(|_| T::get_from_ascii(input))
+            .collect::<Result<_, _>>()
+            .map(U8Array)
+    }
+}
+
+/// An array with a u16 count. Encoding panics for more than `u16::MAX` elements.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct U16Array<T>(pub Vec<T>);
+
+impl<T: CoLaDataType> CoLaDataType for U16Array<T> {
+    fn write_to_data(&self, data: &mut Vec<u8>) {
+        length_prefix::<u16>(self.0.len()).write_to_data(data);
+        self.0.iter().for_each(|c| c.write_to_data(data));
+    }
+
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError> {
+        let len = u16::read_from(input)?;
+        (0..len)
+            .map(|_| T::read_from(input))
+            .collect::<Result<_, _>>()
+            .map(U16Array)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        write_hex(length_prefix::<u16>(self.0.len()).into(), data);
+        self.0.iter().for_each(|c| c.write_ascii(data));
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError> {
+        let len = input.read_unsigned::<u16>()?;
+        (0..len)
+            .map(|_| T::get_from_ascii(input))
+            .collect::<Result<_, _>>()
+            .map(U16Array)
     }
 }
 
@@ -404,3 +729,144 @@ impl<T: CoLaDataType> CoLaDataType for Option<T> {
 // pub fn new_cola_b(stream: TcpStream) -> CoLaB {
 //     CoLaB { stream }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(code: &[u8]) -> Vec<u8> {
+        [&SFA[..], &[SPC], code].concat()
+    }
+
+    #[test]
+    fn answers_other_than_sfa_pass() {
+        assert!(CoLaUtil::check_answer(b"sAN SetAccessMode \x01").is_ok());
+        assert!(CoLaUtil::check_answer(b"").is_ok());
+    }
+
+    #[test]
+    fn every_sopas_error_code_is_reported() {
+        for code in 0..=0x30_u16 {
+            for encoded in [answer(&[code as u8]), answer(&code.to_be_bytes())] {
+                let error = CoLaUtil::check_answer(&encoded).unwrap_err();
+                match code {
+                    0x1 | 0xA => assert!(
+                        matches!(error, CoLaError::AccessDenied { offset: 4, code: c } if c == code),
+                        "{code:#x}: {error:?}"
+                    ),
+                    _ => assert!(
+                        matches!(error, CoLaError::DeviceError { offset: 4, code: c } if c == code),
+                        "{code:#x}: {error:?}"
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn codes_that_look_like_whitespace_are_not_skipped() {
+        for code in [0x09, 0x0A, 0x0D, 0x20] {
+            let error = CoLaUtil::check_answer(&answer(&[code])).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                CoLaError::from_device_code(4, code as u16).to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn option_flags_other_than_0_and_1_are_rejected_in_both_dialects() {
+        let decode_ascii = |data: &[u8]| Option::<u8>::get_from_ascii(&mut AsciiReader::new(data));
+        assert_eq!(decode_ascii(b" 0").unwrap(), None);
+        assert_eq!(decode_ascii(b" 1 2A").unwrap(), Some(0x2A));
+        for flag in [&b" 2 2A"[..], b" FFFF 2A"] {
+            assert!(matches!(
+                decode_ascii(flag),
+                Err(CoLaError::InvalidValue { offset: 1, .. })
+            ));
+        }
+        assert!(Option::<u8>::from_bytes(&[0, 2, 0x2A]).is_err());
+        assert_eq!(
+            Option::<u8>::from_bytes(&[0, 1, 0x2A]).unwrap().0,
+            Some(0x2A)
+        );
+    }
+
+    #[test]
+    fn bools_other_than_0_and_1_are_rejected_in_both_dialects() {
+        let decode_ascii = |data: &[u8]| bool::get_from_ascii(&mut AsciiReader::new(data));
+        assert!(!decode_ascii(b" 0").unwrap());
+        assert!(decode_ascii(b" 1").unwrap());
+        for value in [&b" 2"[..], b" FF"] {
+            assert!(matches!(
+                decode_ascii(value),
+                Err(CoLaError::InvalidValue { offset: 1, .. })
+            ));
+        }
+        assert!(bool::from_bytes(&[1]).unwrap().0);
+        assert!(matches!(
+            bool::from_bytes(&[2]),
+            Err(CoLaError::InvalidValue { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn fixed_strings_drop_their_padding() {
+        let mut data = Vec::new();
+        FixedString::<6>("RANG".into()).write_to_data(&mut data);
+        assert_eq!(data, b"RANG  ");
+        assert_eq!(FixedString::<6>::from_bytes(&data).unwrap().0 .0, "RANG");
+        assert_eq!(FixedString::<4>::from_bytes(b"  \t ").unwrap().0 .0, "  \t");
+        let mut input = AsciiReader::new(b" A   ");
+        assert_eq!(FixedString::<4>::get_from_ascii(&mut input).unwrap().0, "A");
+    }
+
+    #[test]
+    #[should_panic(expected = "7 bytes do not fit a 6 byte string")]
+    fn fixed_strings_longer_than_their_size_are_not_encoded() {
+        FixedString::<6>("RANGING".into()).write_to_data(&mut Vec::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "65536 elements do not fit a u16 count")]
+    fn strings_longer_than_their_count_are_not_encoded() {
+        "x".repeat(65536).write_to_data(&mut Vec::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "256 elements do not fit a u8 count")]
+    fn arrays_longer_than_their_count_are_not_encoded() {
+        U8Array(vec![0_u8; 256]).write_ascii(&mut Vec::new());
+    }
+
+    #[test]
+    fn counts_up_to_the_limit_are_encoded() {
+        let mut data = Vec::new();
+        vec![0_u8; 65535].write_to_data(&mut data);
+        assert_eq!(data[..2], [0xFF, 0xFF]);
+        assert_eq!(Vec::<u8>::from_bytes(&data).unwrap().0.len(), 65535);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn reversed_payloads_are_still_decoded() {
+        let mut input: Vec<u8> = [&[0x01, 0x02][..], &[0, 3], b"abc", &[0xff]]
+            .concat()
+            .into_iter()
+            .rev()
+            .collect();
+        assert_eq!(u16::get_from_data(&mut input).unwrap(), 0x0102);
+        assert_eq!(String::get_from_data(&mut input).unwrap(), "abc");
+        assert_eq!(input, [0xff]);
+        assert!(u16::get_from_data(&mut input).is_err());
+        assert_eq!(input, [0xff]);
+    }
+
+    #[test]
+    fn missing_codes_are_truncated() {
+        assert!(matches!(
+            CoLaUtil::check_answer(&answer(&[])),
+            Err(CoLaError::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use std::{error::Error, fmt, io, time::Duration};
+
+/// SOPAS error codes an `sFA` answer carries when the user level is too low.
+const METHODIN_ACCESSDENIED: u16 = 0x1;
+const VARIABLE_WRITE_ACCESSDENIED: u16 = 0xA;
+
+/// Everything that can go wrong talking CoLa to a device.
+///
+/// Payload offsets count from the first byte of the frame payload, stream offsets from the
+/// first byte the codec saw on the connection.
+#[derive(Debug)]
+pub enum CoLaError {
+    /// The payload ended inside a value starting at `offset`.
+    Truncated {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// Bytes at `offset` that do not decode to the expected value.
+    InvalidValue {
+        offset: usize,
+        what: String,
+    },
+    UnknownCommand {
+        offset: usize,
+        command: String,
+    },
+    ChecksumMismatch {
+        offset: u64,
+        expected: u8,
+        computed: u8,
+    },
+    /// An `sFA` answer.
+    DeviceError {
+        offset: usize,
+        code: u16,
+    },
+    /// An `sFA` answer refusing the request at the current user level.
+    AccessDenied {
+        offset: usize,
+        code: u16,
+    },
+    Timeout {
+        offset: u64,
+        after: Duration,
+    },
+    Transport {
+        offset: u64,
+        source: io::Error,
+    },
+}
+
+impl CoLaError {
+    pub fn truncated(needed: usize, available: usize) -> Self {
+        CoLaError::Truncated {
+            offset: 0,
+            needed,
+            available,
+        }
+    }
+
+    pub fn invalid(offset: usize, what: impl Into<String>) -> Self {
+        CoLaError::InvalidValue {
+            offset,
+            what: what.into(),
+        }
+    }
+
+    /// The error of an `sFA` answer whose code starts at `offset`.
+    pub fn from_device_code(offset: usize, code: u16) -> Self {
+        match code {
+            METHODIN_ACCESSDENIED | VARIABLE_WRITE_ACCESSDENIED => {
+                CoLaError::AccessDenied { offset, code }
+            }
+            code => CoLaError::DeviceError { offset, code },
+        }
+    }
+
+    /// Moves a payload offset that is relative to a value starting `base` bytes in.
+    pub fn offset_by(mut self, base: usize) -> Self {
+        match &mut self {
+            CoLaError::Truncated { offset, .. }
+            | CoLaError::InvalidValue { offset, .. }
+            | CoLaError::UnknownCommand { offset, .. }
+            | CoLaError::DeviceError { offset, .. }
+            | CoLaError::AccessDenied { offset, .. } => *offset += base,
+            _ => {}
+        }
+        self
+    }
+
+    /// Sets the stream offset of a transport error, which the codec cannot know.
+    pub fn at_stream(mut self, position: u64) -> Self {
+        if let CoLaError::Transport { offset, .. } = &mut self {
+            *offset = position;
+        }
+        self
+    }
+
+    /// Whether the same request may succeed when sent again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            CoLaError::ChecksumMismatch { .. } | CoLaError::Timeout { .. }
+        )
+    }
+}
+
+impl fmt::Display for CoLaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoLaError::Truncated {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "payload truncated at byte {offset}, needed {needed} bytes but got {available}"
+            ),
+            CoLaError::InvalidValue { offset, what } => write!(f, "{what} at byte {offset}"),
+            CoLaError::UnknownCommand { offset, command } => {
+                write!(f, "unknown command {command:?} at byte {offset}")
+            }
+            CoLaError::ChecksumMismatch {
+                offset,
+                expected,
+                computed,
+            } => write!(
+                f,
+                "checksum mismatch in frame at byte {offset}, frame says {expected:#04x} but data gives {computed:#04x}"
+            ),
+            CoLaError::DeviceError { offset, code } => {
+                write!(f, "device answered with error {code:#x} at byte {offset}")
+            }
+            CoLaError::AccessDenied { offset, code } => {
+                write!(f, "access denied ({code:#x}) at byte {offset}")
+            }
+            CoLaError::Timeout { offset, after } => {
+                write!(f, "no answer after {after:?} at byte {offset}")
+            }
+            CoLaError::Transport { offset, source } => {
+                write!(f, "transport error at byte {offset}: {source}")
+            }
+        }
+    }
+}
+
+impl Error for CoLaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CoLaError::Transport { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CoLaError {
+    fn from(source: io::Error) -> Self {
+        CoLaError::Transport { offset: 0, source }
+    }
+}
+
+impl From<CoLaError> for io::Error {
+    fn from(value: CoLaError) -> Self {
+        match value {
+            CoLaError::Transport { source, .. } => source,
+            CoLaError::Timeout { .. } => io::Error::new(io::ErrorKind::TimedOut, value),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
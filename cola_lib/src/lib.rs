@@ -1,4 +1,10 @@
+pub mod ascii;
+pub mod binary;
+pub mod codec;
 pub mod cola_a;
+pub mod error;
+
+pub use error::CoLaError;
 
 // pub fn add(left: usize, right: usize) -> usize {
 
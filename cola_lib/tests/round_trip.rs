@@ -0,0 +1,191 @@
+//! Every `CoLaDataType` must decode to exactly what it encoded, in both dialects.
+
+use cola_lib::{
+    ascii::AsciiReader,
+    binary::BinaryReader,
+    cola_a::{CoLaDataType, FixedString, FlexString, U16Array, U8Array},
+};
+use cola_macros::{cola_m, CoLaDataType};
+use proptest::prelude::*;
+
+fn binary<T: CoLaDataType>(value: &T) -> T {
+    let mut data = Vec::new();
+    value.write_to_data(&mut data);
+    data.extend_from_slice(b"tail");
+    let (decoded, rest) = T::from_bytes(&data).unwrap();
+    assert_eq!(rest, b"tail");
+    decoded
+}
+
+fn ascii<T: CoLaDataType>(value: &T) -> T {
+    let mut data = Vec::new();
+    value.write_ascii(&mut data);
+    data.extend_from_slice(b" tail");
+    let mut input = AsciiReader::new(&data);
+    let decoded = T::get_from_ascii(&mut input).unwrap();
+    assert_eq!(input.token().unwrap(), "tail");
+    decoded
+}
+
+macro_rules! round_trip {
+    ($($name:ident: $ty:ty = $strategy:expr;)*) => {
+        proptest! {
+            $(
+                #[test]
+                fn $name(value in $strategy) {
+                    let value: $ty = value;
+                    prop_assert_eq!(&binary(&value), &value);
+                    prop_assert_eq!(&ascii(&value), &value);
+                }
+            )*
+        }
+    };
+}
+
+round_trip! {
+    bool_round_trip: bool = any::<bool>();
+    u8_round_trip: u8 = any::<u8>();
+    u16_round_trip: u16 = any::<u16>();
+    u32_round_trip: u32 = any::<u32>();
+    i8_round_trip: i8 = any::<i8>();
+    i16_round_trip: i16 = any::<i16>();
+    i32_round_trip: i32 = any::<i32>();
+    string_round_trip: String = any::<String>();
+    vec_round_trip: Vec<u16> = prop::collection::vec(any::<u16>(), 0..64);
+    option_round_trip: Option<i32> = any::<Option<i32>>();
+    nested_round_trip: Vec<Option<String>> =
+        prop::collection::vec(any::<Option<String>>(), 0..8);
+    u64_round_trip: u64 = any::<u64>();
+    i64_round_trip: i64 = any::<i64>();
+    array_round_trip: [i16; 3] = any::<[i16; 3]>();
+    tuple_round_trip: (u8, i64, String) = any::<(u8, i64, String)>();
+    fixed_string_round_trip: FixedString<8> = "([ -~]{0,7}[!-~])?".prop_map(FixedString);
+    flex_string_round_trip: FlexString = any::<String>().prop_map(FlexString);
+    u8_array_round_trip: U8Array<u32> =
+        prop::collection::vec(any::<u32>(), 0..=255).prop_map(U8Array);
+    u16_array_round_trip: U16Array<Option<u8>> =
+        prop::collection::vec(any::<Option<u8>>(), 0..64).prop_map(U16Array);
+}
+
+proptest! {
+    #[test]
+    fn f32_round_trip(bits in any::<u32>()) {
+        let value = f32::from_bits(bits);
+        prop_assert_eq!(binary(&value).to_bits(), bits);
+        prop_assert_eq!(ascii(&value).to_bits(), bits);
+    }
+
+    #[test]
+    fn f64_round_trip(bits in any::<u64>()) {
+        let value = f64::from_bits(bits);
+        prop_assert_eq!(binary(&value).to_bits(), bits);
+        prop_assert_eq!(ascii(&value).to_bits(), bits);
+    }
+
+    /// Shorter fixed strings are padded to their length and decode without the padding.
+    #[test]
+    fn fixed_string_padding(value in "[ -~]{0,8}") {
+        let mut data = Vec::new();
+        FixedString::<8>(value.clone()).write_to_data(&mut data);
+        prop_assert_eq!(data, format!("{value:<8}").into_bytes());
+        let unpadded = value.trim_end_matches(' ');
+        prop_assert_eq!(binary(&FixedString::<8>(value.clone())).0, unpadded);
+        prop_assert_eq!(ascii(&FixedString::<8>(value.clone())).0, unpadded);
+    }
+}
+
+#[derive(CoLaDataType, Clone, Debug, PartialEq)]
+struct Sector {
+    resolution: u32,
+    start: i32,
+    name: String,
+    enabled: bool,
+    limits: Vec<i16>,
+    filter: Option<u8>,
+}
+
+#[derive(CoLaDataType, Clone, Debug, PartialEq)]
+struct Angles(u32, i32, i32);
+
+fn sector() -> impl Strategy<Value = Sector> {
+    (
+        any::<u32>(),
+        any::<i32>(),
+        any::<String>(),
+        any::<bool>(),
+        prop::collection::vec(any::<i16>(), 0..16),
+        any::<Option<u8>>(),
+    )
+        .prop_map(
+            |(resolution, start, name, enabled, limits, filter)| Sector {
+                resolution,
+                start,
+                name,
+                enabled,
+                limits,
+                filter,
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn derived_round_trip(value in sector()) {
+        prop_assert_eq!(&binary(&value), &value);
+        prop_assert_eq!(&ascii(&value), &value);
+    }
+
+    #[test]
+    fn derived_tuple_struct_round_trip(value in any::<(u32, i32, i32)>()) {
+        let value = Angles(value.0, value.1, value.2);
+        prop_assert_eq!(&binary(&value), &value);
+        prop_assert_eq!(&ascii(&value), &value);
+    }
+
+    #[test]
+    fn derived_vec_round_trip(value in prop::collection::vec(sector(), 0..4)) {
+        prop_assert_eq!(&binary(&value), &value);
+        prop_assert_eq!(&ascii(&value), &value);
+    }
+}
+
+const S_WN: [u8; 3] = *b"sWN";
+
+#[cola_m]
+#[derive(Clone, Copy, Debug)]
+enum Outgoing {
+    #[cola_m(S_WN, "LMPscancfg")]
+    ScanConfig {
+        frequency: u32,
+        level: i8,
+        enabled: bool,
+        scale: f32,
+    },
+}
+
+proptest! {
+    /// The arguments of an outgoing message follow its command and decode back unchanged.
+    #[test]
+    fn outgoing_round_trip(frequency: u32, level: i8, enabled: bool, scale in any::<u32>()) {
+        let message = Outgoing::ScanConfig { frequency, level, enabled, scale: f32::from_bits(scale) };
+
+        let raw = message.to_raw_message().unwrap();
+        let args = raw.strip_prefix(b"sWN LMPscancfg ".as_slice()).unwrap();
+        let mut input = BinaryReader::new(args);
+        prop_assert_eq!(u32::read_from(&mut input).unwrap(), frequency);
+        prop_assert_eq!(i8::read_from(&mut input).unwrap(), level);
+        prop_assert_eq!(bool::read_from(&mut input).unwrap(), enabled);
+        prop_assert_eq!(f32::read_from(&mut input).unwrap().to_bits(), scale);
+        prop_assert!(input.is_empty());
+
+        let raw = message.to_ascii_message().unwrap();
+        let mut input = AsciiReader::new(&raw);
+        prop_assert_eq!(input.token().unwrap(), "sWN");
+        prop_assert_eq!(input.token().unwrap(), "LMPscancfg");
+        prop_assert_eq!(u32::get_from_ascii(&mut input).unwrap(), frequency);
+        prop_assert_eq!(i8::get_from_ascii(&mut input).unwrap(), level);
+        prop_assert_eq!(bool::get_from_ascii(&mut input).unwrap(), enabled);
+        prop_assert_eq!(f32::get_from_ascii(&mut input).unwrap().to_bits(), scale);
+        prop_assert!(input.rest().is_empty());
+    }
+}
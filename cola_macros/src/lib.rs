@@ -35,6 +35,7 @@ pub fn cola_m(
     let mut data: DeriveInput = parse_macro_input!(cln as DeriveInput);
     let name = &data.ident;
     let mut inner: TokenStream = TokenStream::new();
+    let mut inner_ascii: TokenStream = TokenStream::new();
     let mut outer: TokenStream = TokenStream::new();
     match data.data {
         Data::Enum(ref mut s) => {
@@ -50,23 +51,33 @@ pub fn cola_m(
                 let mut vars = quote! {};
                 let id = &v.ident;
                 let pred = quote! {let mut __internal = cola_lib::cola_a::CoLaUtil::vec_from_command(#cmd);};
-                let eval: proc_macro2::TokenStream = match &v.fields {
-                    syn::Fields::Named(f) => {
-                        let intermediate: proc_macro2::TokenStream = f
-                            .named
-                            .iter()
-                            .map(|f| &f.ident)
-                            .map(|f| quote! {#f ,})
-                            .collect();
-                        vars = quote! {{#intermediate}};
-                        field_expander(f.named.clone()).into()
-                    }
-                    syn::Fields::Unnamed(f) => {
-                        //TODO:
-                        field_expander(f.unnamed.clone()).into()
-                    }
-                    syn::Fields::Unit => quote! {},
-                };
+                let pred_ascii = quote! {let mut __internal = cola_lib::cola_a::CoLaUtil::ascii_from_command(#cmd);};
+                let write = quote! {write_to_data};
+                let write_ascii = quote! {write_ascii};
+                let (eval, eval_ascii): (proc_macro2::TokenStream, proc_macro2::TokenStream) =
+                    match &v.fields {
+                        syn::Fields::Named(f) => {
+                            let intermediate: proc_macro2::TokenStream = f
+                                .named
+                                .iter()
+                                .map(|f| &f.ident)
+                                .map(|f| quote! {#f ,})
+                                .collect();
+                            vars = quote! {{#intermediate}};
+                            (
+                                field_expander(f.named.clone(), &write).into(),
+                                field_expander(f.named.clone(), &write_ascii).into(),
+                            )
+                        }
+                        syn::Fields::Unnamed(f) => {
+                            //TODO:
+                            (
+                                field_expander(f.unnamed.clone(), &write).into(),
+                                field_expander(f.unnamed.clone(), &write_ascii).into(),
+                            )
+                        }
+                        syn::Fields::Unit => (quote! {}, quote! {}),
+                    };
                 inner.extend::<proc_macro::TokenStream>(
                     quote! {
                         #name::#id #vars => {
@@ -77,6 +88,16 @@ pub fn cola_m(
                     }
                     .into(),
                 );
+                inner_ascii.extend::<proc_macro::TokenStream>(
+                    quote! {
+                        #name::#id #vars => {
+                            #pred_ascii
+                            #eval_ascii
+                            __internal
+                        },
+                    }
+                    .into(),
+                );
                 outer.extend::<proc_macro::TokenStream>(quote! {}.into());
                 v.attrs.retain(|a| !a.path().is_ident(COLA_M));
             }
@@ -84,6 +105,7 @@ pub fn cola_m(
         _ => panic!("Can only be applied to enum types!"),
     };
     let inner: proc_macro2::TokenStream = inner.into();
+    let inner_ascii: proc_macro2::TokenStream = inner_ascii.into();
     let _outer: proc_macro2::TokenStream = outer.into();
     let full = quote! {
         #data
@@ -93,6 +115,19 @@ pub fn cola_m(
                     #inner
                 })
             }
+
+            pub fn to_ascii_message(&self) -> Option<cola_lib::cola_a::ColaMessageRaw> {
+                Some(match *self {
+                    #inner_ascii
+                })
+            }
+
+            pub fn to_message(&self, dialect: cola_lib::codec::CoLaDialect) -> Option<cola_lib::cola_a::ColaMessageRaw> {
+                match dialect {
+                    cola_lib::codec::CoLaDialect::Binary => self.to_raw_message(),
+                    cola_lib::codec::CoLaDialect::Ascii => self.to_ascii_message(),
+                }
+            }
         }
     };
     full.into()
@@ -108,6 +143,7 @@ pub fn cola_incoming(
     let mut data: DeriveInput = parse_macro_input!(cln as DeriveInput);
     let name = &data.ident;
     let mut inner: TokenStream = TokenStream::new();
+    let mut inner_ascii: TokenStream = TokenStream::new();
     // let mut outer: TokenStream = TokenStream::new();
     match data.data {
         Data::Enum(ref mut s) => {
@@ -123,24 +159,31 @@ pub fn cola_incoming(
                 let mut vars = quote! {};
                 let id = &v.ident;
                 let pred = quote! {/* let mut __internal = cola_lib::cola_a::CoLaUtil::vec_from_command(#cmd); */};
-                let eval: proc_macro2::TokenStream = match &v.fields {
-                    syn::Fields::Named(f) => {
-                        let intermediate: proc_macro2::TokenStream = f
-                            .named
-                            .iter()
-                            .map(|f| &f.ident)
-                            .map(|f| quote! {#f ,})
-                            .collect();
-                        vars = quote! {{#intermediate}};
-                        field_expander_incoming(f.named.clone()).into()
-                    }
-                    syn::Fields::Unnamed(f) => {
-                        todo!("Not implemented for unnamed data types!")
-                        //TODO:
-                        //field_expander(f.unnamed.clone()).into()
-                    }
-                    syn::Fields::Unit => quote! {},
-                };
+                let read = quote! {cola_lib::cola_a::CoLaDataType::read_from(&mut reader)};
+                let read_ascii =
+                    quote! {cola_lib::cola_a::CoLaDataType::get_from_ascii(&mut reader)};
+                let (eval, eval_ascii): (proc_macro2::TokenStream, proc_macro2::TokenStream) =
+                    match &v.fields {
+                        syn::Fields::Named(f) => {
+                            let intermediate: proc_macro2::TokenStream = f
+                                .named
+                                .iter()
+                                .map(|f| &f.ident)
+                                .map(|f| quote! {#f ,})
+                                .collect();
+                            vars = quote! {{#intermediate}};
+                            (
+                                field_expander_incoming(f.named.clone(), &read).into(),
+                                field_expander_incoming(f.named.clone(), &read_ascii).into(),
+                            )
+                        }
+                        syn::Fields::Unnamed(f) => {
+                            todo!("Not implemented for unnamed data types!")
+                            //TODO:
+                            //field_expander(f.unnamed.clone()).into()
+                        }
+                        syn::Fields::Unit => (quote! {}, quote! {}),
+                    };
                 inner.extend::<proc_macro::TokenStream>(
                     quote! {
                         #cmd => {
@@ -154,29 +197,61 @@ pub fn cola_incoming(
                     }
                     .into(),
                 );
+                inner_ascii.extend::<proc_macro::TokenStream>(
+                    quote! {
+                        #cmd => Ok(#name::#id{#eval_ascii}),
+                    }
+                    .into(),
+                );
                 v.attrs.retain(|a| !a.path().is_ident(COLA_INCOMING));
             }
         }
         _ => panic!("Can only be applied to enum types!"),
     };
     let inner: proc_macro2::TokenStream = inner.into();
+    let inner_ascii: proc_macro2::TokenStream = inner_ascii.into();
     // let _outer: proc_macro2::TokenStream = outer.into();
     let full = quote! {
         #data
         impl #name {
-            pub fn from_raw_message(msg: &mut cola_lib::cola_a::ColaMessageRaw) -> std::result::Result<#name, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-                let cmd_type: String = cola_lib::cola_a::CoLaDataType::get_from_data(msg)?;
-                let cmd: String = cola_lib::cola_a::CoLaDataType::get_from_data(msg)?;
-                // dbg!(&cmd_type);
-                // dbg!(&cmd);
-                // dbg!(&msg);
-                match cmd.as_str() {
+            pub fn from_raw_message(msg: &[u8]) -> std::result::Result<#name, cola_lib::CoLaError> {
+                cola_lib::cola_a::CoLaUtil::check_answer(msg)?;
+                let mut reader = cola_lib::binary::BinaryReader::new(msg);
+                let _cmd_type = reader.token()?;
+                let __command = reader.position();
+                let cmd = reader.token()?;
+                match cmd {
                     #inner
-                    _ => {dbg!("No match!");
-                        return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Failed to get message!",
-            )))}
+                    _ => Err(cola_lib::CoLaError::UnknownCommand {
+                        offset: __command,
+                        command: cmd.to_string(),
+                    }),
+                }
+            }
+
+            pub fn from_ascii_message(msg: &[u8]) -> std::result::Result<#name, cola_lib::CoLaError> {
+                let mut reader = cola_lib::ascii::AsciiReader::new(msg);
+                if reader.token()? == "sFA" {
+                    reader.skip_spaces();
+                    let offset = reader.position();
+                    return Err(cola_lib::CoLaError::from_device_code(offset, reader.read_unsigned()?));
+                }
+                reader.skip_spaces();
+                let __command = reader.position();
+                let cmd = reader.token()?;
+                match cmd {
+                    #inner_ascii
+                    _ => Err(cola_lib::CoLaError::UnknownCommand {
+                        offset: __command,
+                        command: cmd.to_string(),
+                    }),
+                }
+            }
+
+            pub fn from_message(msg: &[u8], dialect: cola_lib::codec::CoLaDialect) -> std::result::Result<#name, cola_lib::CoLaError> {
+                match dialect {
+                    cola_lib::codec::CoLaDialect::Binary => Self::from_raw_message(msg),
+                    cola_lib::codec::CoLaDialect::Ascii => Self::from_ascii_message(msg),
                 }
             }
         }
@@ -194,22 +269,36 @@ pub fn derive_data_type(input: proc_macro::TokenStream) -> proc_macro::TokenStre
     // let wh = binding.;
     let mut inner_a: TokenStream = TokenStream::new();
     let mut inner_b: TokenStream = TokenStream::new();
+    let mut inner_c: TokenStream = TokenStream::new();
+    let mut inner_d: TokenStream = TokenStream::new();
     match data.data {
-        Data::Struct(ref mut s) => s.fields.iter().for_each(|f| {
-            let id = &f.ident;
+        Data::Struct(ref mut s) => s.fields.iter().enumerate().for_each(|(i, f)| {
+            // Tuple structs are built as `Self { 0: .., 1: .. }`.
+            let id = match &f.ident {
+                Some(id) => quote! {#id},
+                None => {
+                    let index = Index::from(i);
+                    quote! {#index}
+                }
+            };
             inner_a
                 .extend::<proc_macro::TokenStream>(quote! {self.#id.write_to_data(data);}.into());
             inner_b.extend::<proc_macro::TokenStream>(
-                quote! {#id: cola_lib::cola_a::CoLaDataType::get_from_data(input)?,}.into(),
+                quote! {#id: cola_lib::cola_a::CoLaDataType::read_from(input)?,}.into(),
+            );
+            inner_c.extend::<proc_macro::TokenStream>(quote! {self.#id.write_ascii(data);}.into());
+            inner_d.extend::<proc_macro::TokenStream>(
+                quote! {#id: cola_lib::cola_a::CoLaDataType::get_from_ascii(input)?,}.into(),
             );
         }),
         Data::Enum(_) => todo!(),
         Data::Union(_) => todo!(),
     }
-    let temp = name.to_string();
 
     let inner_a: proc_macro2::TokenStream = inner_a.into();
     let inner_b: proc_macro2::TokenStream = inner_b.into();
+    let inner_c: proc_macro2::TokenStream = inner_c.into();
+    let inner_d: proc_macro2::TokenStream = inner_d.into();
     quote! {
         impl #impl_gen cola_lib::cola_a::CoLaDataType for #name #ty_gen #wh_gen {
 
@@ -217,32 +306,42 @@ pub fn derive_data_type(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                 #inner_a
             }
 
-            fn get_from_data(input: &mut Vec<u8>) -> std::result::Result<Self, std::boxed::Box<(dyn std::error::Error + 'static)>>  where Self:Sized {
-                print!("Doing: ");
-                 println!(#temp);
+            fn read_from(input: &mut cola_lib::binary::BinaryReader<'_>) -> std::result::Result<Self, cola_lib::CoLaError>  where Self:Sized {
                 Ok(Self{#inner_b})
             }
+
+            fn write_ascii(&self, data: &mut Vec<u8>) {
+                #inner_c
+            }
+
+            fn get_from_ascii(input: &mut cola_lib::ascii::AsciiReader<'_>) -> std::result::Result<Self, cola_lib::CoLaError>  where Self:Sized {
+                Ok(Self{#inner_d})
+            }
         }
     }
     .into()
 }
 
-fn field_expander(input: Punctuated<Field, Comma>) -> TokenStream {
+fn field_expander(
+    input: Punctuated<Field, Comma>,
+    method: &proc_macro2::TokenStream,
+) -> TokenStream {
     input
         .iter()
         .map(|d| &d.ident)
         .flat_map(|d| -> TokenStream {
-            quote! {cola_lib::cola_a::CoLaDataType::write_to_data(&#d, &mut __internal);}.into()
+            quote! {cola_lib::cola_a::CoLaDataType::#method(&#d, &mut __internal);}.into()
         })
         .collect::<TokenStream>()
 }
 
-fn field_expander_incoming(input: Punctuated<Field, Comma>) -> TokenStream {
+fn field_expander_incoming(
+    input: Punctuated<Field, Comma>,
+    method: &proc_macro2::TokenStream,
+) -> TokenStream {
     input
         .iter()
         .map(|d| &d.ident)
-        .flat_map(|d| -> TokenStream {
-            quote! {#d: cola_lib::cola_a::CoLaDataType::get_from_data(msg)?,}.into()
-        })
+        .flat_map(|d| -> TokenStream { quote! {#d: #method?,}.into() })
         .collect::<TokenStream>()
 }
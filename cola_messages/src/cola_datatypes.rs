@@ -1,59 +1,91 @@
-use cola_lib::cola_a::CoLaDataType;
+use cola_lib::{ascii::AsciiReader, binary::BinaryReader, cola_a::CoLaDataType, CoLaError};
 use cola_macros::CoLaDataType;
 
 pub type CoLaFrequency = u32;
 pub type CoLaAngularRes = u32;
 pub type CoLaDefinedAngle = i32;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct LmpSectorConfig(CoLaAngularRes, CoLaDefinedAngle, CoLaDefinedAngle);
-impl CoLaDataType for LmpSectorConfig {
-    fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
+/// Angular resolution, start angle and stop angle of one sector.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CoLaDataType)]
+pub struct LmpSectorConfig(
+    pub CoLaAngularRes,
+    pub CoLaDefinedAngle,
+    pub CoLaDefinedAngle,
+);
+
+// pub type CoLa16DataOutput = [u16; 65535];
+// pub type CoLa8DataOutput = [u8; 65535];
+
+/// Up to [`LmpSectors::MAX`] sectors, sent as a u16 count followed by the sectors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LmpSectors {
+    /// Slots past `len` stay at their default, so equal sector lists compare equal.
+    sectors: [LmpSectorConfig; LmpSectors::MAX],
+    len: usize,
+}
+
+impl LmpSectors {
+    pub const MAX: usize = 4;
+
+    /// `None` for more than [`LmpSectors::MAX`] sectors.
+    pub fn new(sectors: &[LmpSectorConfig]) -> Option<LmpSectors> {
+        let mut out = LmpSectors::default();
+        out.sectors
+            .get_mut(..sectors.len())?
+            .copy_from_slice(sectors);
+        out.len = sectors.len();
+        Some(out)
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<LmpSectorConfig, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    where
-        Self: Sized,
-    {
-        todo!()
+    pub fn sectors(&self) -> &[LmpSectorConfig] {
+        &self.sectors[..self.len]
+    }
+
+    /// Collects at most [`LmpSectors::MAX`] decoded sectors.
+    fn collect(
+        sectors: impl IntoIterator<Item = Result<LmpSectorConfig, CoLaError>>,
+    ) -> Result<LmpSectors, CoLaError> {
+        let mut out = LmpSectors::default();
+        for (slot, sector) in out.sectors.iter_mut().zip(sectors) {
+            *slot = sector?;
+            out.len += 1;
+        }
+        Ok(out)
     }
 }
-impl CoLaDataType for LmpScanCfgError {
+
+impl CoLaDataType for LmpSectors {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
+        (self.len as u16).write_to_data(data);
+        self.sectors().iter().for_each(|s| s.write_to_data(data));
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<LmpScanCfgError, std::boxed::Box<(dyn std::error::Error + 'static)>>
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<LmpSectors, CoLaError>
     where
         Self: Sized,
     {
-        todo!()
+        let start = input.position();
+        match u16::read_from(input)? {
+            count @ 0..=4 => Self::collect((0..count).map(|_| CoLaDataType::read_from(input))),
+            count => Err(CoLaError::invalid(start, format!("{count} sectors"))),
+        }
     }
-}
-
-// pub type CoLa16DataOutput = [u16; 65535];
-// pub type CoLa8DataOutput = [u8; 65535];
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct LmpSectors([Option<LmpSectorConfig>; 4]);
 
-impl CoLaDataType for LmpSectors {
-    fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        (self.len as u16).write_ascii(data);
+        self.sectors().iter().for_each(|s| s.write_ascii(data));
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<LmpSectors, std::boxed::Box<(dyn std::error::Error + 'static)>>
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<LmpSectors, CoLaError>
     where
         Self: Sized,
     {
-        todo!()
+        input.skip_spaces();
+        let start = input.position();
+        match u16::get_from_ascii(input)? {
+            count @ 0..=4 => Self::collect((0..count).map(|_| CoLaDataType::get_from_ascii(input))),
+            count => Err(CoLaError::invalid(start, format!("{count} sectors"))),
+        }
     }
 }
 
@@ -79,7 +111,7 @@ pub struct EncoderData {
 //     }
 // }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct CoLaDataChannel<T>
 where
     T: CoLaDataType + std::fmt::Debug,
@@ -91,7 +123,7 @@ where
     pub angular_step: u16,
     pub data: Vec<T>,
 }
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CoLaDataChannelType {
     Dist1,
     Dist2,
@@ -110,20 +142,25 @@ pub enum CoLaDataChannelType {
 
 impl<T: CoLaDataType + std::fmt::Debug> CoLaDataType for CoLaDataChannel<T> {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
+        self.kind.write_to_data(data);
+        self.scale.write_to_data(data);
+        self.scale_offset.write_to_data(data);
+        self.start_angle.write_to_data(data);
+        self.angular_step.write_to_data(data);
+        self.data.write_to_data(data);
     }
 
-    fn get_from_data(input: &mut Vec<u8>) -> Result<Self, Box<dyn std::error::Error>>
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<Self, CoLaError>
     where
         Self: Sized,
     {
         // dbg!("test");
-        let ty = CoLaDataType::get_from_data(input)?;
-        let sc = CoLaDataType::get_from_data(input)?;
-        let off = CoLaDataType::get_from_data(input)?;
-        let st = CoLaDataType::get_from_data(input)?;
-        let offset = CoLaDataType::get_from_data(input)?;
-        let data = CoLaDataType::get_from_data(input)?;
+        let ty = CoLaDataType::read_from(input)?;
+        let sc = CoLaDataType::read_from(input)?;
+        let off = CoLaDataType::read_from(input)?;
+        let st = CoLaDataType::read_from(input)?;
+        let offset = CoLaDataType::read_from(input)?;
+        let data = CoLaDataType::read_from(input)?;
         let out = CoLaDataChannel {
             kind: ty,
             scale: sc,
@@ -135,46 +172,117 @@ impl<T: CoLaDataType + std::fmt::Debug> CoLaDataType for CoLaDataChannel<T> {
         // dbg!(&out);
         Ok(out)
     }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        self.kind.write_ascii(data);
+        self.scale.write_ascii(data);
+        self.scale_offset.write_ascii(data);
+        self.start_angle.write_ascii(data);
+        self.angular_step.write_ascii(data);
+        self.data.write_ascii(data);
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<Self, CoLaError>
+    where
+        Self: Sized,
+    {
+        Ok(CoLaDataChannel {
+            kind: CoLaDataType::get_from_ascii(input)?,
+            scale: CoLaDataType::get_from_ascii(input)?,
+            scale_offset: CoLaDataType::get_from_ascii(input)?,
+            start_angle: CoLaDataType::get_from_ascii(input)?,
+            angular_step: CoLaDataType::get_from_ascii(input)?,
+            data: CoLaDataType::get_from_ascii(input)?,
+        })
+    }
+}
+
+impl CoLaDataChannelType {
+    /// The content name the channel is sent with, e.g. `DIST1`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dist1 => "DIST1",
+            Self::Dist2 => "DIST2",
+            Self::Dist3 => "DIST3",
+            Self::Dist4 => "DIST4",
+            Self::Dist5 => "DIST5",
+            Self::RSSI1 => "RSSI1",
+            Self::RSSI2 => "RSSI2",
+            Self::RSSI3 => "RSSI3",
+            Self::RSSI4 => "RSSI4",
+            Self::RSSI5 => "RSSI5",
+            Self::VANGL => "VANGL",
+            Self::REFL1 => "REFL1",
+            Self::ANGL1 => "ANGL1",
+        }
+    }
 }
 
 impl CoLaDataType for CoLaDataChannelType {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        panic!("Cannot send channel type")
+        data.extend_from_slice(self.name().as_bytes());
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<CoLaDataChannelType, std::boxed::Box<(dyn std::error::Error + 'static)>>
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<CoLaDataChannelType, CoLaError>
     where
         Self: Sized,
     {
         // dbg!("ch");
-        let o: std::result::Result<
-            CoLaDataChannelType,
-            std::boxed::Box<(dyn std::error::Error + 'static)>,
-        > = match (u32::get_from_data(input)?, u8::get_from_data(input)?) {
-            (0x44495354, 0x31) => Ok(Self::Dist1),
-            (0x44495354, 0x32) => Ok(Self::Dist2),
-            (0x44495354, 0x33) => Ok(Self::Dist3),
-            (0x44495354, 0x34) => Ok(Self::Dist4),
-            (0x44495354, 0x35) => Ok(Self::Dist5),
-            (0x52535349, 0x31) => Ok(Self::RSSI1),
-            (0x52535349, 0x32) => Ok(Self::RSSI2),
-            (0x52535349, 0x33) => Ok(Self::RSSI3),
-            (0x52535349, 0x34) => Ok(Self::RSSI4),
-            (0x52535349, 0x35) => Ok(Self::RSSI5),
-            (0x56414E47, 0x4C) => Ok(Self::VANGL),
-            (0x5245464C, 0x31) => Ok(Self::REFL1),
-            (0x414E474C, 0x31) => Ok(Self::ANGL1),
-            // (_, _) => Ok(Self::Dist1),
-            (a, b) => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Failed to serialize Channel type: {a} {b}"),
-            ))),
-        };
+        let start = input.position();
+        let o: Result<CoLaDataChannelType, CoLaError> =
+            match (u32::read_from(input)?, u8::read_from(input)?) {
+                (0x44495354, 0x31) => Ok(Self::Dist1),
+                (0x44495354, 0x32) => Ok(Self::Dist2),
+                (0x44495354, 0x33) => Ok(Self::Dist3),
+                (0x44495354, 0x34) => Ok(Self::Dist4),
+                (0x44495354, 0x35) => Ok(Self::Dist5),
+                (0x52535349, 0x31) => Ok(Self::RSSI1),
+                (0x52535349, 0x32) => Ok(Self::RSSI2),
+                (0x52535349, 0x33) => Ok(Self::RSSI3),
+                (0x52535349, 0x34) => Ok(Self::RSSI4),
+                (0x52535349, 0x35) => Ok(Self::RSSI5),
+                (0x56414E47, 0x4C) => Ok(Self::VANGL),
+                (0x5245464C, 0x31) => Ok(Self::REFL1),
+                (0x414E474C, 0x31) => Ok(Self::ANGL1),
+                // (_, _) => Ok(Self::Dist1),
+                (a, b) => Err(CoLaError::invalid(
+                    start,
+                    format!("channel type {a:#x} {b:#x}"),
+                )),
+            };
         // dbg!(&o);
         o
     }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        data.push(b' ');
+        data.extend_from_slice(self.name().as_bytes());
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<CoLaDataChannelType, CoLaError>
+    where
+        Self: Sized,
+    {
+        // CoLa-A sends the name itself instead of its bytes.
+        input.skip_spaces();
+        let start = input.position();
+        match input.token()? {
+            "DIST1" => Ok(Self::Dist1),
+            "DIST2" => Ok(Self::Dist2),
+            "DIST3" => Ok(Self::Dist3),
+            "DIST4" => Ok(Self::Dist4),
+            "DIST5" => Ok(Self::Dist5),
+            "RSSI1" => Ok(Self::RSSI1),
+            "RSSI2" => Ok(Self::RSSI2),
+            "RSSI3" => Ok(Self::RSSI3),
+            "RSSI4" => Ok(Self::RSSI4),
+            "RSSI5" => Ok(Self::RSSI5),
+            "VANGL" => Ok(Self::VANGL),
+            "REFL1" => Ok(Self::REFL1),
+            "ANGL1" => Ok(Self::ANGL1),
+            other => Err(CoLaError::invalid(start, format!("channel type {other:?}"))),
+        }
+    }
 }
 
 // pub enum OptionalCoLaData<T: CoLaDataType> {
@@ -217,6 +325,53 @@ pub struct CoLaDataEvent {
     pub angle: u32,
 }
 
+/// Implements [`CoLaDataType`] for a fieldless enum sent as a u8 code, a hex token in
+/// CoLa-A.
+macro_rules! enum8_impl {
+    ($name:ident { $($variant:ident = $code:literal),+ $(,)? }) => {
+        impl CoLaDataType for $name {
+            fn write_to_data(&self, data: &mut Vec<u8>) {
+                self.code().write_to_data(data);
+            }
+
+            fn read_from(input: &mut BinaryReader<'_>) -> Result<$name, CoLaError> {
+                let start = input.position();
+                Self::from_code(start, u8::read_from(input)?)
+            }
+
+            fn write_ascii(&self, data: &mut Vec<u8>) {
+                self.code().write_ascii(data);
+            }
+
+            fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<$name, CoLaError> {
+                input.skip_spaces();
+                let start = input.position();
+                Self::from_code(start, u8::get_from_ascii(input)?)
+            }
+        }
+
+        impl $name {
+            pub fn code(&self) -> u8 {
+                match self {
+                    $(Self::$variant => $code,)+
+                }
+            }
+
+            fn from_code(offset: usize, code: u8) -> Result<$name, CoLaError> {
+                match code {
+                    $($code => Ok(Self::$variant),)+
+                    code => Err(CoLaError::invalid(
+                        offset,
+                        format!("{} {code}", stringify!($name)),
+                    )),
+                }
+            }
+        }
+    };
+}
+
+/// Status of `mLMPsetscancfg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LmpScanCfgError {
     None,
     FrequencyError,
@@ -225,61 +380,195 @@ pub enum LmpScanCfgError {
     ScanAreaError,
     OtherError,
 }
+enum8_impl!(LmpScanCfgError {
+    None = 0,
+    FrequencyError = 1,
+    ResolutionError = 2,
+    ResolutionAndScanOrFreq = 3,
+    ScanAreaError = 4,
+    OtherError = 5,
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AlignmentModeLayer {
     Red,
     Blue,
     Green,
     Yellow,
 }
-impl CoLaDataType for AlignmentModeLayer {
-    fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
-    }
+enum8_impl!(AlignmentModeLayer {
+    Red = 0,
+    Blue = 1,
+    Green = 2,
+    Yellow = 3,
+});
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<AlignmentModeLayer, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    where
-        Self: Sized,
-    {
-        todo!()
-    }
-}
+/// Result of the `LMC` methods, 0 when the sensor accepted the call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LMCError {
     Error,
     Ok,
 }
-impl CoLaDataType for LMCError {
-    fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
-    }
-
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<LMCError, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    where
-        Self: Sized,
-    {
-        todo!()
-    }
-}
+enum8_impl!(LMCError { Ok = 0, Error = 1 });
 
+/// Application of `SetActiveApplications`, sent as its four character id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ActiveApplication {
     FieldApplication,
     Ranging,
 }
 
+impl ActiveApplication {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::FieldApplication => "FEVL",
+            Self::Ranging => "RANG",
+        }
+    }
+
+    fn from_id(offset: usize, id: &[u8]) -> Result<ActiveApplication, CoLaError> {
+        match id {
+            b"FEVL" => Ok(Self::FieldApplication),
+            b"RANG" => Ok(Self::Ranging),
+            id => Err(CoLaError::invalid(
+                offset,
+                format!("application {:?}", String::from_utf8_lossy(id)),
+            )),
+        }
+    }
+}
+
 impl CoLaDataType for ActiveApplication {
     fn write_to_data(&self, data: &mut Vec<u8>) {
-        todo!()
+        data.extend_from_slice(self.id().as_bytes());
     }
 
-    fn get_from_data(
-        input: &mut Vec<u8>,
-    ) -> std::result::Result<ActiveApplication, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    where
-        Self: Sized,
-    {
-        todo!()
+    fn read_from(input: &mut BinaryReader<'_>) -> Result<ActiveApplication, CoLaError> {
+        let start = input.position();
+        Self::from_id(start, input.take(4)?)
+    }
+
+    fn write_ascii(&self, data: &mut Vec<u8>) {
+        data.push(b' ');
+        data.extend_from_slice(self.id().as_bytes());
+    }
+
+    fn get_from_ascii(input: &mut AsciiReader<'_>) -> Result<ActiveApplication, CoLaError> {
+        input.skip_spaces();
+        let start = input.position();
+        Self::from_id(start, input.token()?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: CoLaDataType + std::fmt::Debug + PartialEq>(value: T) {
+        let mut binary = Vec::new();
+        value.write_to_data(&mut binary);
+        assert_eq!(T::from_bytes(&binary).unwrap(), (value, &[][..]));
+        let value = T::from_bytes(&binary).unwrap().0;
+        let mut ascii = Vec::new();
+        value.write_ascii(&mut ascii);
+        let mut input = AsciiReader::new(&ascii);
+        assert_eq!(T::get_from_ascii(&mut input).unwrap(), value);
+        assert!(input.rest().is_empty());
+    }
+
+    #[test]
+    fn sectors_round_trip_in_both_dialects() {
+        let sectors = [
+            LmpSectorConfig(2500, -450_000, 2_250_000),
+            LmpSectorConfig(5000, 0, 900_000),
+            LmpSectorConfig(1250, 1_000_000, 1_100_000),
+            LmpSectorConfig(2500, -10_000, 10_000),
+        ];
+        for len in 0..=LmpSectors::MAX {
+            let value = LmpSectors::new(&sectors[..len]).unwrap();
+            assert_eq!(value.sectors(), &sectors[..len]);
+            round_trip(value);
+        }
+        assert!(LmpSectors::new(&[LmpSectorConfig::default(); 5]).is_none());
+    }
+
+    #[test]
+    fn more_than_four_sectors_are_rejected() {
+        assert!(matches!(
+            LmpSectors::from_bytes(&[0, 5]),
+            Err(CoLaError::InvalidValue { offset: 0, .. })
+        ));
+        assert!(matches!(
+            LmpSectors::get_from_ascii(&mut AsciiReader::new(b" 5")),
+            Err(CoLaError::InvalidValue { offset: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn enums_round_trip_in_both_dialects() {
+        for status in [
+            LmpScanCfgError::None,
+            LmpScanCfgError::FrequencyError,
+            LmpScanCfgError::ResolutionError,
+            LmpScanCfgError::ResolutionAndScanOrFreq,
+            LmpScanCfgError::ScanAreaError,
+            LmpScanCfgError::OtherError,
+        ] {
+            round_trip(status);
+        }
+        for layer in [
+            AlignmentModeLayer::Red,
+            AlignmentModeLayer::Blue,
+            AlignmentModeLayer::Green,
+            AlignmentModeLayer::Yellow,
+        ] {
+            round_trip(layer);
+        }
+        round_trip(LMCError::Ok);
+        round_trip(LMCError::Error);
+        round_trip(ActiveApplication::FieldApplication);
+        round_trip(ActiveApplication::Ranging);
+    }
+
+    #[test]
+    fn enums_use_the_device_codes() {
+        assert_eq!(LMCError::from_bytes(&[0]).unwrap().0, LMCError::Ok);
+        assert_eq!(
+            LmpScanCfgError::get_from_ascii(&mut AsciiReader::new(b" 4")).unwrap(),
+            LmpScanCfgError::ScanAreaError
+        );
+        let mut data = Vec::new();
+        ActiveApplication::Ranging.write_ascii(&mut data);
+        assert_eq!(data, b" RANG");
+    }
+
+    #[test]
+    fn unknown_codes_are_errors() {
+        assert!(LmpScanCfgError::from_bytes(&[6]).is_err());
+        assert!(AlignmentModeLayer::get_from_ascii(&mut AsciiReader::new(b" 4")).is_err());
+        assert!(LMCError::from_bytes(&[2]).is_err());
+        assert!(ActiveApplication::from_bytes(b"ABCD").is_err());
+        assert!(ActiveApplication::from_bytes(b"RAN").is_err());
+        assert!(CoLaDataChannelType::from_bytes(b"DIST6").is_err());
+    }
+
+    #[test]
+    fn data_channels_round_trip_in_both_dialects() {
+        round_trip(CoLaDataChannel {
+            kind: CoLaDataChannelType::Dist1,
+            scale: 1.0,
+            scale_offset: 0.0,
+            start_angle: 0xFFF92230,
+            angular_step: 0x0D05,
+            data: vec![0x0123_u16, 0xFFFF, 0],
+        });
+        round_trip(CoLaDataChannel {
+            kind: CoLaDataChannelType::RSSI1,
+            scale: 0.5,
+            scale_offset: -1.0,
+            start_angle: 0,
+            angular_step: 1,
+            data: Vec::<u8>::new(),
+        });
     }
 }
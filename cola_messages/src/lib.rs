@@ -1,10 +1,10 @@
-use std::{
-    fmt::format,
-    io::{self, Error},
-    time::Duration,
-};
+use std::{io, time::Duration};
 
-use tokio::{io::AsyncWriteExt, net::TcpStream, time::sleep};
+use cola_lib::codec::CoLaCodec;
+pub use cola_lib::{codec::CoLaDialect, CoLaError};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
 
 use crate::messages::{CoLaMessages, CoLaMessagesIncoming};
 
@@ -19,121 +19,81 @@ pub mod messages;
 pub struct CoLaUtil;
 
 impl CoLaUtil {
-    pub fn setup_vec(input: &mut Vec<u8>) -> Vec<u8> {
-        let len: u32 = input.len().try_into().unwrap();
-        let mut output: Vec<u8> = vec![2, 2, 2, 2];
-        let len_str: [u8; 4] = len.to_be_bytes();
-        output.append(&mut len_str.to_vec());
-        let mut check: u8 = 0x00;
-        input.iter().for_each(|x| check ^= x);
-        output.append(input);
-        output.push(check);
-        output
+    /// Frames a byte stream, e.g. a `TcpStream`, in the given dialect for
+    /// [`CoLaUtil::send_message`] and [`CoLaUtil::read_message`].
+    pub fn framed<T: AsyncRead + AsyncWrite>(io: T, dialect: CoLaDialect) -> Framed<T, CoLaCodec> {
+        cola_lib::cola_a::CoLaUtil::framed(io, dialect)
     }
 
-    pub async fn send_message(stream: &mut TcpStream, msg: CoLaMessages) -> io::Result<()> {
-        let data = msg.to_raw_message();
-        if data.is_none() {
-            return Err(Error::new(
-                io::ErrorKind::InvalidData,
-                "Failed to write data to message!",
-            ));
-        }
-        let data = Self::setup_vec(&mut data.unwrap());
-        let out = data.as_slice();
-        stream.write_all(out).await
+    pub async fn send_message<T>(
+        framed: &mut Framed<T, CoLaCodec>,
+        msg: CoLaMessages,
+    ) -> Result<(), CoLaError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let dialect = framed.codec().dialect;
+        let data = msg
+            .to_message(dialect)
+            .ok_or_else(|| CoLaError::invalid(0, "message that cannot be encoded"))?;
+        let position = framed.codec().position();
+        framed.send(data).await.map_err(|e| e.at_stream(position))
     }
 
-    pub async fn read_message(
-        stream: &mut TcpStream,
-    ) -> Result<CoLaMessagesIncoming, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        let _ = stream.readable().await;
-        let mut data: [u8; 8] = [0; 8];
-
-        // dbg!("post");
-
-        loop {
-            match stream.try_read(&mut data) {
-                Ok(8) => break,
-                Ok(_) => {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Incorrect Message length",
-                    )));
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                Err(a) => return Err(Box::new(a)),
-            }
-            sleep(Duration::from_millis(2)).await;
-        }
-
-        // dbg!("post2");
-        let bytes: [u8; 4] = data[4..].try_into().unwrap();
-        let length: usize = u32::from_be_bytes(bytes).try_into().unwrap();
-        // dbg!(&length);
-        let mut data = vec![0_u8; length];
-        let _ = stream.readable().await;
-        let mut out;
-        let mut min = 0;
-        loop {
-            out = stream.try_read(&mut data[min..length]);
-            match out {
-                Ok(a) if a == data[min..length].len() => break,
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                Ok(l) => {
-                    min += l;
-                    continue;
-                }
-                Err(e) => return Err(Box::new(e)),
-            }
-
-            sleep(Duration::from_millis(2)).await;
-        }
-        let _ = stream.readable().await;
-        loop {
-            match stream.try_read(&mut [0_u8; 1]) {
-                Ok(1) => break,
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                _ => {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "No checksum present!",
-                    )))
-                }
+    /// Decodes the next frame in the dialect of the connection.
+    pub async fn read_message<T>(
+        framed: &mut Framed<T, CoLaCodec>,
+    ) -> Result<CoLaMessagesIncoming, CoLaError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let data = match framed.next().await {
+            Some(Ok(frame)) => frame?,
+            Some(Err(e)) => return Err(e.at_stream(framed.codec().position())),
+            None => {
+                return Err(
+                    CoLaError::from(io::Error::from(io::ErrorKind::UnexpectedEof))
+                        .at_stream(framed.codec().position()),
+                )
             }
+        };
+        CoLaMessagesIncoming::from_message(&data, framed.codec().dialect)
+    }
 
-            sleep(Duration::from_millis(2)).await;
+    /// [`CoLaUtil::read_message`] that gives up with [`CoLaError::Timeout`] after `after`.
+    pub async fn read_message_timeout<T>(
+        framed: &mut Framed<T, CoLaCodec>,
+        after: Duration,
+    ) -> Result<CoLaMessagesIncoming, CoLaError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        match tokio::time::timeout(after, Self::read_message(framed)).await {
+            Ok(result) => result,
+            Err(_) => Err(CoLaError::Timeout {
+                offset: framed.codec().position(),
+                after,
+            }),
         }
-
-        // println!("ret:");
-        // dbg!(&data);
-        data.reverse();
-        CoLaMessagesIncoming::test_data(&mut data.clone())
-
-        // match out {
-        //     Ok(l) => {
-        //         if l == length {
-        //             CoLaMessagesIncoming::test_data(&mut data.clone())
-        //         } else {
-        //             Err(Box::new(std::io::Error::new(
-        //                 std::io::ErrorKind::InvalidData,
-        //                 format!("Incorrect string length, {} /= {}", l, length),
-        //             )))
-        //         }
-        //     }
-        //     Err(a) => Err(Box::new(a)),
-        // }
     }
 
-    pub async fn await_message(
-        stream: &mut TcpStream,
+    /// Reads until a message passes `filter`. Other messages, answers to commands this crate
+    /// does not know and [retryable](CoLaError::is_retryable) errors are skipped, any other
+    /// error ends the wait.
+    pub async fn await_message<T>(
+        framed: &mut Framed<T, CoLaCodec>,
         filter: fn(&CoLaMessagesIncoming) -> bool,
-    ) -> CoLaMessagesIncoming {
+    ) -> Result<CoLaMessagesIncoming, CoLaError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
         loop {
-            if let Ok(s) = Self::read_message(stream).await {
-                if filter(&s) {
-                    return s;
-                }
+            match Self::read_message(framed).await {
+                Ok(message) if filter(&message) => return Ok(message),
+                Ok(_) => {}
+                Err(CoLaError::UnknownCommand { .. }) => {}
+                Err(e) if e.is_retryable() => {}
+                Err(e) => return Err(e),
             }
         }
     }
@@ -199,3 +159,119 @@ impl CoLaUtil {
 //         assert_eq!(result, 4);
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use cola_lib::codec::{write_cola_a_frame, write_cola_b_frame};
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    use super::*;
+
+    /// A connection in `dialect` whose peer already sent `payloads`.
+    async fn connection(
+        dialect: CoLaDialect,
+        payloads: &[&[u8]],
+    ) -> (DuplexStream, Framed<DuplexStream, CoLaCodec>) {
+        let (mut device, host) = tokio::io::duplex(4096);
+        let mut bytes = Vec::new();
+        for payload in payloads {
+            match dialect {
+                CoLaDialect::Binary => write_cola_b_frame(payload, &mut bytes),
+                CoLaDialect::Ascii => write_cola_a_frame(payload, &mut bytes),
+            }
+        }
+        device.write_all(&bytes).await.unwrap();
+        (device, CoLaUtil::framed(host, dialect))
+    }
+
+    #[tokio::test]
+    async fn binary_answers_are_decoded_by_their_command() {
+        let (_device, mut framed) = connection(
+            CoLaDialect::Binary,
+            &[
+                b"sAN SetAccessMode \x01",
+                b"sAN LMCstartmeas \x00",
+                b"sAN Run \x01",
+            ],
+        )
+        .await;
+        assert!(matches!(
+            CoLaUtil::read_message(&mut framed).await,
+            Ok(CoLaMessagesIncoming::SetAccessMode { accepted: true })
+        ));
+        assert!(matches!(
+            CoLaUtil::read_message(&mut framed).await,
+            Ok(CoLaMessagesIncoming::LMCstartmeas { status: 0 })
+        ));
+        assert!(matches!(
+            CoLaUtil::read_message(&mut framed).await,
+            Ok(CoLaMessagesIncoming::Run { status: 1 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_binary_answers_are_reported() {
+        let (_device, mut framed) = connection(
+            CoLaDialect::Binary,
+            &[b"sAN SetPassword \x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c"],
+        )
+        .await;
+        match CoLaUtil::read_message(&mut framed).await {
+            Err(CoLaError::UnknownCommand { offset, command }) => {
+                assert_eq!((offset, command.as_str()), (4, "SetPassword"));
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn ascii_answers_are_decoded_by_their_command() {
+        let (_device, mut framed) =
+            connection(CoLaDialect::Ascii, &[b"sAN SetAccessMode 1", b"sFA 5"]).await;
+        assert!(matches!(
+            CoLaUtil::read_message(&mut framed).await,
+            Ok(CoLaMessagesIncoming::SetAccessMode { accepted: true })
+        ));
+        assert!(matches!(
+            CoLaUtil::read_message(&mut framed).await,
+            Err(CoLaError::DeviceError { code: 5, .. })
+        ));
+    }
+
+    fn is_run(message: &CoLaMessagesIncoming) -> bool {
+        matches!(message, CoLaMessagesIncoming::Run { .. })
+    }
+
+    #[tokio::test]
+    async fn awaiting_skips_other_answers_and_corrupted_frames() {
+        let (mut device, mut framed) = connection(
+            CoLaDialect::Binary,
+            &[b"sAN SetAccessMode \x01", b"sAN SetPassword \x01"],
+        )
+        .await;
+        let mut corrupted = Vec::new();
+        write_cola_b_frame(b"sAN Run \x00", &mut corrupted);
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        write_cola_b_frame(b"sAN Run \x01", &mut corrupted);
+        device.write_all(&corrupted).await.unwrap();
+        assert!(matches!(
+            CoLaUtil::await_message(&mut framed, is_run).await,
+            Ok(CoLaMessagesIncoming::Run { status: 1 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn awaiting_stops_at_device_errors_and_closed_connections() {
+        let (device, mut framed) =
+            connection(CoLaDialect::Ascii, &[b"sAN SetAccessMode 1", b"sFA 5"]).await;
+        assert!(matches!(
+            CoLaUtil::await_message(&mut framed, is_run).await,
+            Err(CoLaError::DeviceError { code: 5, .. })
+        ));
+        drop(device);
+        assert!(matches!(
+            CoLaUtil::await_message(&mut framed, is_run).await,
+            Err(CoLaError::Transport { .. })
+        ));
+    }
+}
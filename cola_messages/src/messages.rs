@@ -1,4 +1,3 @@
-use cola_lib::cola_a::CoLaDataType;
 use cola_macros::{cola_incoming, cola_m};
 use subenum::subenum;
 
@@ -156,55 +155,3 @@ pub enum CoLaMessagesIncoming {
     #[cola_incoming(RUN)]
     Run { status: u8 },
 }
-
-impl CoLaMessagesIncoming {
-    pub fn test_data(
-        msg: &mut cola_lib::cola_a::ColaMessageRaw,
-    ) -> std::result::Result<CoLaMessagesIncoming, std::boxed::Box<(dyn std::error::Error + 'static)>>
-    {
-        let cmd: String = CoLaDataType::get_from_data(msg)?;
-        let cmd_type: String = CoLaDataType::get_from_data(msg)?;
-        let ver = CoLaDataType::get_from_data(msg)?;
-        let dev = CoLaDataType::get_from_data(msg)?;
-        let ser = CoLaDataType::get_from_data(msg)?;
-        let sta = CoLaDataType::get_from_data(msg)?;
-        let tele = CoLaDataType::get_from_data(msg)?;
-        let scan = CoLaDataType::get_from_data(msg)?;
-        let timess = CoLaDataType::get_from_data(msg)?;
-        let tot = CoLaDataType::get_from_data(msg)?;
-        let ins = CoLaDataType::get_from_data(msg)?;
-        let ous = CoLaDataType::get_from_data(msg)?;
-        let res = CoLaDataType::get_from_data(msg)?;
-        let lay = CoLaDataType::get_from_data(msg)?;
-        let scf = CoLaDataType::get_from_data(msg)?;
-        let msf = CoLaDataType::get_from_data(msg)?;
-        let enc = CoLaDataType::get_from_data(msg)?;
-        let d16 = Vec::<CoLaDataChannel<u16>>::get_from_data(msg)?;
-        let d8 = CoLaDataType::get_from_data(msg)?;
-        let pos = CoLaDataType::get_from_data(msg)?;
-        // dbg!(&d16);
-        // dbg!(&d8);
-        let out = CoLaMessagesIncoming::LMDData {
-            version: ver,
-            device_number: dev,
-            serial_number: ser,
-            status: sta,
-            telegram_counter: tele,
-            scan_counter: scan,
-            time_since_start: timess,
-            time_of_transmission: tot,
-            input_status: ins,
-            output_status: ous,
-            res: res,
-            layer_angle: lay,
-            scan_frequency: scf,
-            measurement_frequency: msf,
-            encoder_data: enc,
-            longdata: d16,
-            shortdata: d8,
-            position_data: pos,
-        };
-        // dbg!(&out);
-        Ok(out)
-    }
-}
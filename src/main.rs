@@ -1,18 +1,23 @@
 use std::{
+    env,
     f64::consts::PI,
     io,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use base_network::{Measurement, MeasurementLayerOutput, MeasurementModule, StandardResult};
+use base_network::{
+    replay, ArrivalTime, AssembledFrame, Binding, Capture, CaptureFilter, CapturedPacket,
+    ChecksumPolicy, ChecksumVerifier, ClockSync, Deskewer, Device, EchoPolicy, FrameAssembler,
+    Pacing, PointConverter, Record, Recorder, RecorderHandle, ReplayClock, Replayer, ScanPoint,
+    StandardResult, StreamStatistics, Transport, UdpReceiver,
+};
+use cola_messages::CoLaDialect;
 use glam::{Mat4, Vec3};
-use smallvec::SmallVec;
 use tokio::{
-    io::BufReader,
-    net::{TcpStream, UdpSocket},
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
     runtime,
-    time::sleep,
 };
 use vulkan_backend::TestVertexHolder;
 use vulkan_backend::{vulkano_window_setup::VulkanWindowSettings, TestRenderer, TestVertex};
@@ -30,15 +35,26 @@ async fn main() -> TokioResult {
         .build()
         .unwrap();
     let data: TestVertexHolder = Arc::new(Mutex::new(vec![TestVertex::new(0.0, 0.0, 0.0, 0.0)]));
-    // network.spawn(multiscan_data(data.clone()));
+    // network.spawn(multiscan_data(data.clone(), None));
 
     // let mut stream = TcpStream::connect("192.168.0.150:2112").await.unwrap();
-    network.spawn(mrs1000_data(data.clone()));
+    let live = data.clone();
+    match env::var("SICK_REPLAY") {
+        Ok(path) if path.ends_with(RECORDING_EXTENSION) => {
+            network.spawn(replay_recording(live, path, Pacing::RealTime))
+        }
+        Ok(path) => network.spawn(replay_capture(live, path, Pacing::RealTime)),
+        Err(_) => network.spawn(async move {
+            let recorder = recorder().await?;
+            mrs1000_data(live, MRS1000_ADDRESS, recorder).await;
+            Ok(())
+        }),
+    };
 
     let proj = Mat4::perspective_rh_gl(90.0_f32.to_radians(), 1.0, 0.01, 40.0); //Mat4::orthographic_rh_gl(left, right, bottom, top, near, far)
     let scale = Mat4::from_scale(Vec3::splat(0.001));
 
-    // network.spawn(mrs1000_data(data.clone()));
+    // network.spawn(mrs1000_data(data.clone(), MRS1000_ADDRESS));
     let renderer: TestRenderer<{ vulkan_backend::CURRENT_PASSES }> = TestRenderer {
         pointcloud: data,
         proj,
@@ -67,147 +83,232 @@ async fn main() -> TokioResult {
     // }
 }
 
-const NUM_SECTORS: usize = 195;
+const MRS1000_ADDRESS: &str = "192.168.0.150:2112";
+/// Source ids used in raw telegram recordings.
+const MULTISCAN_SOURCE: u32 = 0;
+const MRS1000_SOURCE: u32 = 1;
+const RECORDING_EXTENSION: &str = ".sickraw";
+
+/// The Compact sensor named by `SICK_DEVICE`, a multiScan136 by default.
+fn compact_device() -> Device {
+    env::var("SICK_DEVICE")
+        .ok()
+        .and_then(|name| Device::from_name(&name))
+        .unwrap_or_default()
+}
 
-async fn multiscan_data(handle: TestVertexHolder) -> StandardResult<()> {
-    let udp = UdpSocket::bind("192.168.0.100:2115").await.unwrap();
-    let mut cache: Arc<Mutex<Vec<Vec<TestVertex>>>> =
-        Arc::new(Mutex::new(Vec::with_capacity(NUM_SECTORS)));
-    for _ in 0..NUM_SECTORS {
-        cache
-            .lock()
-            .unwrap()
-            .push(vec![TestVertex::new(0.0, 0.0, 0.0, 0.0)]);
+/// A recorder writing to the path in `SICK_RECORD`, if that is set.
+async fn recorder() -> io::Result<Option<RecorderHandle>> {
+    match env::var("SICK_RECORD") {
+        Ok(path) => Ok(Some(Recorder::spawn(path).await?.0)),
+        Err(_) => Ok(None),
     }
-    loop {
-        let mut inner: Vec<u8> = Vec::with_capacity(16384);
+}
 
-        loop {
-            udp.readable().await?;
-            // dbg!("Rdy");
-            match udp.try_recv_buf(&mut inner) {
-                Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Err(e),
-                Ok(n) if n != 0 => break,
-                _ => {}
-            }
-            sleep(Duration::from_millis(1)).await;
+/// CoLa-A when `SICK_COLA` is `ascii`, CoLa-B otherwise.
+fn cola_dialect() -> CoLaDialect {
+    match env::var("SICK_COLA") {
+        Ok(dialect) if dialect.eq_ignore_ascii_case("ascii") => CoLaDialect::Ascii,
+        _ => CoLaDialect::Binary,
+    }
+}
+
+/// Everything the Compact datagrams of a multiScan or picoScan go through on their way to
+/// the renderer.
+struct MultiscanPipeline {
+    handle: TestVertexHolder,
+    device: Device,
+    checksums: ChecksumVerifier,
+    converter: PointConverter,
+    deskewer: Deskewer,
+    clock: ClockSync,
+    assembler: FrameAssembler,
+    stats: StreamStatistics,
+    last_report: Instant,
+    recorder: Option<RecorderHandle>,
+}
+
+impl MultiscanPipeline {
+    fn new(handle: TestVertexHolder, device: Device) -> Self {
+        let mut converter = PointConverter::new(EchoPolicy::Last);
+        converter.device = device;
+        Self {
+            handle,
+            device,
+            checksums: ChecksumVerifier::new(ChecksumPolicy::Warn),
+            converter,
+            deskewer: Deskewer::new(),
+            clock: ClockSync::default(),
+            assembler: FrameAssembler::new(device.profile().assembler_config()),
+            stats: StreamStatistics::default(),
+            last_report: Instant::now(),
+            recorder: None,
+        }
+    }
+
+    fn datagram(&mut self, inner: &[u8], arrival: ArrivalTime) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(MULTISCAN_SOURCE, Transport::Udp, inner);
         }
-        // dbg!(&inner);
-        let mut buffer = BufReader::new(inner.as_slice());
-        match base_network::CompactMessage::read_message(&mut buffer).await? {
-            base_network::CompactMessage::IMUMessage { imudata: _ } => {}
-            base_network::CompactMessage::DistanceMessage { header: _, data } => {
-                handle_distance(data, &mut cache.clone())?
+        let frames = match self.device.profile().decode(inner, &mut self.checksums) {
+            Ok(msg) => {
+                self.stats.record(&msg, arrival);
+                self.clock.observe_message(&msg, arrival);
+                self.deskewer.push_message(&msg);
+                self.assembler.push_message(msg, arrival.instant)
+            }
+            Err(e) => {
+                eprintln!("Skipping datagram: {e}");
+                return;
+            }
+        };
+        frames
+            .into_iter()
+            .for_each(|frame| self.handle_frame(frame));
+        if self.last_report.elapsed() >= self.stats.window {
+            self.last_report = Instant::now();
+            self.stats
+                .senders()
+                .filter_map(|(id, _)| self.stats.report(*id))
+                .for_each(|report| println!("{report}"));
+            if let Some(drift) = self.clock.drift_ppm() {
+                println!("sensor clock drift {drift:.1} ppm");
             }
         }
-        {
-            let total: Vec<TestVertex> = cache
-                .lock()
-                .unwrap()
-                .iter()
-                .flat_map(|v| v.clone())
-                .collect();
-            let mut lock = handle.lock().expect("Failed to get lock");
-            lock.clone_from(&total);
+    }
+
+    fn handle_frame(&mut self, frame: AssembledFrame) {
+        if !frame.is_complete() {
+            eprintln!(
+                "Frame {} is missing segments {:?}",
+                frame.frame_number, frame.missing_segments
+            );
+        }
+        let mut points = Vec::new();
+        frame
+            .segments
+            .iter()
+            .for_each(|module| self.converter.convert_module(module, &mut points));
+        self.deskewer.deskew(&mut points);
+        if let Some(end) = points.iter().map(|p| p.time_stamp).max() {
+            self.deskewer.prune_before(end);
         }
+        let total: Vec<TestVertex> = points.into_iter().map(to_vertex).collect();
+        let mut lock = self.handle.lock().expect("Failed to get lock");
+        lock.clone_from(&total);
     }
 }
 
-fn handle_distance(
-    // header: CompactHeader,
-    data: Box<SmallVec<[MeasurementModule; 4]>>,
-    handle: &mut Arc<Mutex<Vec<Vec<TestVertex>>>>,
+/// Receives the Compact data port of every sensor sending to this host, each sensor
+/// with its own pipeline.
+async fn multiscan_data(
+    handle: TestVertexHolder,
+    recorder: Option<RecorderHandle>,
 ) -> StandardResult<()> {
-    data.iter().for_each(|module| {
-        module.data.iter().for_each(|d| {
-            deal_with_data(
-                &mut handle.clone(),
-                d.clone(),
-                // header.clone(),
-                module.clone(),
-            )
-            .expect("Failed to resolve data");
-        })
-    });
+    let device = compact_device();
+    let binding = Binding::Unicast(([192, 168, 0, 100], device.profile().data_port).into());
+    let (receiver, mut sensors) = UdpReceiver::bind(&binding, 64)?;
+    tokio::spawn(receiver.run());
+    while let Some(mut sensor) = sensors.recv().await {
+        println!(
+            "{} {} at {}",
+            device.profile().name,
+            sensor.key.sender_id,
+            sensor.key.address
+        );
+        let mut pipeline = MultiscanPipeline::new(handle.clone(), device);
+        pipeline.recorder = recorder.clone();
+        tokio::spawn(async move {
+            while let Some(datagram) = sensor.datagrams.recv().await {
+                pipeline.datagram(&datagram.bytes, datagram.arrival);
+            }
+        });
+    }
     Ok(())
 }
 
-fn deal_with_data(
-    handle: &mut Arc<Mutex<Vec<Vec<TestVertex>>>>,
-    data: MeasurementLayerOutput,
-    // header: CompactHeader,
-    module: MeasurementModule,
-) -> StandardResult<()> {
-    let d_theta: f64 = (data.theta_end as f64 - data.theta_start as f64) / data.data.len() as f64;
-    let d_time: f64 = (data.time_stamp_end - data.time_stamp_start) as f64 / data.data.len() as f64;
-    let time_start: f64 = data.time_stamp_start as f64;
-
-    let phi = data.phi as f64 + PI / 2.0;
-    // dbg!(phi);
-    let s_phi = phi.sin();
-    let c_phi = phi.cos();
+/// Plays a Wireshark capture of a session back through the same paths as live data.
+async fn replay_capture(handle: TestVertexHolder, path: String, pacing: Pacing) -> io::Result<()> {
+    let device = compact_device();
+    let port = device.profile().data_port;
+    let capture = Capture::open(&path, &CaptureFilter::ports([port, 2112]))?;
+    println!(
+        "Replaying {path}: {} packets, {} skipped",
+        capture.packets.len(),
+        capture.skipped
+    );
+    let mrs1000: Vec<CapturedPacket> = capture.tcp_from(2112).cloned().collect();
+    if !mrs1000.is_empty() {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        tokio::spawn(replay::serve_tcp(listener, mrs1000, pacing));
+        tokio::spawn(mrs1000_data(handle.clone(), address, None));
+    }
+    let mut pipeline = MultiscanPipeline::new(handle, device);
+    let mut clock = ReplayClock::default();
+    replay::replay_datagrams(capture.udp(port), pacing, |packet| {
+        pipeline.datagram(&packet.payload, clock.arrival(packet.timestamp))
+    })
+    .await;
+    Ok(())
+}
 
-    let theta_start = data.theta_start as f64;
-    let mut cache: Vec<TestVertex> = data
-        .data
+/// Plays a raw telegram recording back in a loop.
+async fn replay_recording(
+    handle: TestVertexHolder,
+    path: String,
+    pacing: Pacing,
+) -> io::Result<()> {
+    let records = Replayer::open(&path, pacing)?.records().to_vec();
+    let mrs1000: Vec<Record> = records
         .iter()
-        .enumerate()
-        .map(|(n, measurement)| {
-            (
-                n as f64 * d_theta + theta_start,
-                n as f64 * d_time + time_start,
-                measurement,
-            )
-        })
-        .filter_map(|(theta, time, measurement)| match measurement {
-            Measurement::Empty => None,
-            Measurement::Filled {
-                echoes,
-                beam_properties: _,
-                azimuth_angle: _,
-            } => Some((
-                theta,
-                time,
-                echoes[0].distance.expect("Echo without distance") as f64,
-            )),
-        })
-        .filter(|(_theta, _time, radius)| *radius > 0.1)
-        //TODO: Next is temp
-        .map(|(theta, _time, radius)| (theta, (radius / 1250.0).sin(), radius))
-        .map(|(theta, time, radius)| (theta, time, radius * (module.distance_scale_factor as f64)))
-        .map(|(theta, time, radius)| {
-            (
-                radius * s_phi * theta.cos(),
-                radius * c_phi,
-                radius * s_phi * theta.sin(),
-                time,
-            )
-            // r * theta.sin() * c_phi,
-            // r * theta.sin() * s_phi,
-            // r * theta.cos(),
-        })
-        .map(TestVertex::from_tuple)
+        .filter(|r| r.source == MRS1000_SOURCE)
+        .cloned()
         .collect();
-    // cache.sort();
-
-    if !cache.is_empty() {
-        handle.lock().unwrap()[0] = cache;
+    if !mrs1000.is_empty() {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        tokio::spawn(replay::serve_tcp(listener, mrs1000, pacing));
+        tokio::spawn(mrs1000_data(handle.clone(), address, None));
+    }
+    let mut replayer = Replayer::new(records, pacing).only_source(MULTISCAN_SOURCE);
+    replayer.looping = true;
+    let mut pipeline = MultiscanPipeline::new(handle, compact_device());
+    let mut clock = ReplayClock::default();
+    while let Some(record) = replayer.next().await {
+        pipeline.datagram(&record.bytes, clock.arrival(record.timestamp));
     }
-    // handle[0] =
-    handle.lock().unwrap().rotate_right(1);
-    // dbg!(data.phi);
     Ok(())
-    // match data.phi {
-    //     _ => todo!(),
-    // }
-    // todo!()
+}
+
+/// The renderer is y-up while the sensor is z-up.
+fn to_vertex(point: ScanPoint) -> TestVertex {
+    let intensity = point.intensity.map_or(0.0, |i| i as f64 / u16::MAX as f64);
+    TestVertex::from_tuple((point.x as f64, -point.z as f64, point.y as f64, intensity))
 }
 
 const MRSSCALE: f64 = 10.0;
 
-async fn mrs1000_data(handle: TestVertexHolder) {
+/// Polls the MRS1000 at `address`, recording its answers as [`MRS1000_SOURCE`] when a
+/// recorder is given.
+async fn mrs1000_data(
+    handle: TestVertexHolder,
+    address: impl ToSocketAddrs,
+    recorder: Option<RecorderHandle>,
+) {
+    let connection = TcpStream::connect(address).await.unwrap();
+    match recorder {
+        Some(recorder) => {
+            let recorded = recorder.attach(MRS1000_SOURCE, Transport::Tcp, connection);
+            mrs1000_poll(handle, recorded).await
+        }
+        None => mrs1000_poll(handle, connection).await,
+    }
+}
+
+async fn mrs1000_poll(handle: TestVertexHolder, connection: impl AsyncRead + AsyncWrite + Unpin) {
     println!("Started network!");
-    let mut stream = TcpStream::connect("192.168.0.150:2112").await.unwrap();
+    let mut stream = cola_messages::CoLaUtil::framed(connection, cola_dialect());
     // stream.set_nonblocking();
     loop {
         let mut data: [Vec<(f64, f64, f64, f64)>; 5] = [vec![], vec![], vec![], vec![], vec![]];